@@ -0,0 +1,397 @@
+//! Parsing for the `[[ ... ]]` conditional command.
+//!
+//! The words inside the brackets are read here rather than by the
+//! [`Walker`](crate::walker::Walker) because they follow different rules.
+//! `&&`, `||`, `(`, `)`, `<` and `>` are operators of the expression and the
+//! words are never split or globbed.
+
+use std::iter::Peekable;
+
+use crate::lexer::Token;
+use crate::walker::{Expand, TreeItem};
+
+/// An expression inside of `[[ ... ]]`. This is generic over the words so
+/// it can be expanded before it is evaluated.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CondExpr<W = Vec<Expand>> {
+    /// A single word which is true when it is not empty
+    Word(W),
+    /// `-op word`
+    Unary(String, W),
+    /// `word op word`
+    Binary(W, String, W),
+    /// `! expr`
+    Not(Box<CondExpr<W>>),
+    /// `expr && expr`
+    And(Box<CondExpr<W>>, Box<CondExpr<W>>),
+    /// `expr || expr`
+    Or(Box<CondExpr<W>>, Box<CondExpr<W>>),
+}
+
+impl<W> CondExpr<W> {
+    /// Changes every word in the expression. `f` is also told if the word
+    /// is a pattern, which is the right side of `==`, `!=` and `=~`.
+    pub fn map<T, F: FnMut(W, bool) -> T>(self, f: &mut F) -> CondExpr<T> {
+        match self {
+            CondExpr::Word(w) => CondExpr::Word(f(w, false)),
+            CondExpr::Unary(op, w) => CondExpr::Unary(op, f(w, false)),
+            CondExpr::Binary(a, op, b) => {
+                let a = f(a, false);
+                let pattern = matches!(op.as_str(), "=" | "==" | "!=" | "=~");
+                CondExpr::Binary(a, op, f(b, pattern))
+            }
+            CondExpr::Not(e) => CondExpr::Not(Box::new(e.map(f))),
+            CondExpr::And(a, b) => {
+                let a = a.map(f);
+                CondExpr::And(Box::new(a), Box::new(b.map(f)))
+            }
+            CondExpr::Or(a, b) => {
+                let a = a.map(f);
+                CondExpr::Or(Box::new(a), Box::new(b.map(f)))
+            }
+        }
+    }
+}
+
+/// The operators that take one argument.
+pub const UNARY_OPS: &[&str] = &[
+    "-a", "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-n", "-p", "-r", "-s", "-t", "-u", "-w",
+    "-x", "-z", "-G", "-L", "-O", "-S",
+];
+
+/// The operators that go between two arguments.
+pub const BINARY_OPS: &[&str] = &[
+    "=", "==", "!=", "=~", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+#[derive(Debug)]
+enum Piece {
+    Word(Vec<Expand>),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+/// Reads everything after a `[[` up to and including the closing `]]`.
+pub(crate) fn read_cond<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> TreeItem {
+    let mut pieces = vec![];
+    loop {
+        // the right side of `=~` is a regex so it can have parens and pipes
+        let regex = matches!(
+            pieces.last(),
+            Some(Piece::Word(w)) if as_op(w).as_deref() == Some("=~")
+        );
+        match next_piece(tokens, regex) {
+            Ok(Some(p)) => pieces.push(p),
+            Ok(None) => break,
+            Err(e) => return TreeItem::SyntaxError(e),
+        }
+    }
+
+    let mut pieces = pieces.into_iter().peekable();
+    let expr = match parse_or(&mut pieces) {
+        Ok(e) => e,
+        Err(e) => return TreeItem::SyntaxError(e),
+    };
+    match pieces.next() {
+        None => TreeItem::Cond(expr),
+        Some(p) => TreeItem::SyntaxError(format!("unexpected {:?} in conditional", p)),
+    }
+}
+
+/// Gets the next part of the expression. Returns `None` at the closing `]]`.
+fn next_piece<I: Iterator<Item = Token>>(
+    tokens: &mut Peekable<I>,
+    regex: bool,
+) -> Result<Option<Piece>, String> {
+    while let Some(Token::Space | Token::Tab | Token::Newline) = tokens.peek() {
+        let _ = tokens.next();
+    }
+
+    let piece = match tokens.peek() {
+        None => return Err(String::from("expected `]]' but found end of input")),
+        Some(Token::Ident(s)) if s == "]]" => {
+            let _ = tokens.next();
+            return Ok(None);
+        }
+        Some(Token::Amp) => {
+            let _ = tokens.next();
+            match tokens.next() {
+                Some(Token::Amp) => Piece::And,
+                _ => return Err(String::from("expected `&&' in conditional")),
+            }
+        }
+        Some(Token::Pipe) if !regex => {
+            let _ = tokens.next();
+            match tokens.next() {
+                Some(Token::Pipe) => Piece::Or,
+                _ => return Err(String::from("expected `||' in conditional")),
+            }
+        }
+        Some(Token::OpenParen) if !regex => {
+            let _ = tokens.next();
+            Piece::Open
+        }
+        Some(Token::CloseParen) if !regex => {
+            let _ = tokens.next();
+            Piece::Close
+        }
+        Some(Token::Bang) => {
+            let _ = tokens.next();
+            match tokens.peek() {
+                Some(Token::Space | Token::Tab | Token::Newline) | None => Piece::Not,
+                // this is something like `!=`
+                _ => Piece::Word(read_word(tokens, regex, vec![lit("!")])),
+            }
+        }
+        Some(Token::SemiColor | Token::Comment) => {
+            return Err(String::from("expected `]]' before end of command"))
+        }
+        Some(_) => Piece::Word(read_word(tokens, regex, vec![])),
+    };
+    Ok(Some(piece))
+}
+
+fn lit(s: &str) -> Expand {
+    Expand::Literal(String::from(s))
+}
+
+/// Reads tokens into a word until something that separates words.
+fn read_word<I: Iterator<Item = Token>>(
+    tokens: &mut Peekable<I>,
+    regex: bool,
+    mut word: Vec<Expand>,
+) -> Vec<Expand> {
+    loop {
+        let part = match tokens.peek() {
+            None
+            | Some(
+                Token::Space
+                | Token::Tab
+                | Token::Newline
+                | Token::Amp
+                | Token::SemiColor
+                | Token::Comment,
+            ) => break,
+            Some(Token::Pipe | Token::OpenParen | Token::CloseParen) if !regex => break,
+            Some(Token::Doller) => {
+                let _ = tokens.next();
+                let var = match tokens.peek() {
+                    Some(Token::Ident(s)) => s.clone(),
+                    Some(Token::Huh) => String::from("?"),
+                    Some(Token::Pound) => String::from("#"),
+                    _ => {
+                        word.push(lit("$"));
+                        continue;
+                    }
+                };
+                let _ = tokens.next();
+                word.push(Expand::Var(var));
+                continue;
+            }
+            Some(_) => tokens.next().unwrap(),
+        };
+
+        match part {
+            Token::Ident(s) => word.push(Expand::Literal(s)),
+            Token::SingleQuote(s) => word.push(Expand::Quoted(vec![Expand::Literal(s)])),
+            Token::Sub(s) => word.push(Expand::Sub(s)),
+            Token::Param(s) => word.push(Expand::Param(s)),
+            // only a tilde at the start is the home directory
+            Token::Tilde if word.is_empty() => word.push(Expand::Home),
+            Token::OpenBraket => word.push(lit("{")),
+            Token::CloseBraket => word.push(lit("}")),
+            Token::DoubleQuote(v) => {
+                let mut quoted = vec![];
+                let mut v = v.into_iter();
+                while let Some(t) = v.next() {
+                    match t {
+                        Token::Doller => match v.next() {
+                            Some(Token::Ident(s)) => quoted.push(Expand::Var(s)),
+                            _ => quoted.push(lit("$")),
+                        },
+                        Token::Ident(s) => quoted.push(Expand::Literal(s)),
+                        Token::Sub(s) => quoted.push(Expand::Sub(s)),
+                        Token::Param(s) => quoted.push(Expand::Param(s)),
+                        t => quoted.push(Expand::Literal(t.to_string())),
+                    }
+                }
+                word.push(Expand::Quoted(quoted));
+            }
+            t => word.push(Expand::Literal(t.to_string())),
+        }
+    }
+    word
+}
+
+/// If a word is just text that can be an operator then gets it. Some
+/// operators like `==` are more than one token so they are joined.
+fn as_op(word: &[Expand]) -> Option<String> {
+    word.iter()
+        .map(|e| match e {
+            Expand::Literal(s) => Some(s.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+type Pieces = Peekable<std::vec::IntoIter<Piece>>;
+
+fn parse_or(pieces: &mut Pieces) -> Result<CondExpr, String> {
+    let mut node = parse_and(pieces)?;
+    while let Some(Piece::Or) = pieces.peek() {
+        let _ = pieces.next();
+        node = CondExpr::Or(Box::new(node), Box::new(parse_and(pieces)?));
+    }
+    Ok(node)
+}
+
+fn parse_and(pieces: &mut Pieces) -> Result<CondExpr, String> {
+    let mut node = parse_not(pieces)?;
+    while let Some(Piece::And) = pieces.peek() {
+        let _ = pieces.next();
+        node = CondExpr::And(Box::new(node), Box::new(parse_not(pieces)?));
+    }
+    Ok(node)
+}
+
+fn parse_not(pieces: &mut Pieces) -> Result<CondExpr, String> {
+    if let Some(Piece::Not) = pieces.peek() {
+        let _ = pieces.next();
+        return Ok(CondExpr::Not(Box::new(parse_not(pieces)?)));
+    }
+    parse_primary(pieces)
+}
+
+fn parse_primary(pieces: &mut Pieces) -> Result<CondExpr, String> {
+    let word = match pieces.next() {
+        Some(Piece::Open) => {
+            let expr = parse_or(pieces)?;
+            return match pieces.next() {
+                Some(Piece::Close) => Ok(expr),
+                _ => Err(String::from("expected `)' in conditional")),
+            };
+        }
+        Some(Piece::Word(w)) => w,
+        Some(p) => return Err(format!("unexpected {:?} in conditional", p)),
+        None => return Err(String::from("expected an expression in conditional")),
+    };
+
+    // a binary operator takes precedence so `[[ -n == -n ]]` works
+    let binary = match pieces.peek() {
+        Some(Piece::Word(w)) => as_op(w).filter(|op| BINARY_OPS.contains(&op.as_str())),
+        _ => None,
+    };
+    if let Some(op) = binary {
+        let _ = pieces.next();
+        return match pieces.next() {
+            Some(Piece::Word(rhs)) => Ok(CondExpr::Binary(word, op, rhs)),
+            _ => Err(format!("expected an argument after `{}'", op)),
+        };
+    }
+
+    if let Some(op) = as_op(&word).filter(|op| UNARY_OPS.contains(&op.as_str())) {
+        if let Some(Piece::Word(_)) = pieces.peek() {
+            let Some(Piece::Word(arg)) = pieces.next() else {
+                unreachable!()
+            };
+            return Ok(CondExpr::Unary(op, arg));
+        }
+    }
+
+    Ok(CondExpr::Word(word))
+}
+
+#[cfg(test)]
+mod test {
+    use super::CondExpr;
+    use crate::walker::{Expand, TreeItem};
+
+    fn parse(input: &str) -> TreeItem {
+        crate::parse(input).next().unwrap()
+    }
+
+    fn lit(s: &str) -> Vec<Expand> {
+        vec![Expand::Literal(String::from(s))]
+    }
+
+    #[test]
+    fn cond_binary() {
+        let TreeItem::Cond(e) = parse("[[ $a == b* ]]") else {
+            panic!("not a conditional")
+        };
+        assert_eq!(
+            e,
+            CondExpr::Binary(
+                vec![Expand::Var(String::from("a"))],
+                String::from("=="),
+                vec![
+                    Expand::Literal(String::from("b")),
+                    Expand::Literal(String::from("*"))
+                ],
+            )
+        );
+    }
+
+    #[test]
+    fn cond_precedence() {
+        let TreeItem::Cond(e) = parse("[[ ! -f x || ( a && -n b ) ]]") else {
+            panic!("not a conditional")
+        };
+        assert_eq!(
+            e,
+            CondExpr::Or(
+                Box::new(CondExpr::Not(Box::new(CondExpr::Unary(
+                    String::from("-f"),
+                    lit("x")
+                )))),
+                Box::new(CondExpr::And(
+                    Box::new(CondExpr::Word(lit("a"))),
+                    Box::new(CondExpr::Unary(String::from("-n"), lit("b")))
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn cond_regex() {
+        let TreeItem::Cond(e) = parse("[[ x =~ ^(a|b)$ ]]") else {
+            panic!("not a conditional")
+        };
+        let CondExpr::Binary(_, op, rhs) = e else {
+            panic!("not binary")
+        };
+        assert_eq!(op, "=~");
+        let rhs: String = rhs
+            .into_iter()
+            .map(|e| match e {
+                Expand::Literal(s) => s,
+                e => panic!("unexpected {:?}", e),
+            })
+            .collect();
+        assert_eq!(rhs, "^(a|b)$");
+    }
+
+    #[test]
+    fn cond_quoted() {
+        let TreeItem::Cond(e) = parse("[[ x == \"*\"a'?' ]]") else {
+            panic!("not a conditional")
+        };
+        let CondExpr::Binary(_, _, rhs) = e else {
+            panic!("not binary")
+        };
+        assert_eq!(
+            rhs,
+            vec![
+                Expand::Quoted(lit("*")),
+                Expand::Literal(String::from("a")),
+                Expand::Quoted(lit("?")),
+            ]
+        );
+
+        // a quoted operator is just a word
+        assert!(matches!(parse("[[ a '==' b ]]"), TreeItem::SyntaxError(_)));
+    }
+}
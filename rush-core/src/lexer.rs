@@ -1,15 +1,30 @@
 use crate::prelude::*;
 
+use std::cell::Cell;
 use std::iter::Peekable;
+use std::ops::Range;
+use std::result::Result;
 
 /// entry point to turing some chars into a token
 pub(crate) fn next_token<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Option<Token> {
-    match chars.next()? {
-        '\"' => read_double_quotes(chars),
-        '\'' => Some(read_single_quotes(chars)),
+    read_token(chars).map(|t| t.unwrap_or_else(|e| panic!("{}", e)))
+}
+
+/// Like [`next_token`] but input that ends inside a quote is an error.
+fn read_token<I: Iterator<Item = char>>(
+    chars: &mut Peekable<I>,
+) -> Option<Result<Token, Unclosed>> {
+    let token = match chars.next()? {
+        // a line that ends in `\` goes on to the next one
+        '\\' if chars.peek() == Some(&'\n') => {
+            let _ = chars.next();
+            return read_token(chars);
+        }
+        '\"' => return Some(read_double_quotes(chars)),
+        '\'' => return Some(read_single_quotes(chars)),
 
-        '$' => Some(read_doller(chars)),
-        '`' => Some(read_backtick(chars)),
+        '$' => return Some(read_doller(chars)),
+        '`' => return Some(read_backtick(chars)),
 
         '#' => Some(Token::Pound),
         '|' => Some(Token::Pipe),
@@ -31,21 +46,146 @@ pub(crate) fn next_token<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> O
         '\t' => Some(Token::Tab),
         '\n' => Some(Token::Newline),
         c => Some(read_ident(chars, c)),
+    };
+    token.map(Ok)
+}
+
+/// A quote or substitution the input ended inside of. It holds what was
+/// read of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unclosed(pub Token);
+
+impl fmt::Display for Unclosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Token::DoubleQuote(_) => f.write_str("unclosed double quotes"),
+            Token::SingleQuote(_) => f.write_str("unclosed single quote"),
+            Token::Param(_) => f.write_str("unclosed parameter expansion"),
+            _ => f.write_str("unclosed command substitution"),
+        }
+    }
+}
+
+/// A token and the characters of the input it was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub token: Token,
+    /// Counted in characters, not bytes.
+    pub range: Range<usize>,
+}
+
+/// Lexes input that may not be finished, like a line that is still being
+/// typed. If it ends inside a quote the last token holds what was read of
+/// it and `true` is given back with the tokens.
+pub fn spans(input: &str) -> (Vec<Span>, bool) {
+    let taken = Cell::new(0);
+    let mut chars = input
+        .chars()
+        .inspect(|_| taken.set(taken.get() + 1))
+        .peekable();
+
+    let mut spans = Vec::new();
+    let mut start = 0;
+    while let Some(token) = read_token(&mut chars) {
+        // a character that was only peeked at isn't part of the token
+        let peeked = chars.peek().is_some() as usize;
+        let end = taken.get() - peeked;
+        let (token, unclosed) = match token {
+            Ok(token) => (token, false),
+            Err(Unclosed(token)) => (token, true),
+        };
+        spans.push(Span {
+            token,
+            range: start..end,
+        });
+        if unclosed {
+            return (spans, true);
+        }
+        start = end;
     }
+    (spans, false)
+}
+
+/// If input can be run as it is. Input that ends inside quotes, after `|`,
+/// `&&` or a `\`, or with a `(`, `[[` or a compound command like `if` left
+/// open needs more lines.
+pub fn is_complete(input: &str) -> bool {
+    let (spans, unclosed) = spans(input);
+    if unclosed {
+        return false;
+    }
+    if matches!(spans.last(), Some(Span { token: Token::Ident(s), .. }) if s.ends_with('\\')) {
+        return false;
+    }
+
+    let mut parens = 0;
+    let mut cond = false;
+    // the words that close the compound commands that are open
+    let mut closers = Vec::new();
+    // if the next word can be a reserved word
+    let mut start = true;
+    let mut last = (None, None);
+    for span in &spans {
+        let token = &span.token;
+        let case = closers.last() == Some(&"esac");
+        match token {
+            Token::Space | Token::Tab | Token::Comment => continue,
+            // the patterns in a case end with `)`
+            Token::OpenParen if !case => parens += 1,
+            Token::CloseParen if !case => parens -= 1,
+            Token::Ident(s) if cond => cond = s != "]]",
+            Token::Ident(s) if s == "[[" && start => cond = true,
+            Token::Ident(s) if start && closers.last() == Some(&s.as_str()) => {
+                let _ = closers.pop();
+            }
+            Token::Ident(s) if start => match s.as_str() {
+                "if" => closers.push("fi"),
+                "case" => closers.push("esac"),
+                "while" | "until" | "for" | "select" => closers.push("done"),
+                _ => {}
+            },
+            Token::OpenBraket if start => closers.push("}"),
+            Token::CloseBraket if start && closers.last() == Some(&"}") => {
+                let _ = closers.pop();
+            }
+            _ => {}
+        }
+        start = match token {
+            Token::Newline
+            | Token::SemiColor
+            | Token::Pipe
+            | Token::Amp
+            | Token::OpenParen
+            | Token::OpenBraket => true,
+            Token::Bang => start,
+            Token::Ident(s) => matches!(
+                s.as_str(),
+                "if" | "then" | "elif" | "else" | "while" | "until" | "do"
+            ),
+            _ => false,
+        };
+        last = (last.1, Some(token));
+    }
+
+    let joined = matches!(
+        last,
+        (_, Some(Token::Pipe)) | (Some(Token::Amp), Some(Token::Amp))
+    );
+    !joined && !cond && parens <= 0 && closers.is_empty()
 }
 
 fn read_space<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Token {
     match chars.peek() {
         Some('#') => {
-            let _ = read_raw_until(chars, |c| c == '\n', |_, _| {}, false);
+            let _ = read_raw_until(chars, |c| c == '\n', |_, _| {});
             Token::Comment
         }
         _ => Token::Space,
     }
 }
 
-fn read_backtick<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Token {
-    let s = read_raw_until(
+fn read_backtick<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Result<Token, Unclosed> {
+    read_raw_until(
         chars,
         |c| c == '`',
         |c, b| {
@@ -54,17 +194,16 @@ fn read_backtick<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Token {
             }
             b.push('`')
         },
-        true,
     )
-    .unwrap();
-    Token::Sub(s)
+    .map(Token::Sub)
+    .map_err(|s| Unclosed(Token::Sub(s)))
 }
 
-fn read_doller<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Token {
-    match chars.peek().unwrap() {
-        '(' => {
+fn read_doller<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Result<Token, Unclosed> {
+    match chars.peek() {
+        Some('(') => {
             let _ = chars.next();
-            let s = read_raw_until(
+            read_raw_until(
                 chars,
                 |c| c == ')',
                 |c, b| {
@@ -73,14 +212,19 @@ fn read_doller<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Token {
                     }
                     b.push(')')
                 },
-                true,
             )
-            .unwrap();
-            Token::Sub(s)
+            .map(Token::Sub)
+            .map_err(|s| Unclosed(Token::Sub(s)))
+        }
+        Some('{') => {
+            let _ = chars.next();
+            read_raw_until(chars, |c| c == '}', |c, b| b.push(c))
+                .map(Token::Param)
+                .map_err(|s| Unclosed(Token::Param(s)))
         }
         _ => {
             // everything else is lazyily evaluated
-            Token::Doller
+            Ok(Token::Doller)
         }
     }
 }
@@ -119,6 +263,11 @@ fn read_ident<I: Iterator<Item = char>>(chars: &mut Peekable<I>, c: char) -> Tok
     let mut s = String::from(c);
 
     while let Some(c) = chars.peek() {
+        if *c == '\n' && s.ends_with('\\') {
+            s.pop();
+            let _ = chars.next();
+            continue;
+        }
         if is_special(c) {
             break;
         }
@@ -128,22 +277,30 @@ fn read_ident<I: Iterator<Item = char>>(chars: &mut Peekable<I>, c: char) -> Tok
     Token::Ident(s)
 }
 
-fn read_double_quotes<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Option<Token> {
+fn read_double_quotes<I: Iterator<Item = char>>(
+    chars: &mut Peekable<I>,
+) -> Result<Token, Unclosed> {
     let mut v = vec![];
 
     loop {
         match chars.next() {
             Some('\"') => break,
             Some('\\') => v.push(read_escape(chars)),
-            Some('$') => v.push(read_doller(chars)),
+            Some('$') => match read_doller(chars) {
+                Ok(t) => v.push(t),
+                Err(Unclosed(t)) => {
+                    v.push(t);
+                    return Err(Unclosed(Token::DoubleQuote(v)));
+                }
+            },
             Some(c) => v.push(read_ident(chars, c)),
-            None => panic!("unclosed double quotes"),
+            None => return Err(Unclosed(Token::DoubleQuote(v))),
         }
     }
-    Some(Token::DoubleQuote(v))
+    Ok(Token::DoubleQuote(v))
 }
 
-fn read_single_quotes<I: Iterator<Item = char>>(chars: &mut I) -> Token {
+fn read_single_quotes<I: Iterator<Item = char>>(chars: &mut I) -> Result<Token, Unclosed> {
     log::info!("starting to read single quotes");
     read_raw_until(
         chars,
@@ -155,16 +312,16 @@ fn read_single_quotes<I: Iterator<Item = char>>(chars: &mut I) -> Token {
                 b.push(c)
             }
         },
-        true,
     )
     .map(Token::SingleQuote)
-    .expect("unclosed single quote")
+    .map_err(|s| Unclosed(Token::SingleQuote(s)))
 }
 
 fn read_escape<I: Iterator<Item = char>>(chars: &mut I) -> Token {
     match chars.next() {
         Some('n') => Token::Ident(String::from("\n")),
         Some('\\') => Token::Ident(String::from("\\")),
+        Some('\n') => Token::Ident(String::new()),
         // Token::Ident(format!("\\{}", c)),
         Some(c) => todo!("handle escaping: {:?}", c),
         // HACK: I dont know what to do here:
@@ -180,10 +337,9 @@ fn read_escape<I: Iterator<Item = char>>(chars: &mut I) -> Token {
 /// function along with the buffer. that function then is expected to push to
 /// the buffer.
 ///
-/// The must variable defines if the sequence must be matched at then end of
-/// input.
-/// When false, this function always returns [`Option::Some`].
-fn read_raw_until<I, F, E>(chars: &mut I, cond: F, escp: E, must: bool) -> Option<String>
+/// If the input ends before the condition is matched what was read is given
+/// back as an error.
+fn read_raw_until<I, F, E>(chars: &mut I, cond: F, escp: E) -> Result<String, String>
 where
     I: Iterator<Item = char>,
     F: Fn(char) -> bool,
@@ -201,11 +357,11 @@ where
                     // break;
                 }
             }
-            c if cond(c) => return Some(word),
+            c if cond(c) => return Ok(word),
             c => word.push(c),
         }
     }
-    (!must).then_some(word)
+    Err(word)
 }
 
 /// A convience wrapped that just calls [`next_token`] as an iterator.
@@ -390,6 +546,8 @@ pub enum Token {
     /// Any sub shell. This is sepurated early in the pipeline so it is not
     /// parsed twice.
     Sub(String),
+    /// `${...}` with the text inside the braces.
+    Param(String),
 }
 
 impl fmt::Display for Token {
@@ -426,6 +584,7 @@ impl fmt::Display for Token {
             Token::Percent => f.write_str("%"),
             Token::Ident(s) => f.write_str(s),
             Token::Sub(s) => write!(f, "$({})", s),
+            Token::Param(s) => write!(f, "${{{}}}", s),
         }
     }
 }
@@ -454,4 +613,70 @@ mod test {
             assert_eq!(Some(token), lexer.next())
         }
     }
+
+    #[test]
+    fn spans() {
+        let (spans, unclosed) = super::spans("ls 'a b' \"c");
+        let ranges: Vec<_> = spans.iter().map(|s| s.range.clone()).collect();
+        assert_eq!(ranges, [0..2, 2..3, 3..8, 8..9, 9..11]);
+        assert_eq!(spans[2].token, Token::SingleQuote(String::from("a b")));
+        assert!(unclosed);
+
+        let (spans, unclosed) = super::spans("echo $");
+        assert_eq!(spans.last().map(|s| &s.token), Some(&Token::Doller));
+        assert!(!unclosed);
+
+        let (spans, unclosed) = super::spans("echo ${a[@]}x ${b");
+        let tokens: Vec<_> = spans.iter().map(|s| &s.token).collect();
+        assert_eq!(tokens[2], &Token::Param(String::from("a[@]")));
+        assert_eq!(tokens[3], &Token::Ident(String::from("x")));
+        assert_eq!(tokens[5], &Token::Param(String::from("b")));
+        assert!(unclosed);
+    }
+
+    #[test]
+    fn complete() {
+        use super::is_complete;
+
+        for input in [
+            "ls -l",
+            "a && b",
+            "echo 'a|b' # |",
+            "a &",
+            "(cd x)",
+            "[[ -n x ]]",
+        ] {
+            assert!(is_complete(input), "{}", input);
+        }
+        for input in [
+            "echo 'a",
+            "ls |",
+            "a &&",
+            "a ||",
+            "echo a \\",
+            "(cd x",
+            "[[ -n x",
+        ] {
+            assert!(!is_complete(input), "{}", input);
+        }
+        assert!(!is_complete("if true; then\n  echo a"));
+        assert!(is_complete("if true; then\n  echo a\nfi"));
+        assert!(!is_complete("case x in\n  a) echo;;"));
+        assert!(is_complete("echo if fi"));
+    }
+
+    #[test]
+    fn continued_lines() {
+        let tokens: Vec<_> = Lexer::new("echo a\\\nb \\\nc".chars()).collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::Ident(String::from("echo")),
+                Token::Space,
+                Token::Ident(String::from("ab")),
+                Token::Space,
+                Token::Ident(String::from("c")),
+            ]
+        );
+    }
 }
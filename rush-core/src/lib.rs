@@ -2,6 +2,7 @@
 
 use crate::{lexer::Lexer, walker::Walker};
 
+pub mod cond;
 pub mod lexer;
 mod prelude;
 mod util;
@@ -26,7 +26,9 @@ pub(crate) struct OwnedCharBuffer {
 impl From<String> for OwnedCharBuffer {
     fn from(value: String) -> Self {
         // safety: chars will not escape the scope of this object
-        let chars = unsafe { mem::transmute(value.chars()) };
+        let chars = unsafe {
+            mem::transmute::<std::str::Chars<'_>, std::str::Chars<'static>>(value.chars())
+        };
         Self { _s: value, chars }
     }
 }
@@ -1,5 +1,6 @@
 use std::iter::Peekable;
 
+use crate::cond::CondExpr;
 use crate::prelude::*;
 
 pub struct Walker<I>
@@ -7,6 +8,9 @@ where
     I: Iterator<Item = Token>,
 {
     tokens: Peekable<I>,
+    /// If the next word is the first of a command. Some words like `[[` only
+    /// mean something here.
+    start: bool,
 }
 
 impl<I> Walker<I>
@@ -16,6 +20,7 @@ where
     pub fn new(tokens: I) -> Self {
         Self {
             tokens: tokens.peekable(),
+            start: true,
         }
     }
 
@@ -56,16 +61,45 @@ where
     type Item = TreeItem;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let item = self.read_item();
+        if let Some(TreeItem::And | TreeItem::Or | TreeItem::Pipe) = item {
+            // the command goes on after lines that end with these
+            while let Some(Token::Space | Token::Tab | Token::Newline | Token::Comment) =
+                self.tokens.peek()
+            {
+                let _ = self.tokens.next();
+            }
+        }
+        self.start = matches!(
+            item,
+            Some(
+                TreeItem::StatmentEnd
+                    | TreeItem::And
+                    | TreeItem::Or
+                    | TreeItem::Pipe
+                    | TreeItem::Bang
+            )
+        );
+        item
+    }
+}
+
+impl<I> Walker<I>
+where
+    I: Iterator<Item = Token>,
+{
+    fn read_item(&mut self) -> Option<TreeItem> {
         let mut expr = vec![];
         while let Some(t) = self.tokens.peek() {
             match t {
-                Token::Newline => {
+                Token::Newline | Token::SemiColor => {
                     has!(TreeItem::try_from(std::mem::take(&mut expr)).ok());
                     let _ = self.tokens.next();
                     return Some(TreeItem::StatmentEnd);
                 }
 
                 Token::Pipe => {
+                    has!(TreeItem::try_from(std::mem::take(&mut expr)).ok());
                     let _ = self.tokens.next();
                     if let Some(Token::Pipe) = self.tokens.peek() {
                         let _ = self.tokens.next();
@@ -74,6 +108,7 @@ where
                     return Some(TreeItem::Pipe);
                 }
                 Token::Amp => {
+                    has!(TreeItem::try_from(std::mem::take(&mut expr)).ok());
                     let _ = self.tokens.next();
                     match self.tokens.peek() {
                         Some(Token::Amp) => {
@@ -84,26 +119,41 @@ where
                         None => return Some(TreeItem::Background),
                     }
                 }
-                Token::SemiColor => todo!(),
-                Token::LeftArrow => todo!(),
-                Token::RightArrow => {
+                Token::LeftArrow | Token::RightArrow => {
+                    // a number right before the arrow is the fd to redirect
+                    let fd = match expr.as_slice() {
+                        [Expand::Literal(s)] if s.bytes().all(|b| b.is_ascii_digit()) => {
+                            s.parse().ok()
+                        }
+                        _ => None,
+                    };
+                    if fd.is_some() {
+                        expr.clear();
+                    }
+
                     // dont consume the token if we already have something
                     // buffered
                     has!(TreeItem::try_from(std::mem::take(&mut expr)).ok());
 
                     // now it can go
-                    let _ = self.tokens.next();
-                    match self.tokens.peek() {
-                        Some(Token::RightArrow) => {
-                            let _ = self.tokens.next();
-                            return Some(TreeItem::Append);
-                        }
-                        Some(Token::Space) | Some(Token::Ident(_)) => {
-                            return Some(TreeItem::Redirect);
+                    let out = matches!(self.tokens.next(), Some(Token::RightArrow));
+                    let kind = match (out, self.tokens.peek()) {
+                        (true, Some(Token::RightArrow)) => RedirectKind::Append,
+                        (true, Some(Token::Amp)) => RedirectKind::DupWrite,
+                        (true, Some(Token::Pipe)) => RedirectKind::Clobber,
+                        (false, Some(Token::Amp)) => RedirectKind::DupRead,
+                        (false, Some(Token::LeftArrow)) => {
+                            // the rest of the line can't mean anything without it
+                            while self.tokens.next_if(|t| *t != Token::Newline).is_some() {}
+                            return Some(TreeItem::SyntaxError(String::from(
+                                "here documents are not supported",
+                            )));
                         }
-                        Some(_) => todo!("bad token"),
-                        None => todo!("expected string. found eof"),
-                    }
+                        (true, _) => return Some(TreeItem::Redirect(fd, RedirectKind::Write)),
+                        (false, _) => return Some(TreeItem::Redirect(fd, RedirectKind::Read)),
+                    };
+                    let _ = self.tokens.next();
+                    return Some(TreeItem::Redirect(fd, kind));
                 }
                 Token::OpenParen => todo!(),
                 Token::CloseParen => todo!(),
@@ -116,6 +166,14 @@ where
                             };
                             expr.push(Expand::Var(s))
                         }
+                        Some(Token::Huh) => {
+                            let _ = self.tokens.next();
+                            expr.push(Expand::Var(String::from("?")))
+                        }
+                        Some(Token::Pound) => {
+                            let _ = self.tokens.next();
+                            expr.push(Expand::Var(String::from("#")))
+                        }
                         Some(Token::Space) => {
                             expr.push(Expand::Literal(String::from("$")));
                         }
@@ -142,20 +200,20 @@ where
                                 }
                             }
                             Token::Ident(s) => e.push(Expand::Literal(s)),
+                            Token::Param(s) => e.push(Expand::Param(s)),
                             Token::Sub(_) => todo!("evaluate this shit"),
                             _ => unreachable!("bad token in double quotes"),
                         }
                     }
-                    return Some(TreeItem::Word(e));
+                    return Some(TreeItem::Word(vec![Expand::Quoted(e)]));
                 }
                 Token::SingleQuote(_) => {
                     let Some(Token::SingleQuote(s)) = self.tokens.next() else {
                         unreachable!()
                     };
-                    expr.push(Expand::Literal(s))
+                    expr.push(Expand::Quoted(vec![Expand::Literal(s)]))
                 }
                 Token::Tab => todo!(),
-                Token::Glob => todo!(),
                 Token::OpenBraket => todo!(),
                 Token::CloseBraket => todo!(),
                 Token::Pound => todo!("this is not a comment it an expansion"),
@@ -163,11 +221,17 @@ where
                     log::warn!("doing bad expansion of any tilde to home");
                     expr.push(Expand::Home);
                 }
-                Token::Equal => {
+                // `*` and `?` are matched against paths when the word is
+                // expanded
+                Token::Equal | Token::Percent | Token::Glob | Token::Huh => {
                     // let a = TreeItem::Assign(expr, todo!());
-                    todo!()
+                    let t = self.tokens.next().unwrap();
+                    expr.push(Expand::Literal(t.to_string()));
+                }
+                Token::Ident(s) if s == "[[" && self.start && expr.is_empty() => {
+                    let _ = self.tokens.next();
+                    return Some(crate::cond::read_cond(&mut self.tokens));
                 }
-                Token::Percent => todo!(),
                 Token::Ident(_) => {
                     let Some(Token::Ident(s)) = self.tokens.next() else {
                         unreachable!()
@@ -187,10 +251,24 @@ where
                     };
                     expr.push(Expand::Sub(s))
                 }
-                Token::Bang => todo!(),
+                Token::Param(_) => {
+                    let Some(Token::Param(s)) = self.tokens.next() else {
+                        unreachable!()
+                    };
+                    expr.push(Expand::Param(s))
+                }
+                Token::Bang => {
+                    let _ = self.tokens.next();
+                    // a lone `!` before a command negates it otherwise it is
+                    // just text
+                    match self.tokens.peek() {
+                        Some(Token::Space | Token::Tab) if self.start && expr.is_empty() => {
+                            return Some(TreeItem::Bang);
+                        }
+                        _ => expr.push(Expand::Literal(String::from("!"))),
+                    }
+                }
 
-                // Cant Start an expression
-                Token::Huh => todo!(),
                 Token::Comment => {
                     has!(TreeItem::try_from(std::mem::take(&mut expr)).ok());
                     let _ = self.tokens.next();
@@ -199,8 +277,8 @@ where
             }
         }
 
-        // TODO: this can drop data when there is no newline at the end of the file
-        None
+        // input can end without a newline so whatever is left is the last word
+        TreeItem::try_from(expr).ok()
     }
 }
 
@@ -211,8 +289,9 @@ pub enum TreeItem {
     /// making an env for a command.
     /// can also sometimes be in arg position where it can just be stringifyed
     // Assign(Vec<Expand>, Vec<Expand>),
-    Append,
-    Redirect,
+    /// `[n]>`, `[n]>>`, `[n]<`, `[n]>&` or `[n]<&`. The word after this is
+    /// where it goes.
+    Redirect(Option<u32>, RedirectKind),
     /// `&`
     Background,
     /// `||`
@@ -221,12 +300,49 @@ pub enum TreeItem {
     And,
     /// `|`
     Pipe,
+    /// `!` at the start of a pipeline
+    Bang,
+    /// `[[ expression ]]`
+    Cond(CondExpr),
+    /// Input that could not be made into anything. Holds a message saying
+    /// why.
+    SyntaxError(String),
     /// `# *[`Token`]`
     Comment, // (String),
     /// a ';' of '\n'
     StatmentEnd,
 }
 
+/// The different ways a file descriptor can be redirected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectKind {
+    /// `>`
+    Write,
+    /// `>|` which writes even when `noclobber` is set
+    Clobber,
+    /// `>>`
+    Append,
+    /// `<`
+    Read,
+    /// `>&`
+    DupWrite,
+    /// `<&`
+    DupRead,
+}
+
+impl RedirectKind {
+    /// The fd that is redirected when a number isn't given.
+    pub fn default_fd(self) -> u32 {
+        match self {
+            RedirectKind::Write
+            | RedirectKind::Clobber
+            | RedirectKind::Append
+            | RedirectKind::DupWrite => 1,
+            RedirectKind::Read | RedirectKind::DupRead => 0,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Expand {
     Literal(String),
@@ -235,6 +351,12 @@ pub enum Expand {
     Home,
     Brace(String, ExpandAction, Vec<Expand>),
     Sub(String),
+    /// `${...}` with the text inside the braces. What it means is worked out
+    /// when it is expanded.
+    Param(String),
+    /// Text that was quoted. Quoted parts of a pattern or a word with a glob
+    /// in it only match themselves.
+    Quoted(Vec<Expand>),
 }
 
 /// What the brace does expansion does:
@@ -0,0 +1,623 @@
+//! Tab completion. The word before the cursor is found with the lexer so
+//! quotes are understood, then commands, paths, variables or users that
+//! start with it are looked for. Commands with a [`Completer`] complete
+//! their own arguments.
+
+use crate::drive::builtins::quote_word;
+use crate::prelude::*;
+
+use rush_core::lexer;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Completes the arguments of a command. `complete` makes these from shell
+/// code and [`crate::shell::Shell::register_completer`] adds them from Rust.
+pub trait Completer {
+    /// The candidates for the word being completed. They are used as they
+    /// are so they should start with [`Request::word`].
+    fn complete(&self, req: &Request, state: &mut ShellState) -> Vec<String>;
+
+    /// How the candidates are put in the line.
+    fn flags(&self) -> Flags {
+        Flags::default()
+    }
+
+    /// The options of `complete` that would make this, which `complete -p`
+    /// shows. Completers from Rust don't have any.
+    fn spec(&self) -> Option<String> {
+        None
+    }
+}
+
+/// The command a [`Completer`] is asked about. These are the same as bash's
+/// `COMP_WORDS`, `COMP_CWORD`, `COMP_LINE` and `COMP_POINT`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    /// The words of the command up to the one being completed, without
+    /// quotes.
+    pub words: Vec<String>,
+    /// Which word is being completed. It is always the last one.
+    pub index: usize,
+    pub line: String,
+    /// Where the cursor is in the line, counted in characters.
+    pub point: usize,
+}
+
+impl Request {
+    pub fn command(&self) -> &str {
+        &self.words[0]
+    }
+
+    /// The word being completed, up to the cursor.
+    pub fn word(&self) -> &str {
+        &self.words[self.index]
+    }
+
+    /// The word before the one being completed.
+    pub fn prev(&self) -> &str {
+        self.index
+            .checked_sub(1)
+            .map_or("", |i| self.words[i].as_str())
+    }
+}
+
+/// How the candidates of a [`Completer`] are used. These are the `-o`
+/// options of `complete`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+    /// Use the shell's usual completion when there are no candidates.
+    pub bashdefault: bool,
+    /// Complete file names when there are no candidates.
+    pub default: bool,
+    /// Complete directory names when there are no candidates.
+    pub dirnames: bool,
+    /// The candidates are file names. They are quoted and directories get a
+    /// slash.
+    pub filenames: bool,
+    /// Don't add a space after a finished word.
+    pub nospace: bool,
+    /// Add directory names to the candidates.
+    pub plusdirs: bool,
+}
+
+impl Flags {
+    pub const NAMES: &'static [&'static str] = &[
+        "bashdefault",
+        "default",
+        "dirnames",
+        "filenames",
+        "nospace",
+        "plusdirs",
+    ];
+
+    /// Gets the flag with a name from [`Flags::NAMES`].
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "bashdefault" => Some(&mut self.bashdefault),
+            "default" => Some(&mut self.default),
+            "dirnames" => Some(&mut self.dirnames),
+            "filenames" => Some(&mut self.filenames),
+            "nospace" => Some(&mut self.nospace),
+            "plusdirs" => Some(&mut self.plusdirs),
+            _ => None,
+        }
+    }
+
+    /// The names of the flags that are on.
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut copy = *self;
+        Flags::NAMES
+            .iter()
+            .copied()
+            .filter(|n| copy.get_mut(n).is_some_and(|on| *on))
+            .collect()
+    }
+}
+
+/// The completers of each command.
+#[derive(Default, Clone)]
+pub struct Completers {
+    map: BTreeMap<String, Rc<dyn Completer>>,
+}
+
+impl Completers {
+    pub fn get(&self, name: &str) -> Option<Rc<dyn Completer>> {
+        self.map.get(name).cloned()
+    }
+
+    pub fn set(&mut self, name: impl Into<String>, completer: Rc<dyn Completer>) {
+        self.map.insert(name.into(), completer);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Rc<dyn Completer>> {
+        self.map.remove(name)
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    /// Every command with a completer in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Rc<dyn Completer>)> {
+        self.map.iter().map(|(k, v)| (k.as_str(), v))
+    }
+}
+
+impl fmt::Debug for Completers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.map.keys()).finish()
+    }
+}
+
+/// What sort of word is being completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Command,
+    Path,
+    /// A name after a `$`.
+    Var,
+    /// A name after a `~`.
+    User,
+    /// Something a [`Completer`] gave.
+    Other,
+}
+
+/// The word before the cursor and what it could become.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    pub kind: Kind,
+    /// Where the word is in the line, counted in characters.
+    pub range: Range<usize>,
+    /// The quote the word is inside of if it hasn't been closed yet.
+    pub quote: Option<char>,
+    /// The word with its quotes taken off.
+    pub word: String,
+    /// Everything the word could be, sorted.
+    pub candidates: Vec<String>,
+    /// Don't add a space after a finished word.
+    pub nospace: bool,
+}
+
+impl Completion {
+    /// The text to put in place of the word. When `done` the word is closed
+    /// off with its quote and a space unless it is a directory.
+    pub fn replacement(&self, candidate: &str, done: bool) -> String {
+        let done = done && !candidate.ends_with('/');
+        let mut text = match self.quote {
+            Some(q) if done => format!("{q}{candidate}{q}"),
+            Some(q) => format!("{q}{candidate}"),
+            None if matches!(self.kind, Kind::Var | Kind::Other) => candidate.to_owned(),
+            None => escape(candidate),
+        };
+        if done && !self.nospace {
+            text.push(' ');
+        }
+        text
+    }
+
+    /// The longest start every candidate shares.
+    pub fn common_prefix(&self) -> &str {
+        let Some((first, rest)) = self.candidates.split_first() else {
+            return "";
+        };
+        let mut len = first.len();
+        for c in rest {
+            len = first
+                .char_indices()
+                .zip(c.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(len.min(c.len()), |((i, _), _)| len.min(i));
+        }
+        &first[..len]
+    }
+}
+
+/// Finds what the word before `cursor` could be. Nothing is given back when
+/// the cursor isn't somewhere a word can be completed, like in a comment.
+pub fn complete(line: &str, cursor: usize, state: &mut ShellState) -> Option<Completion> {
+    let before: String = line.chars().take(cursor).collect();
+    let word = word_at(&before)?;
+    let text = &word.text;
+
+    let mut flags = Flags::default();
+    let programmed = match state.completer(&word.words[0]) {
+        Some(completer) if !word.command => {
+            flags = completer.flags();
+            let req = Request {
+                index: word.words.len() - 1,
+                words: word.words.clone(),
+                line: line.to_owned(),
+                point: cursor,
+            };
+            Some(programmed(&*completer, &req, flags, state))
+        }
+        _ => None,
+    };
+
+    let (kind, candidates) = match programmed {
+        Some(found) if !found.1.is_empty() || !flags.bashdefault => found,
+        _ => match word.dollar {
+            Some(i) if text[i + 1..].chars().all(is_name) => {
+                let names = var_names(&text[i + 1..], state);
+                let vars = names.iter().map(|n| format!("{}${}", &text[..i], n));
+                (Kind::Var, vars.collect())
+            }
+            _ if text.starts_with('~') && !text.contains('/') => {
+                let users = user_names(&text[1..])
+                    .into_iter()
+                    .map(|u| format!("~{}/", u));
+                (Kind::User, users.collect())
+            }
+            _ if word.command && !text.contains('/') => (Kind::Command, commands(text, state)),
+            _ => (Kind::Path, paths(text)),
+        },
+    };
+
+    Some(Completion {
+        kind,
+        range: word.range,
+        quote: word.quote,
+        word: word.text,
+        candidates,
+        nospace: flags.nospace,
+    })
+}
+
+/// Asks a completer for candidates and falls back to paths like its flags
+/// say.
+fn programmed(
+    completer: &dyn Completer,
+    req: &Request,
+    flags: Flags,
+    state: &mut ShellState,
+) -> (Kind, Vec<String>) {
+    let word = req.word();
+    let mut candidates = completer.complete(req, state);
+    if flags.plusdirs {
+        candidates.extend(dirs(word));
+    }
+    if candidates.is_empty() && flags.dirnames {
+        candidates = dirs(word);
+    }
+    if candidates.is_empty() && flags.default {
+        return (Kind::Path, paths(word));
+    }
+
+    if flags.filenames {
+        for c in candidates.iter_mut().filter(|c| !c.ends_with('/')) {
+            if Path::new(c.as_str()).is_dir() {
+                c.push('/');
+            }
+        }
+    }
+    let kind = if flags.filenames {
+        Kind::Path
+    } else {
+        Kind::Other
+    };
+    (kind, sorted(candidates.into_iter()))
+}
+
+/// The word the cursor is at the end of.
+#[derive(Debug, PartialEq, Eq)]
+struct Word {
+    text: String,
+    range: Range<usize>,
+    quote: Option<char>,
+    /// Where the last `$` that isn't in single quotes is in `text`.
+    dollar: Option<usize>,
+    /// If the word is the name of a command.
+    command: bool,
+    /// The words of the command the cursor is in. The last is `text`.
+    words: Vec<String>,
+}
+
+fn word_at(before: &str) -> Option<Word> {
+    let (spans, unclosed) = lexer::spans(before);
+    if spans.last().is_some_and(|s| s.token == Token::Comment) {
+        return None;
+    }
+
+    let start = spans
+        .iter()
+        .rposition(|s| ends_word(&s.token))
+        .map_or(0, |i| i + 1);
+    let (prev, word) = spans.split_at(start);
+
+    let mut text = String::new();
+    let mut dollar = None;
+    for span in word {
+        push_unquoted(&span.token, &mut text, &mut dollar)?;
+    }
+
+    let quote = match word.last().map(|s| &s.token) {
+        Some(Token::SingleQuote(_)) if unclosed => Some('\''),
+        Some(Token::DoubleQuote(_)) if unclosed => Some('"'),
+        _ => None,
+    };
+    let command = prev
+        .iter()
+        .rev()
+        .find(|s| !matches!(s.token, Token::Space | Token::Tab))
+        .is_none_or(|s| starts_command(&s.token));
+
+    let cmd_start = prev
+        .iter()
+        .rposition(|s| starts_command(&s.token))
+        .map_or(0, |i| i + 1);
+    let mut words = Vec::new();
+    let mut current = String::new();
+    for span in &prev[cmd_start..] {
+        if ends_word(&span.token) {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+        } else {
+            // words with substitutions are kept as far as they can be
+            let _ = push_unquoted(&span.token, &mut current, &mut None);
+        }
+    }
+    words.push(text.clone());
+
+    let end = before.chars().count();
+    let from = word.first().map_or(end, |s| s.range.start);
+    Some(Word {
+        text,
+        range: from..end,
+        quote,
+        dollar,
+        command,
+        words,
+    })
+}
+
+fn ends_word(token: &Token) -> bool {
+    starts_command(token)
+        || matches!(
+            token,
+            Token::Space | Token::Tab | Token::LeftArrow | Token::RightArrow | Token::CloseParen
+        )
+}
+
+/// Tokens after which a command name comes.
+fn starts_command(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Pipe
+            | Token::SemiColor
+            | Token::Amp
+            | Token::OpenParen
+            | Token::Bang
+            | Token::Newline
+    )
+}
+
+/// Adds a token's text without quotes. Substitutions can't be completed so
+/// they give back nothing.
+fn push_unquoted(token: &Token, text: &mut String, dollar: &mut Option<usize>) -> Option<()> {
+    match token {
+        Token::Ident(s) | Token::SingleQuote(s) => text.push_str(s),
+        Token::DoubleQuote(inner) => {
+            for t in inner {
+                push_unquoted(t, text, dollar)?;
+            }
+        }
+        Token::Doller => {
+            *dollar = Some(text.len());
+            text.push('$');
+        }
+        Token::Tilde => text.push('~'),
+        Token::Equal => text.push('='),
+        Token::Percent => text.push('%'),
+        Token::Pound => text.push('#'),
+        Token::Bang => text.push('!'),
+        Token::Glob => text.push('*'),
+        Token::Huh => text.push('?'),
+        Token::OpenBraket => text.push('{'),
+        Token::CloseBraket => text.push('}'),
+        _ => return None,
+    }
+    Some(())
+}
+
+fn is_name(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Quotes a word so it can be typed back in. A `~user/` at the start is
+/// left alone so it is still expanded.
+fn escape(s: &str) -> String {
+    let (tilde, rest) = match s.strip_prefix('~') {
+        Some(rest) => s.split_at(rest.find('/').map_or(s.len(), |i| i + 2)),
+        None => ("", s),
+    };
+    if rest.is_empty() {
+        tilde.to_owned()
+    } else {
+        format!("{}{}", tilde, quote_word(rest))
+    }
+}
+
+/// Variables of the shell and the environment.
+pub(crate) fn var_names(prefix: &str, state: &ShellState) -> Vec<String> {
+    let shell = state.vars().into_iter().map(|(k, _)| k.to_owned());
+    sorted(
+        env_names(prefix)
+            .into_iter()
+            .chain(shell)
+            .filter(|k| k.starts_with(prefix)),
+    )
+}
+
+/// Variables that are exported.
+pub(crate) fn env_names(prefix: &str) -> Vec<String> {
+    sorted(
+        std::env::vars()
+            .map(|(k, _)| k)
+            .filter(|k| k.starts_with(prefix)),
+    )
+}
+
+pub(crate) fn user_names(prefix: &str) -> Vec<String> {
+    sorted(
+        passwd()
+            .into_iter()
+            .map(|(user, _)| user)
+            .filter(|user| user.starts_with(prefix)),
+    )
+}
+
+/// Builtins, aliases and programs in `PATH`.
+pub(crate) fn commands(name: &str, state: &ShellState) -> Vec<String> {
+    let builtins = state.builtins().names().into_iter().map(str::to_owned);
+    let aliases = state.aliases().into_iter().map(|(a, _)| a.to_owned());
+
+    let path = state.get_env_exact("PATH").unwrap_or_default();
+    let programs = std::env::split_paths(&path)
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(name))
+        .filter(|entry| {
+            std::fs::metadata(entry.path())
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+        .map(|entry| entry.file_name().to_string_lossy().into_owned());
+
+    sorted(
+        builtins
+            .chain(aliases)
+            .filter(|c| c.starts_with(name))
+            .chain(programs),
+    )
+}
+
+/// Files in the directory the word names. Hidden files are only given when
+/// the name starts with a `.`. Directories end with a slash.
+pub(crate) fn paths(word: &str) -> Vec<String> {
+    let (dir, file) = match word.rfind('/') {
+        Some(i) => word.split_at(i + 1),
+        None => ("", word),
+    };
+    let search = match dir {
+        "" => PathBuf::from("."),
+        dir => expand_tilde(dir),
+    };
+    let Ok(entries) = std::fs::read_dir(search) else {
+        return Vec::new();
+    };
+
+    sorted(entries.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with(file) || (name.starts_with('.') && !file.starts_with('.')) {
+            return None;
+        }
+        // links to directories count as directories
+        let slash = if entry.path().is_dir() { "/" } else { "" };
+        Some(format!("{}{}{}", dir, name, slash))
+    }))
+}
+
+pub(crate) fn dirs(word: &str) -> Vec<String> {
+    let mut paths = paths(word);
+    paths.retain(|p| p.ends_with('/'));
+    paths
+}
+
+fn expand_tilde(dir: &str) -> PathBuf {
+    let Some(rest) = dir.strip_prefix('~') else {
+        return PathBuf::from(dir);
+    };
+    let (user, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let home = if user.is_empty() {
+        std::env::var("HOME").ok()
+    } else {
+        passwd()
+            .into_iter()
+            .find(|(name, _)| name == user)
+            .map(|(_, home)| home)
+    };
+    match home {
+        Some(home) => PathBuf::from(format!("{}{}", home, rest)),
+        None => PathBuf::from(dir),
+    }
+}
+
+/// The name and home directory of every user.
+fn passwd() -> Vec<(String, String)> {
+    let Ok(file) = std::fs::read_to_string("/etc/passwd") else {
+        return Vec::new();
+    };
+    file.lines()
+        .filter_map(|line| {
+            let fields: Vec<_> = line.split(':').collect();
+            Some((fields.first()?.to_string(), fields.get(5)?.to_string()))
+        })
+        .collect()
+}
+
+pub(crate) fn sorted(words: impl Iterator<Item = String>) -> Vec<String> {
+    let mut words: Vec<_> = words.collect();
+    words.sort_unstable();
+    words.dedup();
+    words
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn word_before_cursor() {
+        let word = word_at("ls -l | gr").unwrap();
+        assert_eq!(word.text, "gr");
+        assert_eq!(word.words, ["gr"]);
+        assert_eq!(word.range, 8..10);
+        assert!(word.command);
+
+        let word = word_at("cat 'my fi").unwrap();
+        assert_eq!((word.text.as_str(), word.quote), ("my fi", Some('\'')));
+        assert_eq!(word.range, 4..10);
+        assert!(!word.command);
+        assert_eq!(word.words, ["cat", "my fi"]);
+
+        let word = word_at("echo \"$HO").unwrap();
+        assert_eq!((word.text.as_str(), word.dollar), ("$HO", Some(0)));
+        assert_eq!(word.quote, Some('"'));
+
+        let word = word_at("echo '$HO'").unwrap();
+        assert_eq!(word.dollar, None);
+
+        let word = word_at("echo > ").unwrap();
+        assert_eq!((word.text.as_str(), word.range), ("", 7..7));
+        assert!(word_at("ls # com").is_none());
+    }
+
+    #[test]
+    fn replacements() {
+        let mut c = Completion {
+            kind: Kind::Path,
+            range: 0..0,
+            quote: None,
+            word: String::new(),
+            candidates: vec![String::from("my file"), String::from("my dir/")],
+            nospace: false,
+        };
+        assert_eq!(c.common_prefix(), "my ");
+        assert_eq!(c.replacement("my file", true), "'my file' ");
+        assert_eq!(c.replacement("my dir/", true), "'my dir/'");
+        assert_eq!(c.replacement("~root/a b", false), "~root/'a b'");
+
+        c.quote = Some('"');
+        assert_eq!(c.replacement("my file", true), "\"my file\" ");
+        c.kind = Kind::Var;
+        c.quote = None;
+        assert_eq!(c.replacement("$HOME", true), "$HOME ");
+        c.nospace = true;
+        assert_eq!(c.replacement("$HOME", true), "$HOME");
+    }
+}
@@ -1,17 +1,27 @@
-use crate::parse::Fd;
+use self::builtins::{Io, Stream};
+use crate::parse::prompt::{expand, printable};
+use crate::parse::{expand_cond, CmdError, Fd, Redirect};
 use crate::prelude::*;
+use crate::shell::options::Opt;
+use crate::util::error_message;
 
 use crate::{
     parse::{Cmd, SimpleCmd, Streams},
     task::Task,
 };
 
-use std::os::fd::FromRawFd;
+use rush_core::walker::RedirectKind;
+
+use std::fs::{File, OpenOptions};
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
 
 #[derive(Debug)]
 pub enum DriverError {
     Spawn,
     Pipe,
+    Wait,
 }
 
 impl fmt::Display for DriverError {
@@ -19,6 +29,7 @@ impl fmt::Display for DriverError {
         match self {
             DriverError::Spawn => f.write_str("failed to spawn command"),
             DriverError::Pipe => f.write_str("failed to open pipe"),
+            DriverError::Wait => f.write_str("failed to wait for command"),
         }
     }
 }
@@ -32,99 +43,570 @@ pub fn run_command(
     state: &mut ShellState,
 ) -> Result<Vec<Task>, DriverError> {
     log::info!("cmd is: {:?}", cmd);
-    use self::builtins::ShellBuiltin;
 
+    state.tested = false;
     match cmd {
-        Cmd::Simple(SimpleCmd { cmd, args, env }) => match cmd.as_str() {
-            "exit" => {
-                log::info!("running exit command");
-                // TODO: fuse the stdin fd to stdout so anything in this
-                // pipe line ignores this call.
-                // This should be done for all builtins.
-                Ok(vec![Task::Builtin(builtins::Exit::run(&args, state))])
+        Cmd::Simple(raw) => match raw.expand(state) {
+            Ok(simple) => run_simple(simple, streams, state),
+            Err(e) => Ok(expand_failed(e, true, state)),
+        },
+        Cmd::Pipeline(stages) => run_pipeline(stages, streams, state),
+        Cmd::And(a, b) => {
+            let left = streams.try_clone().change_context(DriverError::Spawn)?;
+            let code = wait_all(run_command(*a, left, state)?, state)?;
+            if code == 0 {
+                run_command(*b, streams, state)
+            } else {
+                state.tested = true;
+                Ok(vec![Task::Builtin(code)])
             }
-            "cd" => Ok(vec![Task::Builtin(builtins::Cd::run(&args, state))]),
-            cmd => {
-                log::info!("Running command: [{}, {:?}]", cmd, args);
-
-                let child = std::process::Command::new(cmd)
-                    .args(args)
-                    .envs(env)
-                    .stdout(streams.stdout)
-                    .stdin(streams.stdin)
-                    .stderr(streams.stderr)
-                    .spawn()
-                    .change_context(DriverError::Spawn)?;
-
-                // for handling things like ^C and ^Z
-                // let stdin = child.stdin.take().unwrap();
-
-                Ok(vec![Task::System(child)])
+        }
+        Cmd::Or(a, b) => {
+            let left = streams.try_clone().change_context(DriverError::Spawn)?;
+            let code = wait_all(run_command(*a, left, state)?, state)?;
+            if code != 0 {
+                run_command(*b, streams, state)
+            } else {
+                state.tested = true;
+                Ok(vec![Task::Builtin(code)])
             }
-        },
-        Cmd::Pipeline(c, d) => {
-            let mut pipes = [0; 2];
+        }
+        Cmd::Not(c) => {
+            let code = wait_all(run_command(*c, streams, state)?, state)?;
+            state.tested = true;
+            Ok(vec![Task::Builtin((code == 0) as i32)])
+        }
+        Cmd::Cond(e, redirects) => {
+            let expanded = expand_cond(e, state).and_then(|e| {
+                let redirects = redirects.into_iter().map(|r| r.expand(state));
+                Ok((e, redirects.collect::<Result<Vec<_>, _>>()?))
+            });
+            let (e, redirects) = match expanded {
+                Ok(expanded) => expanded,
+                Err(e) => return Ok(expand_failed(e, true, state)),
+            };
+            let code = match builtin_io(streams, &redirects, state.option(Opt::Noclobber)) {
+                Ok(mut io) => builtins::cond(&e, &mut io),
+                Err(e) => {
+                    eprintln!("rush: {}", e);
+                    1
+                }
+            };
+            Ok(vec![Task::Builtin(code)])
+        }
+        Cmd::Empty => Ok(vec![]),
+    }
+}
 
-            if unsafe { libc::pipe2(pipes.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
-                let err = Err(std::io::Error::last_os_error());
-                return err.change_context(DriverError::Pipe);
+/// Runs a command once its words are expanded. A command that expanded to
+/// nothing doesn't run.
+fn run_simple(
+    simple: SimpleCmd,
+    streams: Streams,
+    state: &mut ShellState,
+) -> Result<Vec<Task>, DriverError> {
+    if simple.cmd.is_empty() {
+        return Ok(vec![]);
+    }
+    if state.option(Opt::Xtrace) {
+        trace(&simple, state);
+    }
+
+    let noclobber = state.option(Opt::Noclobber);
+    let SimpleCmd {
+        cmd,
+        args,
+        env,
+        redirects,
+    } = simple;
+    match cmd.as_str() {
+        name if state.builtins().contains(name) => {
+            log::info!("running builtin: [{}, {:?}]", name, args);
+            let builtin = state.builtins().get(name).unwrap();
+
+            let code = if builtin.keeps_redirects() {
+                // the redirections are done to the shell itself so they
+                // stay for every command after this one
+                let res = open_redirects(&redirects, noclobber)
+                    .and_then(|fds| unsafe { redirect_shell(fds) })
+                    .and_then(|_| builtin_io(streams, &[], false));
+                match res {
+                    Ok(mut io) => builtin.run(&args, state, &mut io),
+                    Err(e) => {
+                        eprintln!("rush: {}: {}", name, e);
+                        1
+                    }
+                }
+            } else {
+                match builtin_io(streams, &redirects, noclobber) {
+                    Ok(mut io) => builtin.run(&args, state, &mut io),
+                    Err(e) => {
+                        eprintln!("rush: {}", e);
+                        1
+                    }
+                }
+            };
+            Ok(vec![Task::Builtin(code)])
+        }
+        cmd => {
+            log::info!("Running command: [{}, {:?}]", cmd, args);
+
+            let fds = match open_redirects(&redirects, noclobber) {
+                Ok(fds) => fds,
+                Err(e) => {
+                    eprintln!("rush: {}", e);
+                    return Ok(vec![Task::Builtin(1)]);
+                }
+            };
+
+            let mut command = match state.find_command(cmd) {
+                Some(path) => {
+                    let mut c = std::process::Command::new(path);
+                    c.arg0(cmd);
+                    c
+                }
+                // let spawning fail the normal way
+                None => std::process::Command::new(cmd),
+            };
+            command
+                .args(args)
+                .envs(env)
+                .stdout(streams.stdout)
+                .stdin(streams.stdin)
+                .stderr(streams.stderr);
+
+            // redirections happen after the pipes are set up so they
+            // take precedence over them
+            if !fds.is_empty() {
+                unsafe { command.pre_exec(move || apply_redirects(&fds)) };
+            }
+
+            let child = match command.spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    // this only fails this command, like the command
+                    // ran and exited
+                    let code = match e.kind() {
+                        io::ErrorKind::NotFound if !cmd.contains('/') => {
+                            eprintln!("rush: {}: command not found", cmd);
+                            127
+                        }
+                        io::ErrorKind::NotFound => {
+                            eprintln!("rush: {}: {}", cmd, error_message(&e));
+                            127
+                        }
+                        _ => {
+                            eprintln!("rush: {}: {}", cmd, error_message(&e));
+                            126
+                        }
+                    };
+                    return Ok(vec![Task::Builtin(code)]);
+                }
+            };
+
+            // for handling things like ^C and ^Z
+            // let stdin = child.stdin.take().unwrap();
+
+            Ok(vec![Task::System(child)])
+        }
+    }
+}
+
+/// Reports a command whose words couldn't be expanded, which happens when a
+/// variable that isn't set is used with `nounset` or a `${...}` isn't
+/// understood. That is a mistake in the script so it only gets a short
+/// message, and with `stop` it ends a shell that isn't interactive. The
+/// command fails with status 1.
+fn expand_failed(e: Report<CmdError>, stop: bool, state: &mut ShellState) -> Vec<Task> {
+    match e.current_context() {
+        CmdError::Unbound(_) | CmdError::BadSubstitution(_) => {
+            eprintln!("rush: {}", e.current_context())
+        }
+        _ => eprintln!("{:?}", e),
+    }
+    state.exit |= stop && !state.interactive;
+    vec![Task::Builtin(1)]
+}
+
+/// Waits for every task, which are the stages of a pipeline, and gives back
+/// the status of the pipeline. This is needed when what runs next depends on
+/// it.
+///
+/// Every stage is reaped even when waiting on one of them fails. The status
+/// of each is put in `PIPESTATUS`.
+pub(crate) fn wait_all(tasks: Vec<Task>, state: &mut ShellState) -> Result<i32, DriverError> {
+    let mut err = None;
+    let statuses: Vec<i32> = tasks
+        .into_iter()
+        .map(|t| match t.wait() {
+            Ok(code) => code,
+            Err(e) => {
+                log::error!("{:?}", e);
+                err.get_or_insert(e);
+                1
             }
+        })
+        .collect();
+
+    let code = pipeline_status(&statuses, state.option(Opt::Pipefail));
+    state.set_array(
+        "PIPESTATUS",
+        statuses.iter().map(ToString::to_string).collect(),
+    );
+    state.set_prev(code);
+    match err {
+        Some(e) => Err(e.change_context(DriverError::Wait)),
+        None => Ok(code),
+    }
+}
+
+/// The status of a pipeline from the status of each stage. It is the last
+/// stage's, or with `pipefail` the last one that failed.
+fn pipeline_status(statuses: &[i32], pipefail: bool) -> i32 {
+    if pipefail {
+        statuses
+            .iter()
+            .rev()
+            .find(|c| **c != 0)
+            .copied()
+            .unwrap_or(0)
+    } else {
+        statuses.last().copied().unwrap_or(0)
+    }
+}
 
-            log::info!("made pipes: {:?}", pipes);
+/// Prints a command to stderr for `xtrace`, after `PS4`.
+fn trace(cmd: &SimpleCmd, state: &ShellState) {
+    let ps4 = state
+        .get_env_exact("PS4")
+        .map_or_else(|| String::from("+ "), |ps4| printable(&expand(&ps4, state)));
+    let words: Vec<_> = std::iter::once(&cmd.cmd)
+        .chain(&cmd.args)
+        .map(|w| builtins::quote_word(w))
+        .collect();
+    eprintln!("{}{}", ps4, words.join(" "));
+}
 
-            let mut sc = streams;
-            sc.stdout = unsafe { Fd::from_raw_fd(pipes[1]) };
+/// What a file descriptor is changed to by a [`Redirect`].
+#[derive(Debug)]
+enum Target {
+    File(OwnedFd),
+    Fd(RawFd),
+    Close,
+}
 
-            let sd = Streams {
-                stdin: unsafe { Fd::from_raw_fd(pipes[0]) },
-                ..Default::default()
+/// Opens all of the files a command is redirected to. Nothing is changed in
+/// this process, that is done by [`apply_redirects`]. With `noclobber` a `>`
+/// won't write over a file that is already there.
+fn open_redirects(redirects: &[Redirect], noclobber: bool) -> io::Result<Vec<(RawFd, Target)>> {
+    redirects
+        .iter()
+        .map(|r| {
+            let target = match r.kind {
+                RedirectKind::DupWrite | RedirectKind::DupRead if r.target == "-" => Target::Close,
+                RedirectKind::DupWrite | RedirectKind::DupRead => {
+                    let fd = r.target.parse().map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("{}: ambiguous redirect", r.target),
+                        )
+                    })?;
+                    Target::Fd(fd)
+                }
+                RedirectKind::Write if noclobber && Path::new(&r.target).is_file() => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{}: cannot overwrite existing file", r.target),
+                    ));
+                }
+                kind => {
+                    let mut opts = OpenOptions::new();
+                    match kind {
+                        RedirectKind::Write | RedirectKind::Clobber => {
+                            opts.write(true).create(true).truncate(true)
+                        }
+                        RedirectKind::Append => opts.append(true).create(true),
+                        _ => opts.read(true),
+                    };
+                    let file = opts.open(&r.target).map_err(|e| {
+                        io::Error::new(e.kind(), format!("{}: {}", r.target, error_message(&e)))
+                    })?;
+                    Target::File(OwnedFd::from(file))
+                }
             };
+            Ok((r.fd, target))
+        })
+        .collect()
+}
 
-            let mut a = run_command(*c, sc, state)?;
-            let b = run_command(*d, sd, state)?;
+/// Points each fd at its target, in order.
+///
+/// # Safety
+/// This only makes async signal safe calls so it can be used in
+/// [`CommandExt::pre_exec`]. When used in the shell itself it changes the fds
+/// for everything that runs after.
+unsafe fn apply_redirects(fds: &[(RawFd, Target)]) -> io::Result<()> {
+    for (fd, target) in fds {
+        let res = match target {
+            // the file is already where it needs to be but it would be
+            // closed on exec
+            Target::File(f) if f.as_raw_fd() == *fd => {
+                let flags = libc::fcntl(*fd, libc::F_GETFD);
+                libc::fcntl(*fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC)
+            }
+            Target::File(f) => libc::dup2(f.as_raw_fd(), *fd),
+            Target::Fd(src) => libc::dup2(*src, *fd),
+            Target::Close => {
+                // closing an fd that isn't open is fine
+                libc::close(*fd);
+                0
+            }
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
 
-            a.extend(b);
-            Ok(a)
+/// Applies redirections to the shell itself so every command after this
+/// sees them.
+///
+/// # Safety
+/// See [`apply_redirects`].
+unsafe fn redirect_shell(fds: Vec<(RawFd, Target)>) -> io::Result<()> {
+    apply_redirects(&fds)?;
+    for (fd, target) in fds {
+        // files that were opened right where they go have to stay open
+        match target {
+            Target::File(f) if f.as_raw_fd() == fd => {
+                let _ = f.into_raw_fd();
+            }
+            _ => {}
         }
-        Cmd::And(_, _) => todo!(),
-        Cmd::Or(_, _) => todo!(),
-        Cmd::Not(_) => todo!(),
-        Cmd::Empty => Ok(vec![]),
     }
+    Ok(())
 }
 
-mod builtins {
-    use crate::shell::ShellState;
+/// Runs each command of a pipeline with its output going to the next one.
+///
+/// Every pipe is made before anything starts. The shell lets go of its ends
+/// as soon as each stage has them so a reader sees the end of its input once
+/// the writer is done. If a stage can't be started the ones before it are
+/// killed.
+fn run_pipeline(
+    stages: Vec<Cmd>,
+    streams: Streams,
+    state: &mut ShellState,
+) -> Result<Vec<Task>, DriverError> {
+    let n = stages.len();
+    let mut reads = Vec::with_capacity(n);
+    let mut writes = Vec::with_capacity(n);
+    for _ in 1..n {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+            let err = Err(std::io::Error::last_os_error());
+            return err.change_context(DriverError::Pipe);
+        }
+        log::info!("made pipe: {:?}", fds);
+        reads.push(Some(unsafe { OwnedFd::from_raw_fd(fds[0]) }));
+        writes.push(Some(unsafe { OwnedFd::from_raw_fd(fds[1]) }));
+    }
+
+    let Streams {
+        stdin,
+        stdout,
+        stderr,
+    } = streams;
+    let (mut stdin, mut stdout) = (Some(stdin), Some(stdout));
+
+    let mut tasks = Vec::with_capacity(n);
+    for (i, cmd) in stages.into_iter().enumerate() {
+        let stage_stderr = match &stderr {
+            Fd::Inherit => Ok(Fd::Inherit),
+            Fd::Piped(f) => f.try_clone().map(Fd::Piped),
+        };
+        let stage = stage_stderr
+            .change_context(DriverError::Pipe)
+            .and_then(|stderr| {
+                let streams = Streams {
+                    stdin: match i {
+                        0 => stdin.take().unwrap_or_default(),
+                        i => reads[i - 1].take().map_or(Fd::Inherit, Fd::Piped),
+                    },
+                    stdout: match writes.get_mut(i) {
+                        Some(w) => w.take().map_or(Fd::Inherit, Fd::Piped),
+                        None => stdout.take().unwrap_or_default(),
+                    },
+                    stderr,
+                };
+                // a forked stage has a copy of every end the shell still has
+                let open: Vec<RawFd> = reads
+                    .iter()
+                    .chain(&writes)
+                    .flatten()
+                    .map(|f| f.as_raw_fd())
+                    .collect();
+                run_stage(cmd, streams, &open, state)
+            });
 
-    pub(crate) trait ShellBuiltin {
-        fn run(args: &[String], state: &mut ShellState) -> i32;
+        match stage {
+            Ok(t) => tasks.extend(t),
+            Err(e) => {
+                // the pipes are closed when this returns so the only thing
+                // left is the stages that already started
+                for task in tasks {
+                    task.kill();
+                }
+                return Err(e);
+            }
+        }
     }
+    Ok(tasks)
+}
 
-    pub struct Exit;
-    impl ShellBuiltin for Exit {
-        fn run(args: &[String], state: &mut ShellState) -> i32 {
-            let _ = args;
-            eprintln!("exit");
+/// Runs one command of a pipeline. Builtins are run in a child process, like
+/// a subshell, so they don't hold up the other commands or change the shell.
+/// The child closes `open`, which are the pipe ends of other stages.
+fn run_stage(
+    cmd: Cmd,
+    streams: Streams,
+    open: &[RawFd],
+    state: &mut ShellState,
+) -> Result<Vec<Task>, DriverError> {
+    // the name is only known once the words are expanded. Like a subshell
+    // a stage that can't be expanded only fails itself.
+    let simple = match cmd {
+        Cmd::Simple(raw) => match raw.expand(state) {
+            Ok(simple) => simple,
+            Err(e) => return Ok(expand_failed(e, false, state)),
+        },
+        cmd => return run_command(cmd, streams, state),
+    };
+    if !state.builtins().contains(&simple.cmd) {
+        return run_simple(simple, streams, state);
+    }
+
+    match unsafe { libc::fork() } {
+        -1 => {
+            let err = Err(std::io::Error::last_os_error());
+            err.change_context(DriverError::Spawn)
+        }
+        0 => {
+            // this never returns so something like `exit` only ends this
+            // part of the pipeline
+            crate::signal::reset_caught();
+            for fd in open {
+                unsafe { libc::close(*fd) };
+            }
+            let code = match unsafe { use_streams(streams) } {
+                Ok(()) => match run_simple(simple, Streams::default(), state)
+                    .and_then(|tasks| wait_all(tasks, state))
+                {
+                    Ok(code) => code,
+                    Err(e) => {
+                        eprintln!("rush: {}", e);
+                        1
+                    }
+                },
+                Err(e) => {
+                    eprintln!("rush: {}", error_message(&e));
+                    1
+                }
+            };
+            unsafe { libc::_exit(code) }
+        }
+        pid => {
+            log::info!("forked builtin: {}", pid);
+            Ok(vec![Task::Forked(pid)])
+        }
+    }
+}
 
-            state.exit = true;
-            0
+/// Moves piped streams onto fds 0, 1 and 2 of this process.
+///
+/// # Safety
+/// See [`apply_redirects`].
+unsafe fn use_streams(streams: Streams) -> io::Result<()> {
+    let streams = [streams.stdin, streams.stdout, streams.stderr];
+    for (fd, stream) in (0..).zip(streams) {
+        let Fd::Piped(f) = stream else {
+            continue;
+        };
+        if f.as_raw_fd() == fd {
+            // it is already in place and has to stay open
+            let f = f.into_raw_fd();
+            let flags = libc::fcntl(f, libc::F_GETFD);
+            libc::fcntl(f, libc::F_SETFD, flags & !libc::FD_CLOEXEC);
+        } else if libc::dup2(f.as_raw_fd(), fd) < 0 {
+            return Err(io::Error::last_os_error());
         }
     }
+    Ok(())
+}
+
+/// Gets the standard streams of a builtin after its redirections. Other fds
+/// are not looked at since builtins don't use them.
+fn builtin_io(streams: Streams, redirects: &[Redirect], noclobber: bool) -> io::Result<Io> {
+    let dup = |fd: RawFd| {
+        let new = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 3) };
+        if new < 0 {
+            let e = io::Error::last_os_error();
+            return Err(io::Error::new(
+                e.kind(),
+                format!("{}: {}", fd, error_message(&e)),
+            ));
+        }
+        Ok(unsafe { OwnedFd::from_raw_fd(new) })
+    };
 
-    pub struct Cd;
-    impl ShellBuiltin for Cd {
-        fn run(args: &[String], state: &mut ShellState) -> i32 {
-            let _ = state;
-            let dir = args
-                .iter()
-                .next()
-                .map(ToOwned::to_owned)
-                .unwrap_or_else(|| state.home().to_owned());
+    let mut fds = Vec::with_capacity(3);
+    for (fd, stream) in (0..).zip([streams.stdin, streams.stdout, streams.stderr]) {
+        fds.push(match stream {
+            Fd::Piped(f) => Some(f),
+            // the shell's own stream might be closed
+            Fd::Inherit => dup(fd).ok(),
+        });
+    }
 
-            // libc::chdir(dir)
-            // nix::unistd::chdir(&dir);
-            std::env::set_current_dir(dir).unwrap();
-            0
+    for (fd, target) in open_redirects(redirects, noclobber)? {
+        if !(0..3).contains(&fd) {
+            continue;
         }
+        fds[fd as usize] = match target {
+            Target::File(f) => Some(f),
+            Target::Fd(src) if (0..3).contains(&src) => match &fds[src as usize] {
+                Some(f) => Some(f.try_clone()?),
+                None => {
+                    let msg = format!("{}: Bad file descriptor", src);
+                    return Err(io::Error::other(msg));
+                }
+            },
+            Target::Fd(src) => Some(dup(src)?),
+            Target::Close => None,
+        };
     }
+
+    let mut fds = fds.into_iter().map(|f| Stream(f.map(File::from)));
+    Ok(Io {
+        stdin: fds.next().unwrap(),
+        stdout: fds.next().unwrap(),
+        stderr: fds.next().unwrap(),
+    })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pipeline_statuses() {
+        assert_eq!(pipeline_status(&[], false), 0);
+        assert_eq!(pipeline_status(&[1, 0], false), 0);
+        assert_eq!(pipeline_status(&[0, 1], false), 1);
+        assert_eq!(pipeline_status(&[2, 3, 0], true), 3);
+        assert_eq!(pipeline_status(&[0, 0], true), 0);
+    }
+}
+
+pub mod builtins;
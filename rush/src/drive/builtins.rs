@@ -0,0 +1,354 @@
+use crate::parse::{Fd, Streams};
+use crate::shell::options::Opt;
+use crate::shell::{Condition, ShellState};
+use crate::util::error_message;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+mod alias;
+mod cd;
+mod command;
+mod complete;
+mod history;
+mod print;
+mod read;
+mod set;
+mod test;
+mod trap;
+
+pub use self::alias::{quote, quote_word, Alias, Unalias};
+pub use self::cd::{Cd, Dirs, Popd, Pushd};
+pub use self::command::{resolve, Builtin, Command, Hash, Help, Resolved, Type, KEYWORDS};
+pub use self::complete::{Compgen, Complete};
+pub use self::history::{Fc, History};
+pub use self::print::{Echo, Printf};
+pub use self::read::Read;
+pub use self::set::{Set, Shopt};
+pub use self::test::{cond, Bracket, Test};
+pub use self::trap::Trap;
+
+/// A command that runs in the shell itself instead of in a new process.
+///
+/// Builtins are looked up by name in [`Builtins`] so code that embeds the
+/// shell can add its own.
+pub trait ShellBuiltin {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32;
+
+    /// The usage of the builtin on the first line followed by a short
+    /// description. This is what `help` shows.
+    fn help(&self) -> &str;
+
+    /// If the redirections of this builtin are done to the shell itself so
+    /// they stay for every command after it, like they do for `exec`.
+    fn keeps_redirects(&self) -> bool {
+        false
+    }
+}
+
+/// The builtins a shell knows about, keyed by name.
+#[derive(Clone)]
+pub struct Builtins {
+    map: HashMap<String, Rc<dyn ShellBuiltin>>,
+}
+
+impl Default for Builtins {
+    /// All of the builtins that come with the shell.
+    fn default() -> Self {
+        let mut b = Builtins::empty();
+        b.register("exit", Exit);
+        b.register("exec", Exec);
+        b.register("cd", Cd);
+        b.register("pushd", Pushd);
+        b.register("popd", Popd);
+        b.register("dirs", Dirs);
+        b.register("test", Test);
+        b.register("[", Bracket);
+        b.register(".", Source);
+        b.register("source", Source);
+        b.register("eval", Eval);
+        b.register("read", Read);
+        b.register("echo", Echo);
+        b.register("printf", Printf);
+        b.register("builtin", Builtin);
+        b.register("command", Command);
+        b.register("help", Help);
+        b.register("type", Type);
+        b.register("hash", Hash);
+        b.register("alias", Alias);
+        b.register("unalias", Unalias);
+        b.register("trap", Trap);
+        b.register("set", Set);
+        b.register("shopt", Shopt);
+        b.register("complete", Complete);
+        b.register("compgen", Compgen);
+        b.register("history", History);
+        b.register("fc", Fc);
+        b
+    }
+}
+
+impl Builtins {
+    /// A registry with nothing in it.
+    pub fn empty() -> Self {
+        Builtins {
+            map: HashMap::new(),
+        }
+    }
+
+    /// Adds a builtin. Gives back the one it replaced if there was one with
+    /// the same name.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        builtin: impl ShellBuiltin + 'static,
+    ) -> Option<Rc<dyn ShellBuiltin>> {
+        self.map.insert(name.into(), Rc::new(builtin))
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Rc<dyn ShellBuiltin>> {
+        self.map.remove(name)
+    }
+
+    /// Gets a builtin by name. It is shared so the shell's state can still
+    /// be changed while it runs.
+    pub fn get(&self, name: &str) -> Option<Rc<dyn ShellBuiltin>> {
+        self.map.get(name).cloned()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.map.contains_key(name)
+    }
+
+    /// The names of every builtin in order.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.map.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+impl fmt::Debug for Builtins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+/// The standard streams of a builtin after its pipes and redirections have
+/// been done. Builtins use these rather than the shell's own streams.
+#[derive(Debug)]
+pub struct Io {
+    pub stdin: Stream,
+    pub stdout: Stream,
+    pub stderr: Stream,
+}
+
+impl Io {
+    /// Copies of these streams for the commands a builtin runs itself, like
+    /// the ones run by `eval`. A command can't be started with a closed
+    /// stream so those are given `/dev/null`.
+    pub(crate) fn streams(&self) -> io::Result<Streams> {
+        let fd = |s: &Stream| -> io::Result<Fd> {
+            let file = match &s.0 {
+                Some(f) => f.try_clone()?,
+                None => File::options().read(true).write(true).open("/dev/null")?,
+            };
+            Ok(Fd::Piped(file.into()))
+        };
+        Ok(Streams {
+            stdin: fd(&self.stdin)?,
+            stdout: fd(&self.stdout)?,
+            stderr: fd(&self.stderr)?,
+        })
+    }
+}
+
+/// One of the streams in [`Io`]. It is `None` when the fd was closed with
+/// something like `>&-`.
+#[derive(Debug)]
+pub struct Stream(pub Option<File>);
+
+impl Stream {
+    /// The fd to read or write, or -1 when closed so using it fails.
+    pub fn fd(&self) -> RawFd {
+        self.0.as_ref().map_or(-1, |f| f.as_raw_fd())
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            Some(f) => f.write(buf),
+            None => Err(io::Error::from_raw_os_error(libc::EBADF)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.0 {
+            Some(f) => f.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `exit [n]`
+///
+/// Exits the shell with the status `n`. Without a status the shell exits
+/// with the status of the last command.
+pub struct Exit;
+impl ShellBuiltin for Exit {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        if state.interactive {
+            let _ = writeln!(io.stderr, "exit");
+        }
+
+        let code = match args {
+            [] => state.prev(),
+            [n] => match n.parse::<i64>() {
+                Ok(n) => n as i32,
+                Err(_) => {
+                    let _ = writeln!(io.stderr, "rush: exit: {}: numeric argument required", n);
+                    2
+                }
+            },
+            _ => {
+                let _ = writeln!(io.stderr, "rush: exit: too many arguments");
+                return 1;
+            }
+        };
+
+        state.exit = true;
+        // only the low byte of a status can be seen by the parent
+        code & 0xff
+    }
+
+    fn help(&self) -> &str {
+        "exit [n]\n\
+         Exit the shell with the status n or the status of the last command."
+    }
+}
+
+/// `exec [cmd [args...]]`
+///
+/// Replaces the shell with the given command. The redirections of the command
+/// are done before this is called. Without a command they are all it does.
+pub struct Exec;
+impl ShellBuiltin for Exec {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let _ = state;
+        let Some((cmd, args)) = args.split_first() else {
+            return 0;
+        };
+
+        log::info!("replacing shell with: [{}, {:?}]", cmd, args);
+
+        // this only returns when the exec failed
+        let err = std::process::Command::new(cmd).args(args).exec();
+        let _ = writeln!(io.stderr, "rush: exec: {}: {}", cmd, error_message(&err));
+        match err.kind() {
+            std::io::ErrorKind::NotFound => 127,
+            _ => 126,
+        }
+    }
+
+    fn help(&self) -> &str {
+        "exec [command [args ...]]\n\
+         Replace the shell with a command. Without one its redirections are kept\n\
+         for the rest of the shell."
+    }
+
+    fn keeps_redirects(&self) -> bool {
+        true
+    }
+}
+
+/// `. file [args...]` and `source file [args...]`
+///
+/// Reads the file and runs it in this shell, then runs the `RETURN` trap.
+/// Any args given replace the positional parameters while it runs. The
+/// status is that of the last command in the file.
+pub struct Source;
+impl ShellBuiltin for Source {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let Some((file, args)) = args.split_first() else {
+            let _ = writeln!(io.stderr, "rush: .: filename argument required");
+            return 2;
+        };
+
+        let Some(path) = find_sourced(file, state) else {
+            let _ = writeln!(io.stderr, "rush: .: {}: not found", file);
+            return 1;
+        };
+
+        let data = match std::fs::read_to_string(&path) {
+            Ok(d) => d,
+            Err(e) => {
+                let _ = writeln!(
+                    io.stderr,
+                    "rush: .: {}: {}",
+                    path.display(),
+                    error_message(&e)
+                );
+                return 1;
+            }
+        };
+
+        log::info!("sourcing file: {:?}", path);
+
+        let args = (!args.is_empty()).then(|| args.to_vec());
+        let code = state.run_code(data, args, Some(io));
+        state.run_trap(Condition::Return);
+        code
+    }
+
+    fn help(&self) -> &str {
+        "source file [args ...]\n\
+         Run the commands in a file in this shell. Any args are the positional\n\
+         parameters while it runs. The same as `.`."
+    }
+}
+
+/// Finds the file named by a `.` command. Names with a slash in them are used
+/// as is, otherwise `PATH` is searched and then the current directory. The
+/// `sourcepath` option can turn off searching `PATH`.
+fn find_sourced(file: &str, state: &ShellState) -> Option<PathBuf> {
+    if file.contains('/') {
+        return Some(PathBuf::from(file));
+    }
+
+    let path = if state.option(Opt::Sourcepath) {
+        state.get_env_exact("PATH").unwrap_or_default()
+    } else {
+        String::new()
+    };
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(file))
+        .chain(std::iter::once(PathBuf::from(file)))
+        .find(|p| p.is_file())
+}
+
+/// `eval [args...]`
+///
+/// Joins its arguments with spaces and runs the result in this shell.
+pub struct Eval;
+impl ShellBuiltin for Eval {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let code = args.join(" ");
+        if code.trim().is_empty() {
+            return 0;
+        }
+
+        state.run_code(code, None, Some(io))
+    }
+
+    fn help(&self) -> &str {
+        "eval [args ...]\n\
+         Join the args with spaces and run the result in this shell."
+    }
+}
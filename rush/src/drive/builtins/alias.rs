@@ -0,0 +1,112 @@
+//! `alias` and `unalias`.
+
+use super::{Io, ShellBuiltin};
+use crate::shell::ShellState;
+
+use std::io::Write;
+
+/// `alias [-p] [name[=value] ...]`
+///
+/// Sets aliases, which replace the first word of a command. Names without
+/// a value are shown and with no names every alias is.
+pub struct Alias;
+impl ShellBuiltin for Alias {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let args = match args.first() {
+            Some(f) if f == "-p" || f == "--" => &args[1..],
+            _ => args,
+        };
+
+        if args.is_empty() {
+            for (name, value) in state.aliases() {
+                let _ = writeln!(io.stdout, "alias {}={}", name, quote(value));
+            }
+            return 0;
+        }
+
+        let mut code = 0;
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, value)) if valid_name(name) => state.set_alias(name, value),
+                Some((name, _)) => {
+                    let _ = writeln!(io.stderr, "rush: alias: `{}': invalid alias name", name);
+                    code = 1;
+                }
+                None => match state.alias(arg) {
+                    Some(value) => {
+                        let _ = writeln!(io.stdout, "alias {}={}", arg, quote(value));
+                    }
+                    None => {
+                        let _ = writeln!(io.stderr, "rush: alias: {}: not found", arg);
+                        code = 1;
+                    }
+                },
+            }
+        }
+        code
+    }
+
+    fn help(&self) -> &str {
+        "alias [-p] [name[=value] ...]\n\
+         Set or show aliases. An alias replaces the first word of a command."
+    }
+}
+
+/// `unalias [-a] name...`
+///
+/// Removes aliases. `-a` removes all of them.
+pub struct Unalias;
+impl ShellBuiltin for Unalias {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        match args.first().map(String::as_str) {
+            Some("-a") => {
+                state.clear_aliases();
+                return 0;
+            }
+            None => {
+                let _ = writeln!(io.stderr, "unalias: usage: unalias [-a] name [name ...]");
+                return 2;
+            }
+            _ => {}
+        }
+
+        let mut code = 0;
+        for name in args.iter().filter(|a| *a != "--") {
+            if state.remove_alias(name).is_none() {
+                let _ = writeln!(io.stderr, "rush: unalias: {}: not found", name);
+                code = 1;
+            }
+        }
+        code
+    }
+
+    fn help(&self) -> &str {
+        "unalias [-a] name [name ...]\n\
+         Remove aliases. `-a` removes every alias."
+    }
+}
+
+/// Alias names can't have anything in them the shell would split on.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || "/$`'\"\\|&;<>()=".contains(c))
+}
+
+/// Quotes a value so it can be read back by the shell.
+pub fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Quotes a value only if the shell would read it as something else.
+pub fn quote_word(s: &str) -> String {
+    let plain = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if plain {
+        s.to_owned()
+    } else {
+        quote(s)
+    }
+}
@@ -0,0 +1,493 @@
+//! `cd` and the directory stack builtins `pushd`, `popd` and `dirs`.
+
+use super::{Io, ShellBuiltin};
+use crate::shell::ShellState;
+use crate::util::error_message;
+
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+/// `cd [-L|-P] [dir]`
+///
+/// Changes the working directory. `-` goes to `OLDPWD` and relative paths are
+/// searched for in `CDPATH`. By default `..` is resolved against `PWD` (`-L`)
+/// rather than following symlinks (`-P`).
+pub struct Cd;
+impl ShellBuiltin for Cd {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let mut physical = false;
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next_if(|a| a.starts_with('-') && a.len() > 1) {
+            match arg.as_str() {
+                "--" => break,
+                "-L" => physical = false,
+                "-P" => physical = true,
+                _ => {
+                    let _ = writeln!(io.stderr, "rush: cd: {}: invalid option", arg);
+                    let _ = writeln!(io.stderr, "cd: usage: cd [-L|-P] [dir]");
+                    return 2;
+                }
+            }
+        }
+
+        let (dir, print) = match args.next().map(String::as_str) {
+            None => match state.get_env_exact("HOME") {
+                Some(home) => (home, false),
+                None => {
+                    let _ = writeln!(io.stderr, "rush: cd: HOME not set");
+                    return 1;
+                }
+            },
+            Some("-") => match state.get_env_exact("OLDPWD") {
+                Some(old) => (old, true),
+                None => {
+                    let _ = writeln!(io.stderr, "rush: cd: OLDPWD not set");
+                    return 1;
+                }
+            },
+            Some(dir) => (dir.to_owned(), false),
+        };
+
+        if args.next().is_some() {
+            let _ = writeln!(io.stderr, "rush: cd: too many arguments");
+            return 1;
+        }
+
+        let (path, found) = search_cdpath(&dir, state);
+        match change_dir(&path, physical, state) {
+            Ok(pwd) => {
+                if print || found {
+                    let _ = writeln!(io.stdout, "{}", pwd);
+                }
+                0
+            }
+            Err(e) => {
+                let _ = writeln!(io.stderr, "rush: cd: {}: {}", dir, error_message(&e));
+                1
+            }
+        }
+    }
+
+    fn help(&self) -> &str {
+        "cd [-L|-P] [dir]\n\
+         Change the working directory. `-` is OLDPWD and relative names are\n\
+         looked for in CDPATH."
+    }
+}
+
+/// `pushd [-n] [+N | -N | dir]`
+///
+/// Adds a directory to the top of the stack and changes to it. Without
+/// arguments the top two directories are swapped.
+pub struct Pushd;
+impl ShellBuiltin for Pushd {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let (no_cd, arg) = match stack_args("pushd", args, io) {
+            Ok(a) => a,
+            Err(code) => return code,
+        };
+
+        let mut stack = full_stack(state);
+        match arg {
+            None => {
+                if stack.len() < 2 {
+                    let _ = writeln!(io.stderr, "rush: pushd: no other directory");
+                    return 1;
+                }
+                stack.swap(0, 1);
+            }
+            Some(StackArg::Index(n)) => match stack_index(n, stack.len()) {
+                Some(i) => stack.rotate_left(i),
+                None => {
+                    let _ = writeln!(
+                        io.stderr,
+                        "rush: pushd: {}: directory stack index out of range",
+                        n
+                    );
+                    return 1;
+                }
+            },
+            Some(StackArg::Dir(dir)) => {
+                if no_cd {
+                    stack.insert(1, dir);
+                } else {
+                    stack.insert(0, dir);
+                }
+            }
+        }
+
+        if !no_cd {
+            let (path, _) = search_cdpath(&stack[0], state);
+            match change_dir(&path, false, state) {
+                Ok(pwd) => stack[0] = pwd,
+                Err(e) => {
+                    let _ = writeln!(
+                        io.stderr,
+                        "rush: pushd: {}: {}",
+                        stack[0],
+                        error_message(&e)
+                    );
+                    return 1;
+                }
+            }
+        }
+
+        set_stack(state, stack);
+        print_stack(state, false, io);
+        0
+    }
+
+    fn help(&self) -> &str {
+        "pushd [-n] [+N | -N | dir]\n\
+         Add a directory to the top of the directory stack and change to it."
+    }
+}
+
+/// `popd [-n] [+N | -N]`
+///
+/// Removes the top directory from the stack and changes to the new top.
+pub struct Popd;
+impl ShellBuiltin for Popd {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let (no_cd, arg) = match stack_args("popd", args, io) {
+            Ok(a) => a,
+            Err(code) => return code,
+        };
+
+        let mut stack = full_stack(state);
+        if stack.len() < 2 {
+            let _ = writeln!(io.stderr, "rush: popd: directory stack empty");
+            return 1;
+        }
+
+        let index = match arg {
+            None => 0,
+            Some(StackArg::Index(n)) => match stack_index(n, stack.len()) {
+                Some(i) => i,
+                None => {
+                    let _ = writeln!(
+                        io.stderr,
+                        "rush: popd: {}: directory stack index out of range",
+                        n
+                    );
+                    return 1;
+                }
+            },
+            Some(StackArg::Dir(dir)) => {
+                let _ = writeln!(io.stderr, "rush: popd: {}: invalid argument", dir);
+                return 2;
+            }
+        };
+
+        // with `-n` the top is left alone so the next one is removed
+        let index = if no_cd && index == 0 { 1 } else { index };
+        stack.remove(index);
+
+        if index == 0 {
+            match change_dir(Path::new(&stack[0]), false, state) {
+                Ok(pwd) => stack[0] = pwd,
+                Err(e) => {
+                    let _ = writeln!(io.stderr, "rush: popd: {}: {}", stack[0], error_message(&e));
+                    return 1;
+                }
+            }
+        }
+
+        set_stack(state, stack);
+        print_stack(state, false, io);
+        0
+    }
+
+    fn help(&self) -> &str {
+        "popd [-n] [+N | -N]\n\
+         Remove a directory from the directory stack and change to the new top."
+    }
+}
+
+/// `dirs [-clpv] [+N | -N]`
+///
+/// Shows the directory stack. The current directory is always at the top.
+pub struct Dirs;
+impl ShellBuiltin for Dirs {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let mut long = false;
+        let mut lines = false;
+        let mut numbered = false;
+        let mut index = None;
+
+        for arg in args {
+            if let Some(n) = parse_index(arg) {
+                index = Some(n);
+                continue;
+            }
+            let Some(flags) = arg.strip_prefix('-') else {
+                let _ = writeln!(io.stderr, "rush: dirs: {}: invalid argument", arg);
+                return 2;
+            };
+            for c in flags.chars() {
+                match c {
+                    'c' => state.dirs.clear(),
+                    'l' => long = true,
+                    'p' => lines = true,
+                    'v' => numbered = true,
+                    c => {
+                        let _ = writeln!(io.stderr, "rush: dirs: -{}: invalid option", c);
+                        let _ = writeln!(io.stderr, "dirs: usage: dirs [-clpv] [+N] [-N]");
+                        return 2;
+                    }
+                }
+            }
+        }
+
+        let stack = full_stack(state);
+        let show = |dir: &str| {
+            if long {
+                dir.to_owned()
+            } else {
+                tilde(dir, state)
+            }
+        };
+
+        if let Some(n) = index {
+            return match stack_index(n, stack.len()) {
+                Some(i) => {
+                    let _ = writeln!(io.stdout, "{}", show(&stack[i]));
+                    0
+                }
+                None => {
+                    let _ = writeln!(
+                        io.stderr,
+                        "rush: dirs: {}: directory stack index out of range",
+                        n
+                    );
+                    1
+                }
+            };
+        }
+
+        if numbered {
+            for (i, dir) in stack.iter().enumerate() {
+                let _ = writeln!(io.stdout, "{:2}  {}", i, show(dir));
+            }
+        } else if lines {
+            for dir in stack.iter() {
+                let _ = writeln!(io.stdout, "{}", show(dir));
+            }
+        } else {
+            print_stack(state, long, io);
+        }
+        0
+    }
+
+    fn help(&self) -> &str {
+        "dirs [-clpv] [+N | -N]\n\
+         Show the directory stack."
+    }
+}
+
+/// Finds the directory `cd` should go to. Returns true as the second value
+/// when it was found through a non empty entry in `CDPATH`.
+fn search_cdpath(dir: &str, state: &ShellState) -> (PathBuf, bool) {
+    let relative = !(dir.starts_with('/')
+        || dir == "."
+        || dir == ".."
+        || dir.starts_with("./")
+        || dir.starts_with("../"));
+
+    if relative {
+        if let Some(cdpath) = state.get_env_exact("CDPATH") {
+            for entry in cdpath.split(':') {
+                let base = if entry.is_empty() { "." } else { entry };
+                let path = Path::new(base).join(dir);
+                if path.is_dir() {
+                    return (path, !entry.is_empty());
+                }
+            }
+        }
+    }
+
+    (PathBuf::from(dir), false)
+}
+
+/// Changes the working directory and updates `PWD` and `OLDPWD`. When not
+/// physical, `..` removes the last part of `PWD` instead of going to the
+/// parent of where a symlink points. Returns the new `PWD`.
+fn change_dir(path: &Path, physical: bool, state: &ShellState) -> std::io::Result<String> {
+    let old = state.pwd();
+
+    let logical = normalize(&Path::new(&old).join(path));
+    if physical {
+        std::env::set_current_dir(path)?;
+    } else {
+        std::env::set_current_dir(&logical)?;
+    }
+
+    let pwd = if physical {
+        std::env::current_dir()?
+    } else {
+        logical
+    };
+    let pwd = pwd.to_string_lossy().into_owned();
+
+    std::env::set_var("OLDPWD", old);
+    std::env::set_var("PWD", &pwd);
+    Ok(pwd)
+}
+
+/// Removes all of the `.` and `..` parts of a path without looking at the
+/// file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for part in path.components() {
+        match part {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            part => out.push(part),
+        }
+    }
+    out
+}
+
+/// Replaces the home directory at the start of a path with a `~`.
+fn tilde(dir: &str, state: &ShellState) -> String {
+    let home = state.home();
+    match dir.strip_prefix(home) {
+        Some(rest) if !home.is_empty() && (rest.is_empty() || rest.starts_with('/')) => {
+            format!("~{}", rest)
+        }
+        _ => dir.to_owned(),
+    }
+}
+
+/// The directory stack with the current directory at the top.
+fn full_stack(state: &ShellState) -> Vec<String> {
+    std::iter::once(state.pwd())
+        .chain(state.dirs.iter().rev().cloned())
+        .collect()
+}
+
+/// Saves everything under the top of the stack.
+fn set_stack(state: &mut ShellState, stack: Vec<String>) {
+    state.dirs = stack.into_iter().skip(1).rev().collect();
+}
+
+fn print_stack(state: &ShellState, long: bool, io: &mut Io) {
+    let stack = full_stack(state);
+    let dirs: Vec<_> = stack
+        .iter()
+        .map(|d| if long { d.clone() } else { tilde(d, state) })
+        .collect();
+    let _ = writeln!(io.stdout, "{}", dirs.join(" "));
+}
+
+enum StackArg {
+    /// `+N` counts from the top, `-N` from the bottom
+    Index(isize),
+    Dir(String),
+}
+
+/// Parses `+N` and `-N` into a positive or negative number where `-0` is
+/// the bottom of the stack.
+fn parse_index(arg: &str) -> Option<isize> {
+    if let Some(n) = arg.strip_prefix('+') {
+        n.parse::<isize>().ok().filter(|n| *n >= 0)
+    } else if let Some(n) = arg.strip_prefix('-') {
+        n.parse::<isize>().ok().filter(|n| *n >= 0).map(|n| -n - 1)
+    } else {
+        None
+    }
+}
+
+/// Turns an index from [`parse_index`] into a position in the stack.
+fn stack_index(n: isize, len: usize) -> Option<usize> {
+    let i = if n < 0 { len as isize + n } else { n };
+    (0..len as isize).contains(&i).then_some(i as usize)
+}
+
+/// Reads the `-n` flag and the one argument `pushd` and `popd` take.
+fn stack_args(name: &str, args: &[String], io: &mut Io) -> Result<(bool, Option<StackArg>), i32> {
+    let mut no_cd = false;
+    let mut arg = None;
+    for a in args {
+        if a == "-n" {
+            no_cd = true;
+        } else if let Some(n) = parse_index(a) {
+            arg = Some(StackArg::Index(n));
+        } else if a.starts_with('-') && a.len() > 1 {
+            let _ = writeln!(io.stderr, "rush: {}: {}: invalid option", name, a);
+            return Err(2);
+        } else if arg.is_some() {
+            let _ = writeln!(io.stderr, "rush: {}: too many arguments", name);
+            return Err(1);
+        } else {
+            arg = Some(StackArg::Dir(a.clone()));
+        }
+    }
+    Ok((no_cd, arg))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalized() {
+        let norm = |p| normalize(Path::new(p));
+        assert_eq!(norm("/a/b/../c/./d"), PathBuf::from("/a/c/d"));
+        assert_eq!(norm("//a//b/"), PathBuf::from("/a/b"));
+        assert_eq!(norm("/a/../../b"), PathBuf::from("/b"));
+        assert_eq!(norm("/./."), PathBuf::from("/"));
+    }
+
+    #[test]
+    fn indexes() {
+        assert_eq!(parse_index("+0"), Some(0));
+        assert_eq!(parse_index("+2"), Some(2));
+        assert_eq!(parse_index("-0"), Some(-1));
+        assert_eq!(parse_index("-2"), Some(-3));
+        assert_eq!(parse_index("+-1"), None);
+        assert_eq!(parse_index("2"), None);
+        assert_eq!(parse_index("-x"), None);
+
+        // a stack of 3 is `+0 +1 +2` from the top and `-2 -1 -0`
+        assert_eq!(stack_index(0, 3), Some(0));
+        assert_eq!(stack_index(2, 3), Some(2));
+        assert_eq!(stack_index(3, 3), None);
+        assert_eq!(stack_index(-1, 3), Some(2));
+        assert_eq!(stack_index(-3, 3), Some(0));
+        assert_eq!(stack_index(-4, 3), None);
+    }
+
+    #[test]
+    fn cdpath_order() {
+        let base = std::env::temp_dir().join(format!("rush-cdpath-{}", std::process::id()));
+        for dir in ["one/x", "one/src", "two/x", "two/y"] {
+            std::fs::create_dir_all(base.join(dir)).unwrap();
+        }
+        let base = base.to_string_lossy().into_owned();
+        let mut state = ShellState::default();
+
+        state.set_var("CDPATH", format!("{0}/none:{0}/one:{0}/two", base));
+        assert_eq!(
+            search_cdpath("x", &state),
+            (PathBuf::from(format!("{}/one/x", base)), true)
+        );
+        assert_eq!(
+            search_cdpath("y", &state),
+            (PathBuf::from(format!("{}/two/y", base)), true)
+        );
+        assert_eq!(search_cdpath("z", &state), (PathBuf::from("z"), false));
+        assert_eq!(search_cdpath("./x", &state), (PathBuf::from("./x"), false));
+
+        // an empty entry is the current directory, which is the crate
+        state.set_var("CDPATH", format!(":{}/one", base));
+        assert_eq!(
+            search_cdpath("src", &state),
+            (PathBuf::from("./src"), false)
+        );
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,525 @@
+//! Builtins for finding and running other commands: `builtin`, `command`,
+//! `type`, `hash` and `help`.
+
+use super::alias::quote;
+use super::{Io, ShellBuiltin, Stream};
+use crate::shell::{search_path, ShellState};
+use crate::task::Task;
+use crate::util::error_message;
+
+use std::io::{self, Write};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+/// The `PATH` used by `command -p` that finds all of the standard utilities.
+const DEFAULT_PATH: &str = "/usr/bin:/bin";
+
+/// `builtin name [args...]`
+///
+/// Runs a builtin even when there is a function or alias with the same name.
+pub struct Builtin;
+impl ShellBuiltin for Builtin {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let Some((name, args)) = args.split_first() else {
+            return 0;
+        };
+
+        match state.builtins().get(name) {
+            Some(builtin) => builtin.run(args, state, io),
+            None => {
+                let _ = writeln!(io.stderr, "rush: builtin: {}: not a shell builtin", name);
+                1
+            }
+        }
+    }
+
+    fn help(&self) -> &str {
+        "builtin [name [args ...]]\n\
+         Run a shell builtin, skipping any function or alias with its name."
+    }
+}
+
+/// `command [-pvV] name [args...]`
+///
+/// Runs a builtin or a program from `PATH`, skipping functions and aliases.
+/// With `-p` a default `PATH` is used that finds the standard utilities.
+/// `-v` and `-V` say what a name would run instead of running it.
+pub struct Command;
+impl ShellBuiltin for Command {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let mut default_path = false;
+        let mut describe = None;
+        let mut args = args;
+        while let Some((flag, rest)) = args.split_first() {
+            match flag.as_str() {
+                "--" => {
+                    args = rest;
+                    break;
+                }
+                f if f.starts_with('-') && f.len() > 1 => {
+                    for c in f[1..].chars() {
+                        match c {
+                            'p' => default_path = true,
+                            'v' | 'V' => describe = Some(c),
+                            c => {
+                                let _ =
+                                    writeln!(io.stderr, "rush: command: -{}: invalid option", c);
+                                let _ = writeln!(
+                                    io.stderr,
+                                    "command: usage: command [-pvV] name [args ...]"
+                                );
+                                return 2;
+                            }
+                        }
+                    }
+                }
+                _ => break,
+            }
+            args = rest;
+        }
+
+        if let Some(style) = describe {
+            let style = if style == 'v' {
+                Style::Short
+            } else {
+                Style::Long
+            };
+            let mut code = 0;
+            for name in args {
+                let found = resolve(name, state, default_path);
+                match found.first() {
+                    Some(r) => describe_one(name, r, style, io),
+                    None if style == Style::Long => {
+                        let _ = writeln!(io.stderr, "rush: command: {}: not found", name);
+                        code = 1;
+                    }
+                    None => code = 1,
+                }
+            }
+            return code;
+        }
+
+        let Some((name, args)) = args.split_first() else {
+            return 0;
+        };
+
+        if let Some(builtin) = state.builtins().get(name) {
+            return builtin.run(args, state, io);
+        }
+
+        // `-p` only changes where the program is looked for, not the `PATH`
+        // it runs with
+        let found = match default_path {
+            true => search_path(name, DEFAULT_PATH),
+            false => state.find_command(name),
+        };
+        let mut cmd = match found {
+            Some(path) => {
+                let mut c = std::process::Command::new(path);
+                c.arg0(name);
+                c
+            }
+            None if default_path && !name.contains('/') => {
+                let _ = writeln!(io.stderr, "rush: {}: command not found", name);
+                return 127;
+            }
+            // let spawning fail the normal way
+            None => std::process::Command::new(name),
+        };
+        cmd.args(args);
+
+        match spawn(&mut cmd, io) {
+            Ok(child) => match Task::System(child).wait() {
+                Ok(code) => code,
+                Err(e) => {
+                    let _ = writeln!(io.stderr, "rush: command: {}", e);
+                    1
+                }
+            },
+            Err(e) => {
+                let _ = writeln!(io.stderr, "rush: {}: {}", name, not_found(&e));
+                match e.kind() {
+                    io::ErrorKind::NotFound => 127,
+                    _ => 126,
+                }
+            }
+        }
+    }
+
+    fn help(&self) -> &str {
+        "command [-pvV] name [args ...]\n\
+         Run a builtin or a program, skipping any function or alias with its\n\
+         name. `-p` looks for programs in a default PATH. `-v` and `-V` show\n\
+         what the name would run."
+    }
+}
+
+/// `type [-aptP] name...`
+///
+/// Says what each name runs as a command. `-a` shows everything it could
+/// be, `-t` gives only the kind, `-p` gives only the path of a program and
+/// `-P` always searches `PATH`.
+pub struct Type;
+impl ShellBuiltin for Type {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let mut all = false;
+        let mut style = Style::Long;
+        let mut path_only = false;
+
+        let mut args = args;
+        while let Some((flag, rest)) = args.split_first() {
+            if flag == "--" {
+                args = rest;
+                break;
+            }
+            let Some(flags) = flag.strip_prefix('-').filter(|f| !f.is_empty()) else {
+                break;
+            };
+            for c in flags.chars() {
+                match c {
+                    'a' => all = true,
+                    // there are no functions to skip
+                    'f' => {}
+                    't' => style = Style::Kind,
+                    'p' => style = Style::Path,
+                    'P' => {
+                        style = Style::Path;
+                        path_only = true;
+                    }
+                    c => {
+                        let _ = writeln!(io.stderr, "rush: type: -{}: invalid option", c);
+                        let _ = writeln!(io.stderr, "type: usage: type [-afptP] name [name ...]");
+                        return 2;
+                    }
+                }
+            }
+            args = rest;
+        }
+
+        let mut code = 0;
+        for name in args {
+            let mut found = resolve(name, state, false);
+            if path_only {
+                found.retain(|r| matches!(r, Resolved::File(..)));
+            }
+            if !all {
+                found.truncate(1);
+            }
+
+            if found.is_empty() {
+                if style == Style::Long {
+                    let _ = writeln!(io.stderr, "rush: type: {}: not found", name);
+                }
+                code = 1;
+            }
+            for r in found.iter() {
+                describe_one(name, r, style, io);
+            }
+        }
+        code
+    }
+
+    fn help(&self) -> &str {
+        "type [-afptP] name [name ...]\n\
+         Show what each name runs as a command. It can be an alias, keyword,\n\
+         builtin or a program."
+    }
+}
+
+/// `hash [-lrt] [-p path] [-d] [name...]`
+///
+/// Shows or changes where commands were found in `PATH`. With names they are
+/// searched for now. `-r` forgets everything and `-d` forgets the names.
+pub struct Hash;
+impl ShellBuiltin for Hash {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let mut delete = false;
+        let mut list = false;
+        let mut show = false;
+        let mut path = None;
+        let mut flagged = false;
+
+        let mut args = args.iter();
+        let mut names = vec![];
+        while let Some(arg) = args.next() {
+            let Some(flags) = arg.strip_prefix('-').filter(|f| !f.is_empty()) else {
+                names.push(arg);
+                names.extend(args.by_ref());
+                break;
+            };
+            if flags == "-" {
+                names.extend(args.by_ref());
+                break;
+            }
+            flagged = true;
+            for c in flags.chars() {
+                match c {
+                    'd' => delete = true,
+                    'l' => list = true,
+                    'r' => state.forget_commands(),
+                    't' => show = true,
+                    'p' => match args.next() {
+                        Some(p) => path = Some(PathBuf::from(p)),
+                        None => {
+                            let _ =
+                                writeln!(io.stderr, "rush: hash: -p: option requires an argument");
+                            return 2;
+                        }
+                    },
+                    c => {
+                        let _ = writeln!(io.stderr, "rush: hash: -{}: invalid option", c);
+                        let _ = writeln!(
+                            io.stderr,
+                            "hash: usage: hash [-lr] [-p pathname] [-dt] [name ...]"
+                        );
+                        return 2;
+                    }
+                }
+            }
+        }
+
+        if names.is_empty() {
+            if path.is_some() || delete || show {
+                let _ = writeln!(io.stderr, "rush: hash: a name is needed");
+                return 2;
+            }
+            let all = state.all_hashed();
+            if list {
+                for (name, h) in all {
+                    let _ = writeln!(io.stdout, "builtin hash -p {} {}", h.path.display(), name);
+                }
+            } else if all.is_empty() {
+                // only a plain `hash` says anything about this
+                if !flagged {
+                    let _ = writeln!(io.stdout, "hash: hash table empty");
+                }
+            } else {
+                let _ = writeln!(io.stdout, "hits\tcommand");
+                for (_, h) in all {
+                    let _ = writeln!(io.stdout, "{:4}\t{}", h.hits, h.path.display());
+                }
+            }
+            return 0;
+        }
+
+        let mut code = 0;
+        for name in names.iter() {
+            if let Some(p) = &path {
+                state.set_hashed(name, p.clone());
+                continue;
+            }
+            if delete {
+                if !state.forget_command(name) {
+                    let _ = writeln!(io.stderr, "rush: hash: {}: not found", name);
+                    code = 1;
+                }
+                continue;
+            }
+            if show {
+                match state.hashed(name) {
+                    Some(h) if names.len() == 1 => {
+                        let _ = writeln!(io.stdout, "{}", h.path.display());
+                    }
+                    Some(h) => {
+                        let _ = writeln!(io.stdout, "{}\t{}", name, h.path.display());
+                    }
+                    None => {
+                        let _ = writeln!(io.stderr, "rush: hash: {}: not found", name);
+                        code = 1;
+                    }
+                }
+                continue;
+            }
+
+            // builtins are never looked for
+            if state.builtins().contains(name) {
+                continue;
+            }
+            let found = search_path(name, &state.get_env_exact("PATH").unwrap_or_default());
+            match found {
+                Some(p) => state.set_hashed(name, p),
+                None => {
+                    let _ = writeln!(io.stderr, "rush: hash: {}: not found", name);
+                    code = 1;
+                }
+            }
+        }
+        code
+    }
+
+    fn help(&self) -> &str {
+        "hash [-lr] [-p pathname] [-dt] [name ...]\n\
+         Remember or show where programs are. Without names every remembered\n\
+         program is listed."
+    }
+}
+
+/// `help [name...]`
+///
+/// Shows the usage of every builtin or what the named ones do.
+pub struct Help;
+impl ShellBuiltin for Help {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let builtins = state.builtins();
+
+        if args.is_empty() {
+            let _ = writeln!(
+                io.stdout,
+                "These commands are built into the shell. Use `help name` to learn more \
+                 about one."
+            );
+            let _ = writeln!(io.stdout);
+            let mut usages: Vec<String> = builtins
+                .names()
+                .into_iter()
+                .map(|name| {
+                    let help = builtins.get(name).unwrap();
+                    let usage = help.help().lines().next().unwrap_or(name);
+                    usage.to_owned()
+                })
+                .collect();
+            // some builtins have more than one name
+            usages.sort();
+            usages.dedup();
+            for usage in usages {
+                let _ = writeln!(io.stdout, " {}", usage);
+            }
+            return 0;
+        }
+
+        let mut code = 0;
+        for name in args {
+            match builtins.get(name) {
+                Some(b) => {
+                    let _ = writeln!(io.stdout, "{}: {}", name, b.help());
+                }
+                None => {
+                    let _ = writeln!(io.stderr, "rush: help: no help topics match `{}'", name);
+                    code = 1;
+                }
+            }
+        }
+        code
+    }
+
+    fn help(&self) -> &str {
+        "help [name ...]\n\
+         Show what builtins do. Without a name every builtin is listed."
+    }
+}
+
+/// Words that mean something to the shell at the start of a command. Only
+/// the ones the parser knows about are here.
+pub const KEYWORDS: &[&str] = &["!", "[[", "]]"];
+
+/// Something a command name can run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolved {
+    Alias(String),
+    Keyword,
+    Builtin,
+    /// A program and if its place was already remembered by `hash`
+    File(PathBuf, bool),
+}
+
+/// Finds everything a name can run in the order the shell looks for them.
+/// Every program in `PATH` with the name is included.
+pub fn resolve(name: &str, state: &mut ShellState, default_path: bool) -> Vec<Resolved> {
+    let mut found = vec![];
+    if let Some(alias) = state.alias(name) {
+        found.push(Resolved::Alias(alias.to_owned()));
+    }
+    if KEYWORDS.contains(&name) {
+        found.push(Resolved::Keyword);
+    }
+    if state.builtins().contains(name) {
+        found.push(Resolved::Builtin);
+    }
+
+    if name.contains('/') {
+        let file = PathBuf::from(name);
+        if file.is_file() {
+            found.push(Resolved::File(file, false));
+        }
+        return found;
+    }
+
+    let hashed = match default_path {
+        true => None,
+        false => state.hashed(name).map(|h| h.path.clone()),
+    };
+    if let Some(h) = &hashed {
+        found.push(Resolved::File(h.clone(), true));
+    }
+
+    let path = match default_path {
+        true => String::from(DEFAULT_PATH),
+        false => state.get_env_exact("PATH").unwrap_or_default(),
+    };
+    for dir in std::env::split_paths(&path) {
+        if let Some(file) = search_path(name, &dir.to_string_lossy()) {
+            if hashed.as_ref() != Some(&file) {
+                found.push(Resolved::File(file, false));
+            }
+        }
+    }
+    found
+}
+
+/// How [`describe_one`] shows what a name is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    /// Like `command -v`
+    Short,
+    /// Like `type` and `command -V`
+    Long,
+    /// Like `type -t`
+    Kind,
+    /// Like `type -p`, which only shows programs
+    Path,
+}
+
+fn describe_one(name: &str, r: &Resolved, style: Style, io: &mut Io) {
+    let line = match (style, r) {
+        (Style::Short, Resolved::Alias(a)) => format!("alias {}={}", name, quote(a)),
+        (Style::Short, Resolved::Keyword | Resolved::Builtin) => name.to_owned(),
+        (Style::Short | Style::Path, Resolved::File(p, _)) => p.display().to_string(),
+        (Style::Path, _) => return,
+        (Style::Long, Resolved::Alias(a)) => format!("{} is aliased to `{}'", name, a),
+        (Style::Long, Resolved::Keyword) => format!("{} is a shell keyword", name),
+        (Style::Long, Resolved::Builtin) => format!("{} is a shell builtin", name),
+        (Style::Long, Resolved::File(p, true)) => format!("{} is hashed ({})", name, p.display()),
+        (Style::Long, Resolved::File(p, false)) => format!("{} is {}", name, p.display()),
+        (Style::Kind, Resolved::Alias(_)) => String::from("alias"),
+        (Style::Kind, Resolved::Keyword) => String::from("keyword"),
+        (Style::Kind, Resolved::Builtin) => String::from("builtin"),
+        (Style::Kind, Resolved::File(..)) => String::from("file"),
+    };
+    let _ = writeln!(io.stdout, "{}", line);
+}
+
+/// Starts a program with the streams of a builtin.
+fn spawn(cmd: &mut std::process::Command, io: &Io) -> io::Result<std::process::Child> {
+    cmd.stdin(stdio(&io.stdin)?)
+        .stdout(stdio(&io.stdout)?)
+        .stderr(stdio(&io.stderr)?)
+        .spawn()
+}
+
+/// Gets a copy of a stream for a child process. A closed stream is given to
+/// the child as `/dev/null`.
+fn stdio(stream: &Stream) -> io::Result<Stdio> {
+    match &stream.0 {
+        Some(f) => Ok(Stdio::from(f.try_clone()?)),
+        None => Ok(Stdio::null()),
+    }
+}
+
+/// The message for a command that couldn't be started.
+fn not_found(e: &io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::NotFound => String::from("command not found"),
+        _ => error_message(e),
+    }
+}
@@ -0,0 +1,495 @@
+//! `complete` and `compgen`, which set how the arguments of commands are
+//! completed.
+
+use super::alias::{quote, quote_word};
+use super::{Io, ShellBuiltin};
+use crate::complete::{self, Completer, Flags, Request};
+use crate::shell::{ShellState, Var};
+
+use std::io::Write;
+use std::rc::Rc;
+
+/// `complete [-abcdefuv] [-pr] [-o option] [-A action] [-W wordlist]
+/// [-F function] [-C command] [name ...]`
+///
+/// Sets how the arguments of each name are completed. `-p` shows how they
+/// are and `-r` removes them. With no names these apply to every command.
+pub struct Complete;
+impl ShellBuiltin for Complete {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let (spec, names) = match Parsed::new(args, "complete", io) {
+            Ok(parsed) if parsed.remove => return remove(&parsed.names, state, io),
+            Ok(parsed) if parsed.print || (parsed.names.is_empty() && parsed.spec.is_empty()) => {
+                return print(&parsed.names, state, io);
+            }
+            Ok(parsed) if parsed.names.is_empty() => {
+                let _ = writeln!(io.stderr, "{}", COMPLETE_USAGE);
+                return 2;
+            }
+            Ok(parsed) => (Rc::new(parsed.spec), parsed.names),
+            Err(code) => return code,
+        };
+
+        for name in names {
+            state.completers_mut().set(name, spec.clone());
+        }
+        0
+    }
+
+    fn help(&self) -> &str {
+        "complete [-abcdefuv] [-pr] [-o option] [-A action] [-W wordlist] [-F function] [-C command] [name ...]\n\
+         Set how the arguments of each name are completed. -p shows how they are\n\
+         and -r forgets it. The options are bashdefault, default, dirnames,\n\
+         filenames, nospace and plusdirs. -F runs a command that sets COMPREPLY."
+    }
+}
+
+/// `compgen [-abcdefuv] [-o option] [-A action] [-W wordlist] [-F function]
+/// [-C command] [word]`
+///
+/// Prints what a word could be completed to, one on each line.
+pub struct Compgen;
+impl ShellBuiltin for Compgen {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let parsed = match Parsed::new(args, "compgen", io) {
+            Ok(parsed) if parsed.print || parsed.remove => {
+                let flag = if parsed.print { 'p' } else { 'r' };
+                return invalid_option("compgen", flag, io);
+            }
+            Ok(parsed) => parsed,
+            Err(code) => return code,
+        };
+
+        let word = parsed.names.first().cloned().unwrap_or_default();
+        let req = Request {
+            point: word.chars().count(),
+            line: word.clone(),
+            words: vec![word],
+            index: 0,
+        };
+        let found = parsed.spec.complete(&req, state);
+        for word in &found {
+            let _ = writeln!(io.stdout, "{}", word);
+        }
+        if found.is_empty() {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn help(&self) -> &str {
+        "compgen [-abcdefuv] [-o option] [-A action] [-W wordlist] [-F function] [-C command] [word]\n\
+         Print what word could be completed to with the options of complete."
+    }
+}
+
+const COMPLETE_USAGE: &str = "complete: usage: complete [-abcdefuv] [-pr] [-o option] \
+                              [-A action] [-W wordlist] [-F function] [-C command] [name ...]";
+const COMPGEN_USAGE: &str = "compgen: usage: compgen [-abcdefuv] [-o option] [-A action] \
+                             [-W wordlist] [-F function] [-C command] [word]";
+
+/// Something `complete` can list with a letter or `-A`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Alias,
+    Builtin,
+    Command,
+    Directory,
+    Export,
+    File,
+    User,
+    Variable,
+}
+
+impl Action {
+    const ALL: &'static [Action] = &[
+        Action::Alias,
+        Action::Builtin,
+        Action::Command,
+        Action::Directory,
+        Action::Export,
+        Action::File,
+        Action::User,
+        Action::Variable,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Action::Alias => "alias",
+            Action::Builtin => "builtin",
+            Action::Command => "command",
+            Action::Directory => "directory",
+            Action::Export => "export",
+            Action::File => "file",
+            Action::User => "user",
+            Action::Variable => "variable",
+        }
+    }
+
+    fn letter(self) -> char {
+        match self {
+            Action::Alias => 'a',
+            Action::Builtin => 'b',
+            Action::Command => 'c',
+            Action::Directory => 'd',
+            Action::Export => 'e',
+            Action::File => 'f',
+            Action::User => 'u',
+            Action::Variable => 'v',
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.iter().copied().find(|a| a.name() == name)
+    }
+
+    fn from_letter(c: char) -> Option<Action> {
+        Action::ALL.iter().copied().find(|a| a.letter() == c)
+    }
+
+    /// Everything of this sort that starts with the word.
+    fn candidates(self, word: &str, state: &ShellState) -> Vec<String> {
+        let starts = |names: Vec<&str>| -> Vec<String> {
+            let names = names.into_iter().filter(|n| n.starts_with(word));
+            names.map(str::to_owned).collect()
+        };
+        // the slash is put back by `-o filenames`
+        let trim = |paths: Vec<String>| -> Vec<String> {
+            let paths = paths.into_iter();
+            paths.map(|p| p.trim_end_matches('/').to_owned()).collect()
+        };
+
+        match self {
+            Action::Alias => starts(state.aliases().into_iter().map(|(a, _)| a).collect()),
+            Action::Builtin => starts(state.builtins().names()),
+            Action::Command => complete::commands(word, state),
+            Action::Directory => trim(complete::dirs(word)),
+            Action::Export => complete::env_names(word),
+            Action::File => trim(complete::paths(word)),
+            Action::User => complete::user_names(word),
+            Action::Variable => complete::var_names(word, state),
+        }
+    }
+}
+
+/// A completer made by `complete`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Spec {
+    actions: Vec<Action>,
+    /// `-W`, split on white space.
+    words: Option<String>,
+    /// `-F`, run with the command, the word and the word before it.
+    function: Option<String>,
+    /// `-C`, run in a new shell. Each line it prints is a candidate.
+    command: Option<String>,
+    flags: Flags,
+}
+
+impl Spec {
+    fn is_empty(&self) -> bool {
+        *self == Spec::default()
+    }
+
+    /// Runs `-F`. Like bash the function gets the words in `COMP_WORDS` and
+    /// gives back the candidates in `COMPREPLY`. There are no shell functions
+    /// yet so this is any command that can set it, like a builtin added by
+    /// a program that embeds the shell.
+    fn function(&self, name: &str, req: &Request, state: &mut ShellState) -> Vec<String> {
+        state.set_array("COMP_WORDS", req.words.clone());
+        state.set_var("COMP_CWORD", req.index.to_string());
+        state.set_var("COMP_LINE", req.line.as_str());
+        state.set_var("COMP_POINT", req.point.to_string());
+        state.set_array("COMPREPLY", Vec::new());
+
+        let code = format!(
+            "{} {} {} {}",
+            quote_word(name),
+            quote(req.command()),
+            quote(req.word()),
+            quote(req.prev())
+        );
+        // completing shouldn't change `$?`
+        let prev = state.prev();
+        state.run_code(code, None, None);
+        state.set_prev(prev);
+
+        match state.get_var("COMPREPLY") {
+            Some(Var::Array(reply)) => reply.clone(),
+            Some(Var::Scalar(reply)) => vec![reply.clone()],
+            None => Vec::new(),
+        }
+    }
+
+    /// Runs `-C` in a new shell so what it prints can be read.
+    fn command(&self, cmd: &str, req: &Request) -> Vec<String> {
+        let code = format!(
+            "{} {} {} {}",
+            cmd,
+            quote(req.command()),
+            quote(req.word()),
+            quote(req.prev())
+        );
+        let output = std::env::current_exe().and_then(|exe| {
+            std::process::Command::new(exe)
+                .arg("-c")
+                .arg(code)
+                .env("COMP_LINE", &req.line)
+                .env("COMP_POINT", req.point.to_string())
+                .stdin(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .output()
+        });
+        match output {
+            Ok(out) => String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .map(str::to_owned)
+                .collect(),
+            Err(e) => {
+                log::warn!("could not run completion command {}: {}", cmd, e);
+                Vec::new()
+            }
+        }
+    }
+}
+
+impl Completer for Spec {
+    fn complete(&self, req: &Request, state: &mut ShellState) -> Vec<String> {
+        let word = req.word();
+        let mut found = Vec::new();
+        for action in &self.actions {
+            found.extend(action.candidates(word, state));
+        }
+        if let Some(words) = &self.words {
+            let words = words.split_whitespace().filter(|w| w.starts_with(word));
+            found.extend(words.map(str::to_owned));
+        }
+        if let Some(name) = &self.function {
+            found.extend(self.function(name, req, state));
+        }
+        if let Some(cmd) = &self.command {
+            found.extend(self.command(cmd, req));
+        }
+        found
+    }
+
+    fn flags(&self) -> Flags {
+        let mut flags = self.flags;
+        // files are always completed like files
+        flags.filenames |= self
+            .actions
+            .iter()
+            .any(|a| matches!(a, Action::File | Action::Directory));
+        flags
+    }
+
+    fn spec(&self) -> Option<String> {
+        let mut opts = Vec::new();
+        for name in self.flags.names() {
+            opts.push(format!("-o {}", name));
+        }
+        for action in &self.actions {
+            opts.push(format!("-{}", action.letter()));
+        }
+        if let Some(words) = &self.words {
+            opts.push(format!("-W {}", quote(words)));
+        }
+        if let Some(name) = &self.function {
+            opts.push(format!("-F {}", quote_word(name)));
+        }
+        if let Some(cmd) = &self.command {
+            opts.push(format!("-C {}", quote(cmd)));
+        }
+        Some(opts.join(" "))
+    }
+}
+
+/// The arguments of `complete` and `compgen`.
+#[derive(Debug, Default)]
+struct Parsed {
+    spec: Spec,
+    print: bool,
+    remove: bool,
+    /// The names for `complete` or the word for `compgen`.
+    names: Vec<String>,
+}
+
+impl Parsed {
+    /// Reads the options. Errors are printed and give back the status to
+    /// exit with.
+    fn new(args: &[String], builtin: &str, io: &mut Io) -> Result<Parsed, i32> {
+        let mut parsed = Parsed::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--" => break,
+                a if a.starts_with('-') && a.len() > 1 => {}
+                _ => {
+                    parsed.names.push(arg.clone());
+                    break;
+                }
+            }
+
+            for c in arg[1..].chars() {
+                let spec = &mut parsed.spec;
+                match c {
+                    'p' => parsed.print = true,
+                    'r' => parsed.remove = true,
+                    'o' | 'A' | 'W' | 'F' | 'C' => {
+                        let Some(value) = args.next() else {
+                            let _ = writeln!(
+                                io.stderr,
+                                "rush: {}: -{}: option requires an argument",
+                                builtin, c
+                            );
+                            return Err(2);
+                        };
+                        let bad = match c {
+                            'o' => match spec.flags.get_mut(value) {
+                                Some(flag) => {
+                                    *flag = true;
+                                    None
+                                }
+                                None => Some("option"),
+                            },
+                            'A' => match Action::from_name(value) {
+                                Some(action) => {
+                                    spec.actions.push(action);
+                                    None
+                                }
+                                None => Some("action"),
+                            },
+                            'W' => {
+                                spec.words = Some(value.clone());
+                                None
+                            }
+                            'F' => {
+                                spec.function = Some(value.clone());
+                                None
+                            }
+                            _ => {
+                                spec.command = Some(value.clone());
+                                None
+                            }
+                        };
+                        if let Some(what) = bad {
+                            let _ = writeln!(
+                                io.stderr,
+                                "rush: {}: {}: invalid {} name",
+                                builtin, value, what
+                            );
+                            return Err(2);
+                        }
+                    }
+                    c => match Action::from_letter(c) {
+                        Some(action) => spec.actions.push(action),
+                        None => return Err(invalid_option(builtin, c, io)),
+                    },
+                }
+            }
+        }
+
+        parsed.names.extend(args.cloned());
+        Ok(parsed)
+    }
+}
+
+fn invalid_option(builtin: &str, c: char, io: &mut Io) -> i32 {
+    let _ = writeln!(io.stderr, "rush: {}: -{}: invalid option", builtin, c);
+    let usage = if builtin == "complete" {
+        COMPLETE_USAGE
+    } else {
+        COMPGEN_USAGE
+    };
+    let _ = writeln!(io.stderr, "{}", usage);
+    2
+}
+
+/// Shows the completers of the names, or of every command. Completers added
+/// from Rust can't be made with `complete` so they aren't shown.
+fn print(names: &[String], state: &ShellState, io: &mut Io) -> i32 {
+    let show = |io: &mut Io, name: &str, completer: &dyn Completer| {
+        if let Some(spec) = completer.spec() {
+            let _ = writeln!(io.stdout, "complete {} {}", spec, quote_word(name));
+        }
+    };
+
+    if names.is_empty() {
+        for (name, completer) in state.completers().iter() {
+            show(io, name, &**completer);
+        }
+        return 0;
+    }
+
+    let mut code = 0;
+    for name in names {
+        match state.completers().get(name) {
+            Some(completer) => show(io, name, &*completer),
+            None => code = missing(name, io),
+        }
+    }
+    code
+}
+
+fn remove(names: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+    if names.is_empty() {
+        state.completers_mut().clear();
+        return 0;
+    }
+
+    let mut code = 0;
+    for name in names {
+        if state.completers_mut().remove(name).is_none() {
+            code = missing(name, io);
+        }
+    }
+    code
+}
+
+fn missing(name: &str, io: &mut Io) -> i32 {
+    let _ = writeln!(
+        io.stderr,
+        "rush: complete: {}: no completion specification",
+        name
+    );
+    1
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::drive::builtins::Stream;
+
+    fn parse(args: &[&str]) -> Parsed {
+        let args: Vec<_> = args.iter().map(|a| a.to_string()).collect();
+        let mut io = Io {
+            stdin: Stream(None),
+            stdout: Stream(None),
+            stderr: Stream(None),
+        };
+        Parsed::new(&args, "complete", &mut io).unwrap()
+    }
+
+    #[test]
+    fn specs() {
+        let parsed = parse(&["-o", "nospace", "-dW", "start stop", "-A", "user", "svc"]);
+        assert_eq!(parsed.names, ["svc"]);
+        let spec = parsed.spec;
+        assert_eq!(spec.actions, [Action::Directory, Action::User]);
+        assert!(spec.flags().nospace && spec.flags().filenames);
+        assert_eq!(spec.spec().unwrap(), "-o nospace -d -u -W 'start stop'");
+
+        let parsed = parse(&["-p"]);
+        assert!(parsed.print && parsed.spec.is_empty());
+
+        let spec = parse(&["-W", "start stop status"]).spec;
+        let req = Request {
+            words: vec![String::from("svc"), String::from("st")],
+            index: 1,
+            line: String::from("svc st"),
+            point: 6,
+        };
+        let mut state = ShellState::default();
+        assert_eq!(spec.complete(&req, &mut state), ["start", "stop", "status"]);
+    }
+}
@@ -0,0 +1,495 @@
+//! `history` and `fc`, which show and rerun lines from the history.
+
+use super::alias::quote;
+use super::{Io, ShellBuiltin};
+use crate::shell::history::expand::expand;
+use crate::shell::history::Entry;
+use crate::shell::ShellState;
+use crate::util::{error_message, strftime};
+
+use std::io::Write;
+use std::path::PathBuf;
+
+/// `history [n]`, `history -c`, `history -d offset`, `history -arw [file]`,
+/// `history -p arg ...` or `history -s arg ...`
+///
+/// Shows the last n lines of the history, or all of them, with their
+/// numbers. The flags clear it, delete from it, read and write it, expand
+/// args like they were typed or add args to it.
+pub struct History;
+impl ShellBuiltin for History {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let (flag, rest) = match args.split_first() {
+            Some((f, rest)) if f == "--" => (None, rest),
+            Some((f, rest)) if f.starts_with('-') && f.len() > 1 => (Some(f.as_str()), rest),
+            _ => (None, args),
+        };
+
+        match (flag, rest) {
+            (None, []) => list(state, None, io),
+            (None, [n]) => match n.parse() {
+                Ok(n) => list(state, Some(n), io),
+                Err(_) => {
+                    let _ = writeln!(io.stderr, "rush: history: {}: numeric argument required", n);
+                    1
+                }
+            },
+            (Some("-c"), []) => {
+                state.history_mut().clear();
+                0
+            }
+            (Some("-d"), [offset]) => delete(offset, state, io),
+            // lines are added to the file as they run
+            (Some("-a"), [] | [_]) => 0,
+            (Some(f @ ("-r" | "-w")), [] | [_]) => {
+                let Some(path) = rest
+                    .first()
+                    .map(PathBuf::from)
+                    .or_else(|| state.history_file())
+                else {
+                    return 0;
+                };
+                let res = match f {
+                    "-r" => state.history_mut().read(&path),
+                    _ => state.history().write(&path),
+                };
+                match res {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        let _ = writeln!(
+                            io.stderr,
+                            "rush: history: {}: {}",
+                            path.display(),
+                            error_message(&e)
+                        );
+                        1
+                    }
+                }
+            }
+            (Some("-p"), args) => {
+                for arg in args {
+                    match expand(arg, state.history()) {
+                        Ok(expanded) => {
+                            let line = expanded.map_or_else(|| arg.clone(), |e| e.line);
+                            let _ = writeln!(io.stdout, "{}", line);
+                        }
+                        Err(e) => {
+                            let _ = writeln!(io.stderr, "rush: history: {}", e);
+                            return 1;
+                        }
+                    }
+                }
+                0
+            }
+            (Some("-s"), args) => {
+                // this takes the place of the `history -s` line
+                if state.interactive {
+                    remove_newest(state);
+                }
+                state.add_history(args.join(" "));
+                0
+            }
+            (None, _) | (Some("-c" | "-d" | "-a" | "-r" | "-w"), _) => {
+                let _ = writeln!(io.stderr, "{}", HISTORY_USAGE);
+                2
+            }
+            (Some(f), _) => {
+                let _ = writeln!(io.stderr, "rush: history: {}: invalid option", f);
+                let _ = writeln!(io.stderr, "{}", HISTORY_USAGE);
+                2
+            }
+        }
+    }
+
+    fn help(&self) -> &str {
+        "history [-c] [-d offset] [n] or history -arw [file] or history -ps arg [arg ...]\n\
+         Show the history with line numbers, or the last n lines. -c clears it and\n\
+         -d deletes a line or a range like 4-8. -r reads a file into it and -w\n\
+         writes it to one. -p expands the args and -s adds them as a line.\n\
+         HISTTIMEFORMAT is used to show when each line ran."
+    }
+}
+
+const HISTORY_USAGE: &str = "history: usage: history [-c] [-d offset] [n] or history -arw [file] \
+                             or history -ps arg [arg ...]";
+
+/// Shows the last `n` entries, or all of them.
+fn list(state: &ShellState, n: Option<usize>, io: &mut Io) -> i32 {
+    let entries = state.history().entries();
+    let from = n.map_or(0, |n| entries.len().saturating_sub(n));
+    let format = state.get_env_exact("HISTTIMEFORMAT");
+    for entry in &entries[from..] {
+        let time = match (&format, entry.time) {
+            (Some(format), Some(time)) => strftime(format, time),
+            _ => String::new(),
+        };
+        let _ = writeln!(io.stdout, "{:5}  {}{}", entry.number, time, entry.line);
+    }
+    0
+}
+
+/// Deletes an entry or a range of them. Negative offsets count back from
+/// the end where -1 is the newest.
+fn delete(offset: &str, state: &mut ShellState, io: &mut Io) -> i32 {
+    let entries = state.history().entries();
+    let number = |s: &str| -> Option<usize> {
+        match s.parse::<i64>().ok()? {
+            n if n < 0 => {
+                let i = entries.len().checked_sub(n.unsigned_abs() as usize)?;
+                Some(entries[i].number)
+            }
+            n => Some(n as usize),
+        }
+    };
+
+    // a leading `-` is a negative number, not a range
+    let range = match offset.get(1..).and_then(|o| o.split_once('-')) {
+        Some((a, b)) => {
+            let a = &offset[..a.len() + 1];
+            number(a).zip(number(b))
+        }
+        None => number(offset).map(|n| (n, n)),
+    };
+    let numbers: Vec<_> = match range {
+        Some((a, b)) if a <= b => entries
+            .iter()
+            .map(|e| e.number)
+            .filter(|n| (a..=b).contains(n))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    if numbers.is_empty() {
+        let _ = writeln!(
+            io.stderr,
+            "rush: history: {}: history position out of range",
+            offset
+        );
+        return 1;
+    }
+    for n in numbers {
+        state.history_mut().remove(n);
+    }
+    0
+}
+
+/// Removes the line that ran this builtin from the history. The shell adds
+/// typed lines before they run.
+fn remove_newest(state: &mut ShellState) {
+    if let Some(number) = state.history().entries().last().map(|e| e.number) {
+        state.history_mut().remove(number);
+    }
+}
+
+/// `fc [-e editor] [-lnr] [first [last]]` or `fc -s [old=new] [first]`
+///
+/// Lists lines from the history with `-l`. Otherwise the lines are opened
+/// in an editor and what it leaves is run, or with `-s` the line is run
+/// again after replacing old with new. first and last are numbers, offsets
+/// back from the end when negative, or the start of a line.
+pub struct Fc;
+impl ShellBuiltin for Fc {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let mut editor = None;
+        let mut list_mode = false;
+        let mut numbers = true;
+        let mut reverse = false;
+        let mut rerun = false;
+
+        let mut rest = args;
+        while let Some((arg, tail)) = rest.split_first() {
+            if arg == "--" {
+                rest = tail;
+                break;
+            }
+            // a negative number is an offset
+            if !arg.starts_with('-') || arg.len() == 1 || arg[1..].parse::<u64>().is_ok() {
+                break;
+            }
+            rest = tail;
+            for c in arg[1..].chars() {
+                match c {
+                    'e' => match rest.split_first() {
+                        Some((e, tail)) => {
+                            editor = Some(e.clone());
+                            rest = tail;
+                        }
+                        None => {
+                            let _ =
+                                writeln!(io.stderr, "rush: fc: -e: option requires an argument");
+                            let _ = writeln!(io.stderr, "{}", FC_USAGE);
+                            return 2;
+                        }
+                    },
+                    'l' => list_mode = true,
+                    'n' => numbers = false,
+                    'r' => reverse = true,
+                    's' => rerun = true,
+                    c => {
+                        let _ = writeln!(io.stderr, "rush: fc: -{}: invalid option", c);
+                        let _ = writeln!(io.stderr, "{}", FC_USAGE);
+                        return 2;
+                    }
+                }
+            }
+        }
+        let args = rest;
+
+        // a typed `fc` is already the newest line but isn't one to use
+        let own = state.interactive && !state.history().is_empty();
+        if own && !list_mode {
+            remove_newest(state);
+        }
+        let entries = state.history().entries();
+        let entries = if own && list_mode {
+            &entries[..entries.len() - 1]
+        } else {
+            entries
+        };
+
+        if rerun || editor.as_deref() == Some("-") {
+            let (sub, first) = match args {
+                [sub, rest @ ..] if sub.contains('=') => (sub.split_once('='), rest.first()),
+                [first, ..] => (None, Some(first)),
+                [] => (None, None),
+            };
+            let Some(i) = find(first.map_or("-1", String::as_str), entries) else {
+                let _ = writeln!(io.stderr, "rush: fc: no command found");
+                return 1;
+            };
+            let mut line = entries[i].line.clone();
+            if let Some((old, new)) = sub.filter(|(old, _)| !old.is_empty()) {
+                line = line.replacen(old, new, 1);
+            }
+            return run_lines(line, state, io);
+        }
+
+        let (first, last) = match (args.first(), args.get(1)) {
+            (Some(f), Some(l)) => (f.as_str(), l.as_str()),
+            (Some(f), None) if list_mode => (f.as_str(), "-1"),
+            (Some(f), None) => (f.as_str(), f.as_str()),
+            (None, _) if list_mode => ("-16", "-1"),
+            (None, _) => ("-1", "-1"),
+        };
+        let range = match (find(first, entries), find(last, entries)) {
+            // a list from further back than the history starts at the start
+            (None, Some(l)) if list_mode && first.starts_with('-') => Some((0, l)),
+            (Some(f), Some(l)) => Some((f, l)),
+            _ => None,
+        };
+        let Some((first, last)) = range else {
+            let _ = writeln!(io.stderr, "rush: fc: history specification out of range");
+            return 1;
+        };
+
+        let mut lines: Vec<&Entry> = entries[first.min(last)..=first.max(last)].iter().collect();
+        if (first > last) != reverse {
+            lines.reverse();
+        }
+
+        if list_mode {
+            for entry in lines {
+                let _ = if numbers {
+                    writeln!(io.stdout, "{}\t {}", entry.number, entry.line)
+                } else {
+                    writeln!(io.stdout, "\t {}", entry.line)
+                };
+            }
+            return 0;
+        }
+
+        let text: Vec<_> = lines.iter().map(|e| e.line.as_str()).collect();
+        let text = text.join("\n") + "\n";
+        let editor = editor
+            .or_else(|| state.get_env_exact("FCEDIT"))
+            .or_else(|| state.get_env_exact("EDITOR"))
+            .unwrap_or_else(|| String::from("vi"));
+        match edit(&text, &editor, state) {
+            Ok(Some(text)) => run_lines(text, state, io),
+            Ok(None) => 1,
+            Err(e) => {
+                let _ = writeln!(io.stderr, "rush: fc: {}", error_message(&e));
+                1
+            }
+        }
+    }
+
+    fn help(&self) -> &str {
+        "fc [-e editor] [-lnr] [first [last]] or fc -s [old=new] [first]\n\
+         List lines from the history with -l, or edit them with FCEDIT and run the\n\
+         result. -s runs a line again after replacing old with new. first and last\n\
+         are numbers, negative offsets or the start of a line."
+    }
+}
+
+const FC_USAGE: &str = "fc: usage: fc [-e editor] [-lnr] [first [last]] or fc -s [old=new] [first]";
+
+/// Finds a line by number, by an offset back from the end or by how it
+/// starts. The index into `entries` is given back.
+fn find(spec: &str, entries: &[Entry]) -> Option<usize> {
+    match spec.parse::<i64>() {
+        Ok(n) if n < 0 => entries.len().checked_sub(n.unsigned_abs() as usize),
+        Ok(n) => entries.iter().position(|e| e.number as i64 == n),
+        Err(_) => entries.iter().rposition(|e| e.line.starts_with(spec)),
+    }
+}
+
+/// Opens some text in an editor and gives back what it was left as. Nothing
+/// is given back when the editor fails.
+fn edit(text: &str, editor: &str, state: &mut ShellState) -> std::io::Result<Option<String>> {
+    let path = std::env::temp_dir().join(format!("rush-fc-{}", std::process::id()));
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        file.write_all(text.as_bytes())?;
+    }
+
+    let prev = state.prev();
+    let code = state.run_code(
+        format!("{} {}", editor, quote(&path.to_string_lossy())),
+        None,
+        None,
+    );
+    state.set_prev(prev);
+
+    let edited = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+    match code {
+        0 => edited.map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Shows some lines, adds them to the history and runs them. The status is
+/// that of the last one.
+fn run_lines(text: String, state: &mut ShellState, io: &mut Io) -> i32 {
+    if text.trim().is_empty() {
+        return 0;
+    }
+    let _ = write!(io.stdout, "{}", text);
+    if !text.ends_with('\n') {
+        let _ = writeln!(io.stdout);
+    }
+    let _ = io.stdout.flush();
+    state.add_history(text.trim_end());
+    state.run_code(text, None, Some(io))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::drive::builtins::Stream;
+    use crate::shell::history::Filter;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A shell with some lines in its history that doesn't write them to a
+    /// file.
+    fn state(lines: &[&str]) -> ShellState {
+        let mut state = ShellState::default();
+        state.set_var("HISTFILE", "");
+        for line in lines {
+            state.history_mut().add(line, &Filter::default(), None);
+        }
+        state
+    }
+
+    /// Runs a builtin and gives back its status and what it printed.
+    fn run(builtin: impl ShellBuiltin, args: &[&str], state: &mut ShellState) -> (i32, String) {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNT.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("rush-hist-{}-{}", std::process::id(), n));
+        let out = std::fs::File::create(&path).unwrap();
+
+        let args: Vec<_> = args.iter().map(|a| a.to_string()).collect();
+        let mut io = Io {
+            stdin: Stream(None),
+            stdout: Stream(Some(out)),
+            stderr: Stream(None),
+        };
+        let code = builtin.run(&args, state, &mut io);
+        drop(io);
+
+        let out = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (code, out)
+    }
+
+    fn lines(state: &ShellState) -> Vec<&str> {
+        let entries = state.history().entries();
+        entries.iter().map(|e| e.line.as_str()).collect()
+    }
+
+    #[test]
+    fn delete_offsets() {
+        let all = ["a", "b", "c", "d", "e"];
+
+        let mut s = state(&all);
+        assert_eq!(run(History, &["-d", "2"], &mut s).0, 0);
+        assert_eq!(lines(&s), ["a", "c", "d", "e"]);
+        // numbers stay with their lines
+        assert_eq!(run(History, &["-d", "3-4"], &mut s).0, 0);
+        assert_eq!(lines(&s), ["a", "e"]);
+
+        let mut s = state(&all);
+        assert_eq!(run(History, &["-d", "-1"], &mut s).0, 0);
+        assert_eq!(run(History, &["-d", "-3--2"], &mut s).0, 0);
+        assert_eq!(lines(&s), ["a", "d"]);
+
+        let mut s = state(&all);
+        for bad in ["0", "9", "-9", "4-2", "x", "2-x"] {
+            assert_eq!(run(History, &["-d", bad], &mut s).0, 1, "{}", bad);
+        }
+        assert_eq!(lines(&s), all);
+    }
+
+    #[test]
+    fn fc_list() {
+        let mut s = state(&["a", "b", "c", "d", "e"]);
+        assert_eq!(
+            run(Fc, &["-l", "2", "4"], &mut s),
+            (0, "2\t b\n3\t c\n4\t d\n".into())
+        );
+        assert_eq!(run(Fc, &["-l", "-2"], &mut s), (0, "4\t d\n5\t e\n".into()));
+        assert_eq!(
+            run(Fc, &["-ln", "3", "2"], &mut s),
+            (0, "\t c\n\t b\n".into())
+        );
+        assert_eq!(
+            run(Fc, &["-lr", "c", "d"], &mut s),
+            (0, "4\t d\n3\t c\n".into())
+        );
+        // a list from before the start begins at the first line
+        assert_eq!(run(Fc, &["-l", "-20", "1"], &mut s), (0, "1\t a\n".into()));
+        assert_eq!(run(Fc, &["-l", "9"], &mut s).0, 1);
+        assert_eq!(run(Fc, &["-l", "x"], &mut s).0, 1);
+    }
+
+    #[test]
+    fn fc_rerun() {
+        let mut s = state(&["echo one", "echo two", "false"]);
+        assert_eq!(
+            run(Fc, &["-s", "echo"], &mut s),
+            (0, "echo two\ntwo\n".into())
+        );
+        assert_eq!(
+            run(Fc, &["-s", "two=three", "2"], &mut s),
+            (0, "echo three\nthree\n".into())
+        );
+        assert_eq!(run(Fc, &["-s", "3"], &mut s), (1, "false\n".into()));
+        assert_eq!(run(Fc, &["-s", "9"], &mut s).0, 1);
+
+        // a typed `fc` is replaced by the line it runs
+        let mut s = state(&["echo one", "fc -s one=two"]);
+        s.interactive = true;
+        assert_eq!(
+            run(Fc, &["-s", "one=two"], &mut s),
+            (0, "echo two\ntwo\n".into())
+        );
+        assert_eq!(lines(&s), ["echo one", "echo two"]);
+    }
+}
@@ -0,0 +1,526 @@
+//! `echo` and `printf`, which write their arguments to stdout.
+
+use super::{Io, ShellBuiltin};
+use crate::shell::ShellState;
+use crate::util::error_message;
+
+use std::ffi::CString;
+use std::io::Write;
+
+/// `echo [-neE] [args...]`
+///
+/// Writes the arguments separated by spaces and followed by a newline. `-n`
+/// leaves off the newline and `-e` turns on backslash escapes.
+pub struct Echo;
+impl ShellBuiltin for Echo {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let _ = state;
+        flush("echo", io, &echo(args))
+    }
+
+    fn help(&self) -> &str {
+        "echo [-neE] [args ...]\n\
+         Write the args separated by spaces. `-n` leaves off the newline and `-e`\n\
+         expands backslash escapes."
+    }
+}
+
+fn echo(args: &[String]) -> Vec<u8> {
+    let mut newline = true;
+    let mut escapes = false;
+
+    let mut args = args;
+    // anything that isn't only these flags is printed instead
+    while let Some((flags, rest)) = args.split_first() {
+        let Some(f) = flags.strip_prefix('-') else {
+            break;
+        };
+        if f.is_empty() || !f.chars().all(|c| matches!(c, 'n' | 'e' | 'E')) {
+            break;
+        }
+        for c in f.chars() {
+            match c {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+        args = rest;
+    }
+
+    let mut buf = Vec::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            buf.push(b' ');
+        }
+        if !escapes {
+            buf.extend_from_slice(arg.as_bytes());
+        } else if unescape(arg, true, &mut buf) {
+            // `\c` stops all output
+            return buf;
+        }
+    }
+    if newline {
+        buf.push(b'\n');
+    }
+    buf
+}
+
+/// `printf format [args...]`
+///
+/// Writes the arguments as given by the format. The format is used again
+/// while there are arguments left.
+pub struct Printf;
+impl ShellBuiltin for Printf {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let _ = state;
+        let (out, code) = printf(args, &mut io.stderr);
+        match flush("printf", io, &out) {
+            0 => code,
+            err => err,
+        }
+    }
+
+    fn help(&self) -> &str {
+        "printf format [args ...]\n\
+         Write the args as given by the format."
+    }
+}
+
+fn printf(args: &[String], stderr: &mut dyn Write) -> (Vec<u8>, i32) {
+    let args = match args.split_first() {
+        Some((first, rest)) if first == "--" => rest,
+        _ => args,
+    };
+    let Some((format, args)) = args.split_first() else {
+        let _ = writeln!(stderr, "printf: usage: printf format [arguments]");
+        return (vec![], 2);
+    };
+
+    let mut printer = Printer {
+        args,
+        pos: 0,
+        buf: Vec::new(),
+        stderr,
+        failed: false,
+    };
+
+    loop {
+        let start = printer.pos;
+        match printer.format(format) {
+            Ok(true) => {}
+            // `\c` stops all output
+            Ok(false) => break,
+            Err(e) => {
+                let _ = writeln!(printer.stderr, "rush: printf: {}", e);
+                return (printer.buf, 1);
+            }
+        }
+        // a format with no conversions would loop forever
+        if printer.pos >= args.len() || printer.pos == start {
+            break;
+        }
+    }
+
+    (printer.buf, printer.failed as i32)
+}
+
+fn flush(name: &str, io: &mut Io, buf: &[u8]) -> i32 {
+    match io.stdout.write_all(buf).and_then(|_| io.stdout.flush()) {
+        Ok(()) => 0,
+        Err(e) => {
+            let _ = writeln!(
+                io.stderr,
+                "rush: {}: write error: {}",
+                name,
+                error_message(&e)
+            );
+            1
+        }
+    }
+}
+
+/// Expands backslash escapes into the buffer. `echo` and `%b` take octal as
+/// `\0NNN` while printf formats take `\NNN`. Returns true if a `\c` was found
+/// and output should stop.
+fn unescape(s: &str, zero_octal: bool, buf: &mut Vec<u8>) -> bool {
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut b = [0; 4];
+            buf.extend_from_slice(c.encode_utf8(&mut b).as_bytes());
+            continue;
+        }
+
+        let Some(e) = chars.next() else {
+            buf.push(b'\\');
+            break;
+        };
+        match e {
+            'a' => buf.push(0x07),
+            'b' => buf.push(0x08),
+            'c' => return true,
+            'e' | 'E' => buf.push(0x1b),
+            'f' => buf.push(0x0c),
+            'n' => buf.push(b'\n'),
+            'r' => buf.push(b'\r'),
+            't' => buf.push(b'\t'),
+            'v' => buf.push(0x0b),
+            '\\' => buf.push(b'\\'),
+            '"' if !zero_octal => buf.push(b'"'),
+            '\'' if !zero_octal => buf.push(b'\''),
+            '0'..='7' => {
+                // `\0` counts as the start of the number in `\0NNN`
+                let (mut n, max) = match (zero_octal, e) {
+                    (true, '0') => (0, 3),
+                    (true, _) => {
+                        buf.push(b'\\');
+                        buf.push(e as u8);
+                        continue;
+                    }
+                    (false, _) => (e.to_digit(8).unwrap(), 2),
+                };
+                for _ in 0..max {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(d) => {
+                            n = n * 8 + d;
+                            let _ = chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                buf.push(n as u8);
+            }
+            'x' => {
+                let mut n = 0;
+                let mut len = 0;
+                while len < 2 {
+                    match chars.peek().and_then(|c| c.to_digit(16)) {
+                        Some(d) => {
+                            n = n * 16 + d;
+                            len += 1;
+                            let _ = chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                if len == 0 {
+                    buf.extend_from_slice(b"\\x");
+                } else {
+                    buf.push(n as u8);
+                }
+            }
+            e => {
+                buf.push(b'\\');
+                let mut b = [0; 4];
+                buf.extend_from_slice(e.encode_utf8(&mut b).as_bytes());
+            }
+        }
+    }
+    false
+}
+
+/// Works through a printf format, taking arguments as they are needed.
+struct Printer<'a> {
+    args: &'a [String],
+    pos: usize,
+    buf: Vec<u8>,
+    stderr: &'a mut dyn Write,
+    /// An argument was not a valid number
+    failed: bool,
+}
+
+/// The `%[flags][width][.precision]` part of a conversion.
+#[derive(Debug, Default)]
+struct Spec {
+    flags: String,
+    width: Option<i64>,
+    precision: Option<i64>,
+}
+
+impl Printer<'_> {
+    fn next_arg(&mut self) -> Option<&str> {
+        let arg = self.args.get(self.pos).map(String::as_str);
+        self.pos += 1;
+        arg
+    }
+
+    /// Writes the format once. Returns false if a `\c` was found.
+    fn format(&mut self, format: &str) -> Result<bool, String> {
+        let mut chars = format.chars().peekable();
+        let mut lit = String::new();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    lit.push('\\');
+                    if let Some(c) = chars.next() {
+                        lit.push(c);
+                    }
+                    continue;
+                }
+                '%' if chars.peek() == Some(&'%') => {
+                    let _ = chars.next();
+                    lit.push('%');
+                    continue;
+                }
+                '%' => {}
+                c => {
+                    lit.push(c);
+                    continue;
+                }
+            }
+
+            if unescape(&lit, false, &mut self.buf) {
+                return Ok(false);
+            }
+            lit.clear();
+
+            let mut spec = Spec::default();
+            while let Some(&f) = chars.peek().filter(|c| "-+ #0".contains(**c)) {
+                spec.flags.push(f);
+                let _ = chars.next();
+            }
+            spec.width = self.number(&mut chars)?;
+            if chars.peek() == Some(&'.') {
+                let _ = chars.next();
+                // a negative precision from `*` is the same as none
+                spec.precision = Some(self.number(&mut chars)?.unwrap_or(0)).filter(|p| *p >= 0);
+            }
+            // length modifiers do nothing here
+            while chars
+                .peek()
+                .is_some_and(|c| matches!(c, 'h' | 'l' | 'L' | 'q' | 'j' | 'z' | 't'))
+            {
+                let _ = chars.next();
+            }
+
+            let Some(conv) = chars.next() else {
+                return Err(String::from("`%': missing format character"));
+            };
+            if !self.convert(conv, &spec)? {
+                return Ok(false);
+            }
+        }
+
+        Ok(!unescape(&lit, false, &mut self.buf))
+    }
+
+    /// Reads a width or precision, which can be `*` to take it from the args.
+    fn number(
+        &mut self,
+        chars: &mut std::iter::Peekable<std::str::Chars>,
+    ) -> Result<Option<i64>, String> {
+        if chars.peek() == Some(&'*') {
+            let _ = chars.next();
+            let arg = self.next_arg().unwrap_or_default().to_owned();
+            return Ok(Some(self.int(&arg)));
+        }
+
+        let mut digits = String::new();
+        while let Some(&d) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            digits.push(d);
+            let _ = chars.next();
+        }
+        match digits.is_empty() {
+            true => Ok(None),
+            false => digits
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("{}: invalid field width", digits)),
+        }
+    }
+
+    /// Writes one conversion. Returns false if a `%b` had a `\c` in it.
+    fn convert(&mut self, conv: char, spec: &Spec) -> Result<bool, String> {
+        let arg = self.next_arg().unwrap_or_default().to_owned();
+        match conv {
+            's' => self.pad(spec, arg.as_bytes()),
+            'b' => {
+                let mut expanded = Vec::new();
+                let stop = unescape(&arg, true, &mut expanded);
+                self.pad(spec, &expanded);
+                if stop {
+                    return Ok(false);
+                }
+            }
+            'c' => {
+                let c = arg.chars().next().map(String::from).unwrap_or_default();
+                let spec = Spec {
+                    flags: spec.flags.clone(),
+                    width: spec.width,
+                    precision: None,
+                };
+                self.pad(&spec, c.as_bytes());
+            }
+            'd' | 'i' => {
+                let n = self.int(&arg);
+                self.c_format(spec, "lld", n)
+            }
+            'o' | 'u' | 'x' | 'X' => {
+                let n = self.int(&arg);
+                self.c_format(spec, &format!("ll{}", conv), n)
+            }
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' | 'a' | 'A' => {
+                let n = self.float(&arg);
+                self.c_format(spec, &conv.to_string(), n)
+            }
+            c => return Err(format!("`{}': invalid format character", c)),
+        }
+        Ok(true)
+    }
+
+    /// Writes a string with the width and precision of the spec.
+    fn pad(&mut self, spec: &Spec, s: &[u8]) {
+        let s = match spec.precision {
+            Some(p) if (p as usize) < s.len() => &s[..p as usize],
+            _ => s,
+        };
+        let width = spec.width.unwrap_or(0);
+        let left = spec.flags.contains('-') || width < 0;
+        let fill = (width.unsigned_abs() as usize).saturating_sub(s.len());
+
+        if !left {
+            self.buf.extend(std::iter::repeat_n(b' ', fill));
+        }
+        self.buf.extend_from_slice(s);
+        if left {
+            self.buf.extend(std::iter::repeat_n(b' ', fill));
+        }
+    }
+
+    /// Formats a number with the C library so the output matches other
+    /// shells exactly.
+    fn c_format<N: CArg>(&mut self, spec: &Spec, conv: &str, n: N) {
+        let mut fmt = format!("%{}", spec.flags);
+        if let Some(w) = spec.width {
+            fmt.push_str(&w.to_string());
+        }
+        if let Some(p) = spec.precision {
+            fmt.push_str(&format!(".{}", p));
+        }
+        fmt.push_str(conv);
+
+        let fmt = CString::new(fmt).expect("format has no nul");
+        let len = unsafe { n.snprintf(std::ptr::null_mut(), 0, &fmt) };
+        if len < 0 {
+            return;
+        }
+        let mut out = vec![0u8; len as usize + 1];
+        unsafe { n.snprintf(out.as_mut_ptr() as *mut libc::c_char, out.len(), &fmt) };
+        out.pop();
+        self.buf.extend_from_slice(&out);
+    }
+
+    /// Parses a numeric argument. A leading quote gives the value of the next
+    /// character.
+    fn int(&mut self, arg: &str) -> i64 {
+        if let Some(c) = arg.strip_prefix(['\'', '"']) {
+            return c.chars().next().map_or(0, |c| c as i64);
+        }
+
+        let s = arg.trim_start();
+        let (neg, digits) = match s.strip_prefix('-') {
+            Some(d) => (true, d),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let res = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+            u64::from_str_radix(hex, 16)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            u64::from_str_radix(&digits[1..], 8)
+        } else if digits.is_empty() {
+            Ok(0)
+        } else {
+            digits.parse()
+        };
+
+        match res {
+            Ok(n) if neg => (n as i64).wrapping_neg(),
+            Ok(n) => n as i64,
+            Err(_) => {
+                let _ = writeln!(self.stderr, "rush: printf: {}: invalid number", arg);
+                self.failed = true;
+                0
+            }
+        }
+    }
+
+    fn float(&mut self, arg: &str) -> f64 {
+        if let Some(c) = arg.strip_prefix(['\'', '"']) {
+            return c.chars().next().map_or(0.0, |c| c as u32 as f64);
+        }
+        if arg.trim().is_empty() {
+            return 0.0;
+        }
+        match arg.trim().parse() {
+            Ok(n) => n,
+            Err(_) => {
+                let _ = writeln!(self.stderr, "rush: printf: {}: invalid number", arg);
+                self.failed = true;
+                0.0
+            }
+        }
+    }
+}
+
+/// A value that can be passed to `snprintf`.
+trait CArg: Copy {
+    /// # Safety
+    /// The format has to take exactly one value of this type.
+    unsafe fn snprintf(self, buf: *mut libc::c_char, len: usize, fmt: &CString) -> libc::c_int;
+}
+
+impl CArg for i64 {
+    unsafe fn snprintf(self, buf: *mut libc::c_char, len: usize, fmt: &CString) -> libc::c_int {
+        libc::snprintf(buf, len, fmt.as_ptr(), self as libc::c_longlong)
+    }
+}
+
+impl CArg for f64 {
+    unsafe fn snprintf(self, buf: *mut libc::c_char, len: usize, fmt: &CString) -> libc::c_int {
+        libc::snprintf(buf, len, fmt.as_ptr(), self as libc::c_double)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{echo, printf};
+
+    fn run(args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        let (out, _) = printf(&args, &mut std::io::sink());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn printf_conversions() {
+        assert_eq!(
+            run(&["%s-%d|%5.2f|%-4x|\\n", "a", "42", "3.14159", "255"]),
+            "a-42| 3.14|ff  |\n"
+        );
+        assert_eq!(
+            run(&["%05d %o %X %u %c", "-12", "8", "255", "7", "xyz"]),
+            "-0012 10 FF 7 x"
+        );
+        assert_eq!(run(&["%*s|%.*s", "4", "ab", "1", "cd"]), "  ab|c");
+        assert_eq!(run(&["%.*s|%.*d", "-1", "abc", "-2", "7"]), "abc|7");
+        assert_eq!(
+            run(&["%e %g %%", "1234.5", "0.0001"]),
+            "1.234500e+03 0.0001 %"
+        );
+        assert_eq!(run(&["%d", "'A"]), "65");
+    }
+
+    #[test]
+    fn printf_recycle() {
+        assert_eq!(run(&["%s=%s\n", "a", "1", "b"]), "a=1\nb=\n");
+        assert_eq!(run(&["x\\101\\c%s", "y"]), "xA");
+        assert_eq!(run(&["%b|", "a\\tb\\0101", "c\\cd"]), "a\tbA|c");
+    }
+
+    #[test]
+    fn echo_escapes() {
+        let args: Vec<String> = ["-ne", "a\\tb\\0101", "-n"].map(String::from).to_vec();
+        assert_eq!(echo(&args), b"a\tbA -n");
+    }
+}
@@ -0,0 +1,377 @@
+//! `read`, which reads a line of input into variables.
+
+use super::{Io, ShellBuiltin};
+use crate::shell::ShellState;
+use crate::util::error_message;
+
+use std::io::{self, Write};
+use std::os::fd::RawFd;
+use std::time::{Duration, Instant};
+
+/// `read [-r] [-a array] [-d delim] [-n count] [-p prompt] [-t timeout] [name...]`
+///
+/// Reads a line and splits it on `IFS` into the given names. The last name
+/// gets the rest of the line. Without names the line goes in `REPLY`.
+///
+/// Input is read one byte at a time so nothing past the delimiter is taken
+/// from a pipe that other commands might read after.
+pub struct Read;
+impl ShellBuiltin for Read {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let opts = match ReadOpts::parse(args) {
+            Ok(o) => o,
+            Err(e) => {
+                let _ = writeln!(io.stderr, "rush: read: {}", e);
+                let _ = writeln!(
+                    io.stderr,
+                    "read: usage: read [-r] [-a array] [-d delim] [-n count] [-p prompt] \
+                     [-t timeout] [name ...]"
+                );
+                return 2;
+            }
+        };
+
+        let fd = io.stdin.fd();
+        let tty = unsafe { libc::isatty(fd) == 1 };
+
+        if let Some(prompt) = &opts.prompt {
+            // like other shells the prompt is only shown to people
+            if tty {
+                let _ = write!(io.stderr, "{}", prompt);
+            }
+        }
+
+        // a terminal normally only gives input a line at a time
+        let _raw = (tty && (opts.count.is_some() || opts.delim != '\n')).then(|| RawInput::new(fd));
+
+        let (chars, end) = match read_input(fd, &opts) {
+            Ok(r) => r,
+            Err(e) => {
+                let _ = writeln!(io.stderr, "rush: read: {}", error_message(&e));
+                return 1;
+            }
+        };
+
+        let ifs = state
+            .get_env_exact("IFS")
+            .unwrap_or_else(|| String::from(" \t\n"));
+
+        if let Some(array) = &opts.array {
+            state.set_array(array, split(&chars, &ifs, None));
+        } else if opts.names.is_empty() {
+            state.set_var("REPLY", chars.iter().map(|(c, _)| c).collect::<String>());
+        } else {
+            let mut fields = split(&chars, &ifs, Some(opts.names.len())).into_iter();
+            for name in opts.names.iter() {
+                state.set_var(name, fields.next().unwrap_or_default());
+            }
+        }
+
+        match end {
+            End::Done => 0,
+            End::Eof => 1,
+            // the same as being killed by SIGALRM
+            End::Timeout => 128 + libc::SIGALRM,
+        }
+    }
+
+    fn help(&self) -> &str {
+        "read [-r] [-a array] [-d delim] [-n count] [-p prompt] [-t timeout] [name ...]\n\
+         Read a line from stdin and split it into the names using IFS."
+    }
+}
+
+#[derive(Debug)]
+struct ReadOpts {
+    raw: bool,
+    array: Option<String>,
+    delim: char,
+    count: Option<usize>,
+    prompt: Option<String>,
+    timeout: Option<Duration>,
+    names: Vec<String>,
+}
+
+impl ReadOpts {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut opts = ReadOpts {
+            raw: false,
+            array: None,
+            delim: '\n',
+            count: None,
+            prompt: None,
+            timeout: None,
+            names: Vec::new(),
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let Some(flags) = arg.strip_prefix('-').filter(|f| !f.is_empty()) else {
+                opts.names.push(arg.clone());
+                break;
+            };
+            if flags == "-" {
+                break;
+            }
+
+            let mut flags = flags.chars();
+            while let Some(flag) = flags.next() {
+                if flag == 'r' {
+                    opts.raw = true;
+                    continue;
+                }
+
+                // the value is either the rest of this arg or the next one
+                let rest: String = flags.by_ref().collect();
+                let value = if rest.is_empty() {
+                    args.next()
+                        .cloned()
+                        .ok_or_else(|| format!("-{}: option requires an argument", flag))?
+                } else {
+                    rest
+                };
+
+                match flag {
+                    'a' => opts.array = Some(value),
+                    // an empty delimiter means read until a NUL
+                    'd' => opts.delim = value.chars().next().unwrap_or('\0'),
+                    'n' => {
+                        let n = value
+                            .parse()
+                            .map_err(|_| format!("{}: invalid number", value))?;
+                        opts.count = Some(n);
+                    }
+                    'p' => opts.prompt = Some(value),
+                    't' => {
+                        let secs = value
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|t| *t >= 0.0 && t.is_finite())
+                            .ok_or_else(|| format!("{}: invalid timeout specification", value))?;
+                        opts.timeout = Some(Duration::from_secs_f64(secs));
+                    }
+                    c => return Err(format!("-{}: invalid option", c)),
+                }
+            }
+        }
+        opts.names.extend(args.cloned());
+        Ok(opts)
+    }
+}
+
+/// Why reading stopped.
+#[derive(Debug, PartialEq, Eq)]
+enum End {
+    /// The delimiter was found or enough characters were read
+    Done,
+    Eof,
+    Timeout,
+}
+
+/// Reads up to the delimiter. Each character is paired with if it was escaped
+/// by a backslash so it is not used for splitting.
+fn read_input(fd: RawFd, opts: &ReadOpts) -> io::Result<(Vec<(char, bool)>, End)> {
+    let deadline = opts.timeout.map(|t| Instant::now() + t);
+    let mut chars = vec![];
+    let mut escaped = false;
+
+    if opts.count == Some(0) {
+        return Ok((chars, End::Done));
+    }
+
+    loop {
+        let c = match read_char(fd, deadline)? {
+            Some(Some(c)) => c,
+            Some(None) => return Ok((chars, End::Eof)),
+            None => return Ok((chars, End::Timeout)),
+        };
+
+        if escaped {
+            escaped = false;
+            // a backslash before a newline continues the line
+            if c != '\n' {
+                chars.push((c, true));
+            }
+        } else if c == opts.delim {
+            return Ok((chars, End::Done));
+        } else if c == '\\' && !opts.raw {
+            escaped = true;
+            continue;
+        } else {
+            chars.push((c, false));
+        }
+
+        if opts.count.is_some_and(|n| chars.len() >= n) {
+            return Ok((chars, End::Done));
+        }
+    }
+}
+
+/// Reads a single utf-8 character without reading anything after it. Returns
+/// `Some(None)` at the end of input and `None` when the deadline has passed.
+fn read_char(fd: RawFd, deadline: Option<Instant>) -> io::Result<Option<Option<char>>> {
+    let Some(first) = read_byte(fd, deadline)? else {
+        return Ok(None);
+    };
+    let Some(first) = first else {
+        return Ok(Some(None));
+    };
+
+    let len = match first {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+
+    let mut buf = vec![first];
+    while buf.len() < len {
+        match read_byte(fd, deadline)? {
+            Some(Some(b)) => buf.push(b),
+            _ => break,
+        }
+    }
+
+    let c = std::str::from_utf8(&buf)
+        .ok()
+        .and_then(|s| s.chars().next())
+        .unwrap_or(char::REPLACEMENT_CHARACTER);
+    Ok(Some(Some(c)))
+}
+
+fn read_byte(fd: RawFd, deadline: Option<Instant>) -> io::Result<Option<Option<u8>>> {
+    if let Some(deadline) = deadline {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let mut poll = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ms = left.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(&mut poll, 1, ms) } {
+            0 => return Ok(None),
+            n if n < 0 => {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    return read_byte(fd, Some(deadline));
+                }
+                return Err(e);
+            }
+            _ => {}
+        }
+    }
+
+    let mut b = 0u8;
+    loop {
+        match unsafe { libc::read(fd, &mut b as *mut u8 as *mut libc::c_void, 1) } {
+            1 => return Ok(Some(Some(b))),
+            0 => return Ok(Some(None)),
+            _ => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// Splits input into fields on the characters in `ifs`. Whitespace in `ifs`
+/// is trimmed from the ends and any amount of it counts as one separator.
+/// When there is a max the last field is the rest of the input.
+fn split(chars: &[(char, bool)], ifs: &str, max: Option<usize>) -> Vec<String> {
+    let is_sep = |&(c, esc): &(char, bool)| !esc && ifs.contains(c);
+    let is_space = |p: &(char, bool)| is_sep(p) && p.0.is_whitespace();
+
+    let mut fields = vec![];
+    let mut i = 0;
+    while chars.get(i).is_some_and(is_space) {
+        i += 1;
+    }
+
+    while i < chars.len() {
+        if max.is_some_and(|m| fields.len() + 1 >= m) {
+            let mut end = chars.len();
+            while end > i && is_space(&chars[end - 1]) {
+                end -= 1;
+            }
+            fields.push(chars[i..end].iter().map(|(c, _)| c).collect());
+            break;
+        }
+
+        let start = i;
+        while i < chars.len() && !is_sep(&chars[i]) {
+            i += 1;
+        }
+        fields.push(chars[start..i].iter().map(|(c, _)| c).collect());
+
+        // any whitespace around at most one other separator
+        while chars.get(i).is_some_and(is_space) {
+            i += 1;
+        }
+        if chars.get(i).is_some_and(|p| is_sep(p) && !is_space(p)) {
+            i += 1;
+            while chars.get(i).is_some_and(is_space) {
+                i += 1;
+            }
+        }
+    }
+    fields
+}
+
+/// Puts a terminal in non canonical mode so characters can be read as they
+/// are typed. The old mode is restored when this is dropped.
+struct RawInput {
+    fd: RawFd,
+    old: libc::termios,
+}
+
+impl RawInput {
+    fn new(fd: RawFd) -> Option<Self> {
+        unsafe {
+            let mut old = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(fd, &mut old) != 0 {
+                return None;
+            }
+            let mut new = old;
+            new.c_lflag &= !libc::ICANON;
+            new.c_cc[libc::VMIN] = 1;
+            new.c_cc[libc::VTIME] = 0;
+            libc::tcsetattr(fd, libc::TCSANOW, &new);
+            Some(RawInput { fd, old })
+        }
+    }
+}
+
+impl Drop for RawInput {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.old) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::split;
+
+    fn chars(s: &str) -> Vec<(char, bool)> {
+        s.chars().map(|c| (c, false)).collect()
+    }
+
+    #[test]
+    fn split_ifs() {
+        let ifs = " \t\n";
+        assert_eq!(split(&chars("  a b  c "), ifs, None), ["a", "b", "c"]);
+        assert_eq!(split(&chars("  a b  c "), ifs, Some(2)), ["a", "b  c"]);
+        assert_eq!(split(&chars("a:b::c"), ":", None), ["a", "b", "", "c"]);
+        assert_eq!(split(&chars("a : b"), " :", None), ["a", "b"]);
+        assert_eq!(split(&chars(" a b "), "", Some(1)), [" a b "]);
+    }
+
+    #[test]
+    fn split_escaped() {
+        let mut input = chars("a b");
+        input[1].1 = true;
+        assert_eq!(split(&input, " ", None), ["a b"]);
+    }
+}
@@ -0,0 +1,255 @@
+//! `set` and `shopt`, which change the shell's options.
+
+use super::alias::quote_word;
+use super::{Io, ShellBuiltin};
+use crate::shell::options::Opt;
+use crate::shell::{ShellState, Var};
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// `set [-+eCfHnuvx] [-+o option] [--] [args...]`
+///
+/// Turns options on with `-` and off with `+`. Anything after the options
+/// replaces the positional parameters. With no arguments every variable is
+/// shown.
+pub struct Set;
+impl ShellBuiltin for Set {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        if args.is_empty() {
+            print_vars(state, io);
+            return 0;
+        }
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let on = match arg.as_str() {
+                "--" => {
+                    state.set_args(args.cloned().collect());
+                    return 0;
+                }
+                a if a.starts_with('-') => true,
+                a if a.starts_with('+') => false,
+                _ => {
+                    // the first word that isn't an option starts the arguments
+                    let mut rest = vec![arg.clone()];
+                    rest.extend(args.cloned());
+                    state.set_args(rest);
+                    return 0;
+                }
+            };
+
+            for c in arg[1..].chars() {
+                let opt = if c == 'o' {
+                    match args.next() {
+                        Some(name) => Opt::from_name(name).ok_or(name.as_str()),
+                        None => {
+                            print_options(state, on, io);
+                            continue;
+                        }
+                    }
+                } else {
+                    Opt::from_letter(c).ok_or(&arg[..])
+                };
+
+                match opt {
+                    Ok(opt) => state.set_option(opt, on),
+                    Err(name) if c == 'o' => {
+                        let _ = writeln!(io.stderr, "rush: set: {}: invalid option name", name);
+                        return 1;
+                    }
+                    Err(_) => {
+                        let _ =
+                            writeln!(io.stderr, "rush: set: {}{}: invalid option", &arg[..1], c);
+                        let _ = writeln!(
+                            io.stderr,
+                            "set: usage: set [-eCfHnuvx] [-o option] [--] [args ...]"
+                        );
+                        return 2;
+                    }
+                }
+            }
+        }
+        0
+    }
+
+    fn help(&self) -> &str {
+        "set [-eCfHnuvx] [-o option] [--] [args ...]\n\
+         Turn shell options on, or off with `+`, and set the positional parameters.\n\
+         The options are errexit (-e), histexpand (-H), noclobber (-C), noglob (-f),\n\
+         noexec (-n), nounset (-u), pipefail, verbose (-v) and xtrace (-x). emacs\n\
+         and vi pick the keys used to edit lines."
+    }
+}
+
+/// `shopt [-pqsu] [-o] [name ...]`
+///
+/// Turns options on with `-s` and off with `-u`. Without either the options
+/// are shown. `-o` uses the options of `set` instead of the extended ones.
+pub struct Shopt;
+impl ShellBuiltin for Shopt {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let mut set = None;
+        let mut print = false;
+        let mut quiet = false;
+        let mut set_opts = false;
+        let mut args = args;
+        while let Some((flag, rest)) = args.split_first() {
+            match flag.as_str() {
+                "--" => {
+                    args = rest;
+                    break;
+                }
+                f if f.starts_with('-') && f.len() > 1 => {
+                    for c in f[1..].chars() {
+                        match c {
+                            's' if set == Some(false) => return both(io),
+                            'u' if set == Some(true) => return both(io),
+                            's' => set = Some(true),
+                            'u' => set = Some(false),
+                            'p' => print = true,
+                            'q' => quiet = true,
+                            'o' => set_opts = true,
+                            c => {
+                                let _ = writeln!(io.stderr, "rush: shopt: -{}: invalid option", c);
+                                let _ = writeln!(
+                                    io.stderr,
+                                    "shopt: usage: shopt [-pqsu] [-o] [name ...]"
+                                );
+                                return 2;
+                            }
+                        }
+                    }
+                }
+                _ => break,
+            }
+            args = &args[1..];
+        }
+
+        let all = if set_opts { Opt::SET } else { Opt::SHOPT };
+        let opts = if args.is_empty() {
+            all.to_vec()
+        } else {
+            let mut opts = Vec::with_capacity(args.len());
+            for name in args {
+                let opt = if set_opts {
+                    Opt::from_name(name)
+                } else {
+                    Opt::from_shopt(name)
+                };
+                match opt {
+                    Some(opt) => opts.push(opt),
+                    None => {
+                        let _ = writeln!(
+                            io.stderr,
+                            "rush: shopt: {}: invalid shell option name",
+                            name
+                        );
+                        return 1;
+                    }
+                }
+            }
+            opts
+        };
+
+        match set {
+            Some(on) if !args.is_empty() => {
+                for opt in opts {
+                    state.set_option(opt, on);
+                }
+                0
+            }
+            _ => {
+                let mut code = 0;
+                for opt in opts {
+                    let on = state.option(opt);
+                    // `-s` and `-u` alone pick which options are shown
+                    if set.is_some_and(|s| s != on) {
+                        continue;
+                    }
+                    code = code.max(!on as i32);
+                    if quiet {
+                        continue;
+                    }
+                    let _ = match (print, set_opts) {
+                        (true, true) => writeln!(io.stdout, "set {}o {}", sign(on), opt.name()),
+                        (true, false) => writeln!(io.stdout, "shopt {} {}", flag(on), opt.name()),
+                        (false, _) => writeln!(io.stdout, "{:<16}{}", opt.name(), on_off(on)),
+                    };
+                }
+                code
+            }
+        }
+    }
+
+    fn help(&self) -> &str {
+        "shopt [-pqsu] [-o] [name ...]\n\
+         Turn options on with -s or off with -u, or show them. -o uses the options\n\
+         of `set`. The other options are expand_aliases and sourcepath."
+    }
+}
+
+/// Shows every option of `set`. `set -o` shows a table and `set +o` shows
+/// the commands to get them back.
+fn print_options(state: &ShellState, table: bool, io: &mut Io) {
+    for opt in Opt::SET {
+        let on = state.option(*opt);
+        let _ = if table {
+            writeln!(io.stdout, "{:<16}{}", opt.name(), on_off(on))
+        } else {
+            writeln!(io.stdout, "set {}o {}", sign(on), opt.name())
+        };
+    }
+}
+
+/// Shows every variable in a way it can be read back.
+fn print_vars(state: &ShellState, io: &mut Io) {
+    let mut vars: BTreeMap<String, String> =
+        std::env::vars().map(|(k, v)| (k, quote_word(&v))).collect();
+    for (k, v) in state.vars() {
+        let value = match v {
+            Var::Scalar(s) => quote_word(s),
+            Var::Array(a) => {
+                let items: Vec<_> = a.iter().map(|s| quote_word(s)).collect();
+                format!("({})", items.join(" "))
+            }
+        };
+        vars.insert(k.to_owned(), value);
+    }
+
+    for (k, v) in vars {
+        let _ = writeln!(io.stdout, "{}={}", k, v);
+    }
+}
+
+fn both(io: &mut Io) -> i32 {
+    let _ = writeln!(
+        io.stderr,
+        "rush: shopt: cannot set and unset shell options simultaneously"
+    );
+    1
+}
+
+fn sign(on: bool) -> char {
+    if on {
+        '-'
+    } else {
+        '+'
+    }
+}
+
+fn flag(on: bool) -> &'static str {
+    if on {
+        "-s"
+    } else {
+        "-u"
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
@@ -0,0 +1,395 @@
+//! `test`, `[` and the evaluation of `[[ ... ]]`.
+
+use super::{Io, ShellBuiltin};
+use crate::shell::ShellState;
+use crate::util::glob_match;
+
+use rush_core::cond::{CondExpr, BINARY_OPS, UNARY_OPS};
+
+use std::ffi::CString;
+use std::fs::Metadata;
+use std::io::Write;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+/// `test expr`
+///
+/// Exits with 0 when the expression is true, 1 when it is false and 2 when
+/// it is invalid.
+pub struct Test;
+impl ShellBuiltin for Test {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let _ = state;
+        report("test", test(args), &mut io.stderr)
+    }
+
+    fn help(&self) -> &str {
+        "test [expr]\n\
+         Check a condition. Exits with 0 when it is true and 1 when it is false."
+    }
+}
+
+/// `[ expr ]`
+///
+/// The same as [`Test`] but the last argument has to be a `]`.
+pub struct Bracket;
+impl ShellBuiltin for Bracket {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let _ = state;
+        match args.split_last() {
+            Some((last, args)) if last == "]" => report("[", test(args), &mut io.stderr),
+            _ => {
+                let _ = writeln!(io.stderr, "rush: [: missing `]'");
+                2
+            }
+        }
+    }
+
+    fn help(&self) -> &str {
+        "[ expr ]\n\
+         The same as `test` but the last arg has to be `]`."
+    }
+}
+
+/// Evaluates a `[[ ... ]]` expression. Unlike `test` the right side of `==`
+/// and `!=` is a glob pattern and `=~` matches an extended regex.
+pub fn cond(expr: &CondExpr<String>, io: &mut Io) -> i32 {
+    report("[[", eval_cond(expr), &mut io.stderr)
+}
+
+fn eval_cond(expr: &CondExpr<String>) -> Result<bool, String> {
+    match expr {
+        CondExpr::Word(w) => Ok(!w.is_empty()),
+        CondExpr::Unary(op, arg) => unary(op, arg),
+        CondExpr::Binary(a, op, b) => match op.as_str() {
+            "=" | "==" => Ok(glob_match(b, a)),
+            "!=" => Ok(!glob_match(b, a)),
+            "=~" => regex_match(b, a),
+            op => binary(a, op, b),
+        },
+        CondExpr::Not(e) => eval_cond(e).map(|b| !b),
+        CondExpr::And(a, b) => Ok(eval_cond(a)? && eval_cond(b)?),
+        CondExpr::Or(a, b) => Ok(eval_cond(a)? || eval_cond(b)?),
+    }
+}
+
+fn report(name: &str, res: Result<bool, String>, stderr: &mut impl Write) -> i32 {
+    match res {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            let _ = writeln!(stderr, "rush: {}: {}", name, e);
+            2
+        }
+    }
+}
+
+/// Evaluates the arguments of `test`. Up to four arguments are handled with
+/// the rules POSIX gives so things like `test ! = x` work. Anything longer
+/// is parsed with `-a`, `-o`, `!` and parens.
+fn test(args: &[String]) -> Result<bool, String> {
+    let is_binary = |op: &str| (BINARY_OPS.contains(&op) && op != "=~") || op == "-a" || op == "-o";
+
+    match args {
+        [] => Ok(false),
+        [a] => Ok(!a.is_empty()),
+        [bang, a] if bang == "!" => Ok(a.is_empty()),
+        [op, a] if UNARY_OPS.contains(&op.as_str()) => unary(op, a),
+        [op, _] => Err(format!("{}: unary operator expected", op)),
+        [a, op, b] if is_binary(op) => match op.as_str() {
+            "-a" => Ok(!a.is_empty() && !b.is_empty()),
+            "-o" => Ok(!a.is_empty() || !b.is_empty()),
+            op => binary(a, op, b),
+        },
+        [bang, rest @ ..] if bang == "!" && args.len() <= 4 => test(rest).map(|b| !b),
+        [open, inner @ .., close] if open == "(" && close == ")" && args.len() <= 4 => test(inner),
+        _ => {
+            let mut parser = TestParser { args, pos: 0 };
+            let res = parser.or()?;
+            match parser.args.get(parser.pos) {
+                None => Ok(res),
+                Some(a) => Err(format!("{}: too many arguments", a)),
+            }
+        }
+    }
+}
+
+/// A recursive decent parser for long `test` expressions.
+struct TestParser<'a> {
+    args: &'a [String],
+    pos: usize,
+}
+
+impl TestParser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.args.get(self.pos).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<&str> {
+        let arg = self.args.get(self.pos).map(String::as_str);
+        self.pos += 1;
+        arg
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut res = self.and()?;
+        while self.peek() == Some("-o") {
+            self.pos += 1;
+            // both sides are always parsed even if they are not needed
+            let rhs = self.and()?;
+            res = res || rhs;
+        }
+        Ok(res)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut res = self.not()?;
+        while self.peek() == Some("-a") {
+            self.pos += 1;
+            let rhs = self.not()?;
+            res = res && rhs;
+        }
+        Ok(res)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        if self.peek() == Some("!") {
+            self.pos += 1;
+            return self.not().map(|b| !b);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        let args = self.args;
+        let pos = self.pos;
+
+        // a binary operator takes precedence over anything else
+        if let (Some(a), Some(op), Some(b)) = (args.get(pos), args.get(pos + 1), args.get(pos + 2))
+        {
+            if BINARY_OPS.contains(&op.as_str()) && op != "=~" {
+                self.pos += 3;
+                return binary(a, op, b);
+            }
+        }
+
+        match self.next() {
+            None => Err(String::from("argument expected")),
+            Some("(") => {
+                let res = self.or()?;
+                match self.next() {
+                    Some(")") => Ok(res),
+                    _ => Err(String::from("`)' expected")),
+                }
+            }
+            Some(op) if UNARY_OPS.contains(&op) => {
+                let op = op.to_owned();
+                match self.next() {
+                    Some(arg) => unary(&op, arg),
+                    None => Err(format!("{}: argument expected", op)),
+                }
+            }
+            Some(a) => Ok(!a.is_empty()),
+        }
+    }
+}
+
+/// Runs one of the [`UNARY_OPS`].
+fn unary(op: &str, arg: &str) -> Result<bool, String> {
+    let meta = || std::fs::metadata(arg).ok();
+    let is = |f: fn(&Metadata) -> bool| meta().as_ref().is_some_and(f);
+
+    Ok(match op {
+        "-n" => !arg.is_empty(),
+        "-z" => arg.is_empty(),
+        "-a" | "-e" => meta().is_some(),
+        "-b" => is(|m| m.file_type().is_block_device()),
+        "-c" => is(|m| m.file_type().is_char_device()),
+        "-d" => is(|m| m.is_dir()),
+        "-f" => is(|m| m.is_file()),
+        "-g" => is(|m| m.mode() & libc::S_ISGID != 0),
+        "-h" | "-L" => std::fs::symlink_metadata(arg).is_ok_and(|m| m.file_type().is_symlink()),
+        "-k" => is(|m| m.mode() & libc::S_ISVTX != 0),
+        "-p" => is(|m| m.file_type().is_fifo()),
+        "-s" => is(|m| m.len() > 0),
+        "-S" => is(|m| m.file_type().is_socket()),
+        "-u" => is(|m| m.mode() & libc::S_ISUID != 0),
+        "-O" => is(|m| m.uid() == unsafe { libc::geteuid() }),
+        "-G" => is(|m| m.gid() == unsafe { libc::getegid() }),
+        "-r" => access(arg, libc::R_OK),
+        "-w" => access(arg, libc::W_OK),
+        "-x" => access(arg, libc::X_OK),
+        "-t" => {
+            let fd = int(arg)?;
+            unsafe { libc::isatty(fd as libc::c_int) == 1 }
+        }
+        op => return Err(format!("{}: unary operator expected", op)),
+    })
+}
+
+/// Runs one of the [`BINARY_OPS`] except for `=~`.
+fn binary(a: &str, op: &str, b: &str) -> Result<bool, String> {
+    let modified = |f: &str| std::fs::metadata(f).and_then(|m| m.modified()).ok();
+
+    Ok(match op {
+        "=" | "==" => a == b,
+        "!=" => a != b,
+        "<" => a < b,
+        ">" => a > b,
+        "-eq" => int(a)? == int(b)?,
+        "-ne" => int(a)? != int(b)?,
+        "-lt" => int(a)? < int(b)?,
+        "-le" => int(a)? <= int(b)?,
+        "-gt" => int(a)? > int(b)?,
+        "-ge" => int(a)? >= int(b)?,
+        "-nt" => match (modified(a), modified(b)) {
+            (Some(a), Some(b)) => a > b,
+            (Some(_), None) => true,
+            _ => false,
+        },
+        "-ot" => match (modified(a), modified(b)) {
+            (Some(a), Some(b)) => a < b,
+            (None, Some(_)) => true,
+            _ => false,
+        },
+        "-ef" => match (std::fs::metadata(a), std::fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        },
+        op => return Err(format!("{}: binary operator expected", op)),
+    })
+}
+
+fn int(s: &str) -> Result<i64, String> {
+    s.trim()
+        .parse()
+        .map_err(|_| format!("{}: integer expression expected", s))
+}
+
+/// Checks if this process can access a file with its effective ids.
+fn access(path: &str, mode: libc::c_int) -> bool {
+    let Ok(path) = CString::new(path) else {
+        return false;
+    };
+    unsafe { libc::faccessat(libc::AT_FDCWD, path.as_ptr(), mode, libc::AT_EACCESS) == 0 }
+}
+
+/// Matches a string against a POSIX extended regex.
+fn regex_match(pattern: &str, s: &str) -> Result<bool, String> {
+    let (Ok(re), Ok(s)) = (CString::new(pattern), CString::new(s)) else {
+        return Ok(false);
+    };
+
+    unsafe {
+        let mut compiled = std::mem::zeroed::<libc::regex_t>();
+        if libc::regcomp(
+            &mut compiled,
+            re.as_ptr(),
+            libc::REG_EXTENDED | libc::REG_NOSUB,
+        ) != 0
+        {
+            return Err(format!("{}: invalid regular expression", pattern));
+        }
+        let res = libc::regexec(&compiled, s.as_ptr(), 0, std::ptr::null_mut(), 0);
+        libc::regfree(&mut compiled);
+        Ok(res == 0)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod test {
+    use super::*;
+
+    fn run(args: &[&str]) -> i32 {
+        let args: Vec<_> = args.iter().map(|a| a.to_string()).collect();
+        report("test", test(&args), &mut Vec::new())
+    }
+
+    #[test]
+    fn few_args() {
+        assert_eq!(run(&[]), 1);
+        assert_eq!(run(&["x"]), 0);
+        assert_eq!(run(&[""]), 1);
+        // one argument is never an operator
+        assert_eq!(run(&["-n"]), 0);
+        assert_eq!(run(&["!", ""]), 0);
+        assert_eq!(run(&["-z", ""]), 0);
+        assert_eq!(run(&["-q", "x"]), 2);
+        // a binary operator comes before `!`
+        assert_eq!(run(&["!", "=", "x"]), 1);
+        assert_eq!(run(&["-n", "=", "-n"]), 0);
+        assert_eq!(run(&["!", "a", "=", "a"]), 1);
+        assert_eq!(run(&["(", "", ")"]), 1);
+        assert_eq!(run(&["a", "<", "b"]), 0);
+    }
+
+    #[test]
+    fn precedence() {
+        // `-a` binds tighter than `-o`
+        assert_eq!(run(&["x", "-o", "", "-a", ""]), 0);
+        assert_eq!(run(&["", "-o", "x", "-a", ""]), 1);
+        // and `!` tighter than both
+        assert_eq!(run(&["!", "x", "-o", "x", "-a", "x"]), 0);
+        assert_eq!(run(&["!", "(", "x", "-o", "x", ")", "-a", "x"]), 1);
+        assert_eq!(run(&["x", "-a", "(", "", "-o", "y", ")"]), 0);
+        assert_eq!(run(&["x", "-a", "(", "y"]), 2);
+        assert_eq!(run(&["x", "y", "-a", "z", "w"]), 2);
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(run(&["10", "-gt", "9"]), 0);
+        assert_eq!(run(&[" -3", "-lt", "2 "]), 0);
+        assert_eq!(run(&["1", "-eq", "a"]), 2);
+        assert_eq!(run(&["", "-ne", "1"]), 2);
+        assert_eq!(run(&["1.5", "-le", "2"]), 2);
+
+        let mut err = Vec::new();
+        let args = [String::from("x"), String::from("-eq"), String::from("1")];
+        assert_eq!(report("test", test(&args), &mut err), 2);
+        assert_eq!(err, b"rush: test: x: integer expression expected\n");
+    }
+
+    #[test]
+    fn files() {
+        let dir = std::env::temp_dir().join(format!("rush-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let empty = dir.join("empty");
+        let full = dir.join("full");
+        let link = dir.join("link");
+        std::fs::write(&empty, "").unwrap();
+        std::fs::write(&full, "data").unwrap();
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(&full, &link).unwrap();
+        let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1000);
+        std::fs::File::options()
+            .write(true)
+            .open(&empty)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let (dir, empty, full, link) = (
+            dir.to_str().unwrap(),
+            empty.to_str().unwrap(),
+            full.to_str().unwrap(),
+            link.to_str().unwrap(),
+        );
+        assert_eq!(run(&["-e", empty]), 0);
+        assert_eq!(run(&["-f", empty]), 0);
+        assert_eq!(run(&["-d", empty]), 1);
+        assert_eq!(run(&["-d", dir]), 0);
+        assert_eq!(run(&["-s", empty]), 1);
+        assert_eq!(run(&["-s", full]), 0);
+        assert_eq!(run(&["-L", link]), 0);
+        assert_eq!(run(&["-L", full]), 1);
+        assert_eq!(run(&["-e", "/no/such/file"]), 1);
+        assert_eq!(run(&[full, "-nt", empty]), 0);
+        assert_eq!(run(&[empty, "-ot", full]), 0);
+        assert_eq!(run(&[full, "-nt", "/no/such/file"]), 0);
+        assert_eq!(run(&[link, "-ef", full]), 0);
+        assert_eq!(run(&[empty, "-ef", full]), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+//! `trap`.
+
+use super::alias::quote;
+use super::{Io, ShellBuiltin};
+use crate::shell::{Condition, ShellState};
+use crate::signal;
+use crate::util::error_message;
+
+use std::io::Write;
+
+/// `trap [-lp] [[action] condition ...]`
+///
+/// Sets code to run when the shell gets a signal or when one of `EXIT`,
+/// `ERR`, `DEBUG` or `RETURN` happens. An action of `-` resets the
+/// conditions and an empty one ignores them.
+pub struct Trap;
+impl ShellBuiltin for Trap {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let (flag, args) = match args.first().map(String::as_str) {
+            Some("--") => (None, &args[1..]),
+            Some(f @ ("-l" | "-p")) => (Some(f), &args[1..]),
+            _ => (None, args),
+        };
+
+        match (flag, args) {
+            (Some("-l"), _) => {
+                for (name, sig) in signal::SIGNALS {
+                    let _ = writeln!(io.stdout, "{:2}) SIG{}", sig, name);
+                }
+                0
+            }
+            (Some("-p"), []) | (None, []) => {
+                for (cond, code) in state.traps() {
+                    let _ = writeln!(io.stdout, "trap -- {} {}", quote(code), cond);
+                }
+                0
+            }
+            (Some(_), names) => match conditions(names, io) {
+                Some(conds) => {
+                    for cond in conds {
+                        if let Some(code) = state.trap(cond) {
+                            let _ = writeln!(io.stdout, "trap -- {} {}", quote(code), cond);
+                        }
+                    }
+                    0
+                }
+                None => 1,
+            },
+            // a lone condition is reset like it was given `-`
+            (None, [_]) => set(None, args, state, io),
+            (None, [action, names @ ..]) => {
+                let action = (action != "-").then_some(action.as_str());
+                set(action, names, state, io)
+            }
+        }
+    }
+
+    fn help(&self) -> &str {
+        "trap [-lp] [[action] condition ...]\n\
+         Run action when the shell gets a signal or on EXIT, ERR, DEBUG or RETURN.\n\
+         An action of `-` resets the condition and an empty action ignores it."
+    }
+}
+
+/// Sets or resets the trap of every condition. `None` resets them.
+fn set(action: Option<&str>, names: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+    let conds = match conditions(names, io) {
+        Some(conds) => conds,
+        None => return 1,
+    };
+
+    let mut code = 0;
+    for cond in conds {
+        if let Condition::Signal(sig) = cond {
+            let res = match action {
+                // the shell always handles ^C itself
+                None if sig == libc::SIGINT => signal::catch(sig),
+                None => signal::reset(sig),
+                Some("") => signal::ignore(sig),
+                Some(_) => signal::catch(sig),
+            };
+            if let Err(e) = res {
+                let _ = writeln!(io.stderr, "rush: trap: {}: {}", cond, error_message(&e));
+                code = 1;
+                continue;
+            }
+        }
+
+        match action {
+            Some(action) => state.set_trap(cond, action.to_owned()),
+            None => {
+                state.remove_trap(cond);
+            }
+        }
+    }
+    code
+}
+
+/// Parses the names of conditions. Nothing is given back if any are bad so
+/// a trap is never set halfway.
+fn conditions(names: &[String], io: &mut Io) -> Option<Vec<Condition>> {
+    let mut conds = Vec::with_capacity(names.len());
+    for name in names {
+        match Condition::from_name(name) {
+            Some(Condition::Signal(sig)) if sig == libc::SIGKILL || sig == libc::SIGSTOP => {
+                let _ = writeln!(io.stderr, "rush: trap: {}: cannot be trapped", name);
+                return None;
+            }
+            Some(cond) => conds.push(cond),
+            None => {
+                let _ = writeln!(
+                    io.stderr,
+                    "rush: trap: {}: invalid signal specification",
+                    name
+                );
+                return None;
+            }
+        }
+    }
+    Some(conds)
+}
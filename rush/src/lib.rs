@@ -0,0 +1,17 @@
+//! The rush shell as a library. The binary is a thin wrapper around
+//! [`Shell`], which embedders can also use to run a shell with their own
+//! builtins and completers.
+
+#![feature(let_chains, vec_into_raw_parts)]
+
+pub mod complete;
+pub mod drive;
+pub mod parse;
+pub mod prelude;
+pub mod shell;
+pub mod signal;
+pub mod task;
+pub mod util;
+pub mod walker;
+
+pub use crate::shell::{Shell, ShellState};
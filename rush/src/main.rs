@@ -1,30 +1,25 @@
-#![feature(let_chains, vec_into_raw_parts)]
-
-pub mod drive;
-pub mod parse;
-pub mod prelude;
-pub mod shell;
-pub mod task;
-pub mod util;
-pub mod walker;
-
-use crate::prelude::*;
+use rush::prelude::*;
+use rush::shell::options::Opt;
+use rush::shell::ShellError;
 
 use std::{fs, process::ExitCode};
 
-use rush_core::lexer::Lexer;
 use std::{env, path::PathBuf};
 
 fn main() -> ExitCode {
+    use clap::Parser;
     use std::io::Read;
-    let exit = match ShellMode::get() {
+
+    let args = Args::parse();
+    let opts = args.options();
+    let exit = match ShellMode::from(args) {
         ShellMode::Run(path) => {
             log::info!("running file: {:?}", path);
 
             let data = fs::read_to_string(path).unwrap();
             let input = data.chars();
 
-            Shell::sourced(Lexer::new(input)).run(false)
+            run(Shell::script(input), &opts, false)
         }
         ShellMode::Eval => {
             log::warn!("the implementation for reading from stdin is shitty.");
@@ -35,12 +30,12 @@ fn main() -> ExitCode {
 
             let input = buf.chars();
 
-            Shell::sourced(Lexer::new(input)).run(false)
+            run(Shell::script(input), &opts, false)
         }
         ShellMode::Interactive => {
             log::info!("running interactive session");
 
-            Shell::interactive().run(true)
+            run(Shell::interactive(), &opts, true)
         }
         ShellMode::Login => {
             log::info!("running login session");
@@ -48,22 +43,22 @@ fn main() -> ExitCode {
             // Logins shells read /etc/profile and ~/.profile
             // and then function as normal interactive shells
 
-            Shell::login().run(true)
+            run(Shell::login(), &opts, true)
         }
         ShellMode::Command(cmd) => {
             log::info!("running command: {:?}", cmd);
 
             let input = cmd.chars().peekable();
 
-            Shell::sourced(Lexer::new(input)).run(false)
+            run(Shell::script(input), &opts, false)
         }
     };
 
     match exit {
-        Ok(_) => ExitCode::SUCCESS,
+        // only the low byte of a status makes it to the parent
+        Ok(code) => ExitCode::from(code as u8),
         Err(e) => {
             eprint!("{:?}", e);
-            // TODO: more complext exit codes
             ExitCode::FAILURE
         }
     }
@@ -78,11 +73,15 @@ pub enum ShellMode {
     Command(String),
 }
 
-impl ShellMode {
-    pub fn get() -> Self {
-        use clap::Parser;
-        Self::from(Args::parse())
+/// Runs a shell with the options from the command line turned on.
+fn run<I>(mut shell: Shell<I>, opts: &[Opt], live: bool) -> Result<i32, ShellError>
+where
+    I: Iterator<Item = Token>,
+{
+    for opt in opts {
+        shell.set_option(*opt, true);
     }
+    shell.run(live)
 }
 
 impl From<Args> for ShellMode {
@@ -131,6 +130,62 @@ struct Args {
     #[arg(short, long)]
     interactive: bool,
 
+    /// Exit when a command fails.
+    #[arg(short = 'e')]
+    errexit: bool,
+
+    /// Don't let `>` overwrite files.
+    #[arg(short = 'C')]
+    noclobber: bool,
+
+    /// Don't expand paths.
+    #[arg(short = 'f')]
+    noglob: bool,
+
+    /// Read commands without running them.
+    #[arg(short = 'n')]
+    noexec: bool,
+
+    /// Treat using an unset variable as an error.
+    #[arg(short = 'u')]
+    nounset: bool,
+
+    /// Print input as it is read.
+    #[arg(short = 'v')]
+    verbose: bool,
+
+    /// Print commands before they run.
+    #[arg(short = 'x')]
+    xtrace: bool,
+
+    /// Turn on an option by name, like `pipefail`.
+    #[arg(short = 'o', value_name = "OPTION", value_parser = parse_option)]
+    option: Vec<Opt>,
+
     /// File to execute. Pass `-` to read stdin, which is default
     file: Option<String>,
 }
+
+impl Args {
+    /// Every option that was turned on.
+    fn options(&self) -> Vec<Opt> {
+        let flags = [
+            (self.errexit, Opt::Errexit),
+            (self.noclobber, Opt::Noclobber),
+            (self.noglob, Opt::Noglob),
+            (self.noexec, Opt::Noexec),
+            (self.nounset, Opt::Nounset),
+            (self.verbose, Opt::Verbose),
+            (self.xtrace, Opt::Xtrace),
+        ];
+        flags
+            .into_iter()
+            .filter_map(|(on, opt)| on.then_some(opt))
+            .chain(self.option.iter().copied())
+            .collect()
+    }
+}
+
+fn parse_option(name: &str) -> std::result::Result<Opt, String> {
+    Opt::from_name(name).ok_or_else(|| format!("invalid option name `{}`", name))
+}
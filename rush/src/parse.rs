@@ -1,10 +1,17 @@
+use rush_core::cond::CondExpr;
 use rush_core::lexer::Lexer;
-use rush_core::walker::{TreeItem, Walker};
+use rush_core::walker::{Expand, RedirectKind, TreeItem, Walker};
 
 use crate::prelude::*;
+use crate::shell::history::expand::expand;
+use crate::shell::options::Opt;
 
 use crate::util::{OwnedCharBuffer, StaticMap};
 
+use self::line::{read_line, Editor, ReadlineOutput};
+use self::prompt::Prompt;
+
+use std::collections::VecDeque;
 use std::iter::Peekable;
 use std::os::fd::{FromRawFd, OwnedFd, RawFd};
 use std::process::Stdio;
@@ -16,6 +23,11 @@ where
     I: Iterator<Item = Token>,
 {
     tokens: Peekable<Walker<I>>,
+    /// Items from an expanded alias. These are read before `tokens`.
+    pending: VecDeque<TreeItem>,
+    /// The aliases that made `pending`. These aren't expanded again so an
+    /// alias can use a command with the same name.
+    expanded: Vec<String>,
 }
 
 impl<I> Parser<I>
@@ -25,23 +37,129 @@ where
     pub fn new(tokens: I) -> Parser<I> {
         Parser {
             tokens: Walker::new(tokens).peekable(),
+            pending: VecDeque::new(),
+            expanded: Vec::new(),
+        }
+    }
+
+    fn peek(&mut self) -> Option<&TreeItem> {
+        match self.pending.front() {
+            Some(item) => Some(item),
+            None => self.tokens.peek(),
+        }
+    }
+
+    fn next_item(&mut self) -> Option<TreeItem> {
+        match self.pending.pop_front() {
+            Some(item) => Some(item),
+            None => {
+                self.expanded.clear();
+                self.tokens.next()
+            }
+        }
+    }
+
+    /// Gets what an alias for a command word expands to. Only words that are
+    /// plain text can be aliases.
+    fn expand_alias(&mut self, word: &[Expand], state: &ShellState) -> Option<Vec<TreeItem>> {
+        let name: String = word
+            .iter()
+            .map(|e| match e {
+                Expand::Literal(s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        if self.expanded.contains(&name) {
+            return None;
         }
+
+        let value = state.alias(&name)?;
+        log::info!("expanding alias {} to {:?}", name, value);
+        let items = rush_core::parse(value).collect();
+        self.expanded.push(name);
+        Some(items)
     }
 
     pub fn next(&mut self, state: &ShellState) -> Option<Result<Cmd, CmdError>> {
         // when there are no tokens left return
-        self.tokens.peek()?;
+        self.peek()?;
 
         log::info!("getting next command.");
 
-        Some(self.get_next(state))
+        let res = self.get_next(state);
+        if res.is_err() {
+            // dont try to make sense of the rest of a broken statement
+            while let Some(t) = self.next_item() {
+                if let TreeItem::StatmentEnd = t {
+                    break;
+                }
+            }
+        }
+        Some(res)
     }
 
+    /// Reads pipelines joined by `&&` and `||` up to the end of the
+    /// statement. These are left associative so in `a && b || c`, `c` runs
+    /// when either `a` or `b` fails.
     fn get_next(&mut self, state: &ShellState) -> Result<Cmd, CmdError> {
-        let mut cmd = SimpleCmd::default();
+        let mut node = self.get_pipe(state)?;
+        loop {
+            match self.next_item() {
+                Some(TreeItem::And) => {
+                    node = Cmd::And(Box::new(node), Box::new(self.get_pipe(state)?));
+                }
+                Some(TreeItem::Or) => {
+                    node = Cmd::Or(Box::new(node), Box::new(self.get_pipe(state)?));
+                }
+                Some(TreeItem::StatmentEnd) | None => return Ok(node),
+                Some(t) => {
+                    return Err(Report::new(CmdError::Syntax(format!("unexpected {:?}", t))))
+                }
+            }
+        }
+    }
+
+    /// Reads commands joined by `|` which can be negated with a `!`.
+    fn get_pipe(&mut self, state: &ShellState) -> Result<Cmd, CmdError> {
+        if let Some(TreeItem::Bang) = self.peek() {
+            let _ = self.next_item();
+            return Ok(Cmd::Not(Box::new(self.get_pipe(state)?)));
+        }
+        self.get_stages(state)
+    }
+
+    fn get_stages(&mut self, state: &ShellState) -> Result<Cmd, CmdError> {
+        let mut stages = vec![self.get_simple(state)?];
+        while let Some(TreeItem::Pipe) = self.peek() {
+            let _ = self.next_item();
+            stages.push(self.get_simple(state)?);
+        }
+
+        if stages.len() == 1 {
+            return Ok(stages.pop().unwrap());
+        }
+        Ok(Cmd::Pipeline(stages))
+    }
+
+    /// Reads a single command and its redirections. Stops before any
+    /// operator.
+    fn get_simple(&mut self, state: &ShellState) -> Result<Cmd, CmdError> {
+        let mut cmd = RawCmd::default();
+        let mut cond = None;
         loop {
-            let Some(token) = self.tokens.next() else {
-                return Ok(cmd.build());
+            match self.peek() {
+                None
+                | Some(
+                    TreeItem::And
+                    | TreeItem::Or
+                    | TreeItem::Pipe
+                    | TreeItem::Bang
+                    | TreeItem::StatmentEnd,
+                ) => break,
+                Some(_) => {}
+            }
+            let Some(token) = self.next_item() else {
+                unreachable!()
             };
             log::info!("got token: {:?}", token);
 
@@ -50,36 +168,51 @@ where
             // not one. This reduces the load on the tokenizer.
 
             match token {
-                TreeItem::Word(v) => {
-                    let a = v.into_iter().map(|e| crate::walker::expand(e, state)).fold(
-                        String::new(),
-                        |mut s, e| {
-                            s.push_str(&e);
-                            s
-                        },
-                    );
-                    cmd.push_ident(a);
-                }
-                TreeItem::Or => {
-                    let c = cmd.build();
-                    return Ok(Cmd::Or(Box::new(c), Box::new(self.get_next(state)?)));
-                }
-                TreeItem::And => {
-                    let c = cmd.build();
-                    return Ok(Cmd::And(Box::new(c), Box::new(self.get_next(state)?)));
+                TreeItem::Word(_) if cond.is_some() => {
+                    return Err(Report::new(CmdError::Syntax(String::from(
+                        "unexpected word after `]]'",
+                    ))));
                 }
-                TreeItem::Pipe => {
-                    let c = cmd.build();
-                    return Ok(Cmd::Pipeline(Box::new(c), Box::new(self.get_next(state)?)));
+                TreeItem::Word(v) => {
+                    if cmd.words.is_empty() && state.option(Opt::ExpandAliases) {
+                        if let Some(items) = self.expand_alias(&v, state) {
+                            // the alias is read in place of the word
+                            for item in items.into_iter().rev() {
+                                self.pending.push_front(item);
+                            }
+                            continue;
+                        }
+                    }
+                    cmd.words.push(v);
                 }
-                TreeItem::StatmentEnd => return Ok(cmd.build()),
+                TreeItem::Cond(e) => cond = Some(e),
+                TreeItem::SyntaxError(msg) => return Err(Report::new(CmdError::Syntax(msg))),
                 // TreeItem::Assign(_, _) => todo!(),
-                TreeItem::Append => todo!(),
-                TreeItem::Redirect => todo!(),
+                TreeItem::Redirect(fd, kind) => {
+                    let Some(TreeItem::Word(v)) = self.next_item() else {
+                        return Err(Report::new(CmdError::MissingTarget));
+                    };
+                    cmd.redirects.push(Redirect {
+                        fd: fd.unwrap_or_else(|| kind.default_fd()) as RawFd,
+                        kind,
+                        target: v,
+                    });
+                }
                 TreeItem::Background => todo!(),
                 TreeItem::Comment => {}
+                TreeItem::And
+                | TreeItem::Or
+                | TreeItem::Pipe
+                | TreeItem::Bang
+                | TreeItem::StatmentEnd => unreachable!(),
             }
         }
+
+        match cond {
+            Some(e) => Ok(Cmd::Cond(e, cmd.redirects)),
+            None if cmd.words.is_empty() => Ok(Cmd::Empty),
+            None => Ok(Cmd::Simple(cmd)),
+        }
     }
 }
 
@@ -87,14 +220,24 @@ where
 pub enum CmdError {
     BadToken(Token),
     MissingName,
+    MissingTarget,
     SubShell,
+    Syntax(String),
+    /// A variable that isn't set was used with `nounset` on.
+    Unbound(String),
+    /// The text of a `${...}` that isn't understood.
+    BadSubstitution(String),
 }
 impl fmt::Display for CmdError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             CmdError::BadToken(t) => write!(f, "invalid token recieved: {:?}", t),
             CmdError::MissingName => f.write_str("a name is needed to call it"),
+            CmdError::MissingTarget => f.write_str("expected a file to redirect to"),
             CmdError::SubShell => f.write_str("Error in subshell. This could recurse."),
+            CmdError::Syntax(msg) => write!(f, "syntax error: {}", msg),
+            CmdError::Unbound(name) => write!(f, "{}: unbound variable", name),
+            CmdError::BadSubstitution(text) => write!(f, "${{{}}}: bad substitution", text),
         }
     }
 }
@@ -103,20 +246,58 @@ impl Context for CmdError {}
 /// If you want to create one
 #[derive(Debug, PartialEq)]
 pub enum Cmd {
-    Simple(SimpleCmd),
-    Pipeline(Box<Cmd>, Box<Cmd>),
+    Simple(RawCmd),
+    /// Commands joined by `|`. There are always at least two.
+    Pipeline(Vec<Cmd>),
     And(Box<Cmd>, Box<Cmd>),
     Or(Box<Cmd>, Box<Cmd>),
     Not(Box<Cmd>),
+    /// `[[ expression ]]` and its redirections
+    Cond(CondExpr, Vec<Redirect<Word>>),
     Empty,
 }
 
+/// A word as it was read, before it is expanded.
+pub type Word = Vec<Expand>;
+
+/// A simple command as it was read. The words are only expanded right before
+/// it runs so they see what the commands before it did, like the `$?` they
+/// set or a `cd` changing `$PWD`.
+#[derive(Debug, Default, PartialEq)]
+pub struct RawCmd {
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect<Word>>,
+}
+
 /// The most basic command - it, its arguments, and its redirections.
 #[derive(Debug, Default, PartialEq)]
 pub struct SimpleCmd {
     pub cmd: String,
     pub args: Vec<String>,
     pub env: StaticMap<String, String>,
+    pub redirects: Vec<Redirect>,
+}
+
+/// Changes a file descriptor of a command before it runs. These are applied
+/// in the order they are written.
+#[derive(Debug, PartialEq)]
+pub struct Redirect<T = String> {
+    /// The fd of the command that is changed.
+    pub fd: RawFd,
+    pub kind: RedirectKind,
+    /// The file to open or for duplications the fd to copy. A `-` closes
+    /// the fd instead.
+    pub target: T,
+}
+
+impl Redirect<Word> {
+    pub fn expand(self, state: &ShellState) -> Result<Redirect, CmdError> {
+        Ok(Redirect {
+            fd: self.fd,
+            kind: self.kind,
+            target: crate::walker::expand_word(self.target, state)?,
+        })
+    }
 }
 
 #[derive(Debug, Default)]
@@ -147,6 +328,45 @@ pub struct Streams {
     pub stderr: Fd,
 }
 
+impl Fd {
+    pub fn try_clone(&self) -> std::io::Result<Fd> {
+        match self {
+            Fd::Inherit => Ok(Fd::Inherit),
+            Fd::Piped(f) => f.try_clone().map(Fd::Piped),
+        }
+    }
+}
+
+impl Streams {
+    /// Copies of these streams for another command, like the left side of
+    /// `&&`.
+    pub fn try_clone(&self) -> std::io::Result<Streams> {
+        Ok(Streams {
+            stdin: self.stdin.try_clone()?,
+            stdout: self.stdout.try_clone()?,
+            stderr: self.stderr.try_clone()?,
+        })
+    }
+}
+
+impl RawCmd {
+    /// Expands the words and the targets of the redirections.
+    pub fn expand(self, state: &ShellState) -> Result<SimpleCmd, CmdError> {
+        let mut cmd = SimpleCmd::default();
+        for word in self.words {
+            for field in crate::walker::expand_glob(word, state)? {
+                cmd.push_ident(field);
+            }
+        }
+        cmd.redirects = self
+            .redirects
+            .into_iter()
+            .map(|r| r.expand(state))
+            .collect::<Result<_, _>>()?;
+        Ok(cmd)
+    }
+}
+
 impl SimpleCmd {
     pub fn push_ident(&mut self, ident: String) {
         if self.cmd.is_empty() {
@@ -155,13 +375,25 @@ impl SimpleCmd {
             self.args.push(ident);
         }
     }
+}
 
-    pub fn build(self) -> Cmd {
-        if self.cmd.is_empty() {
-            Cmd::Empty
-        } else {
-            Cmd::Simple(self)
-        }
+/// Expands the words of a `[[` expression. The right side of a pattern match
+/// has its quoted parts escaped.
+pub fn expand_cond(expr: CondExpr, state: &ShellState) -> Result<CondExpr<String>, CmdError> {
+    let mut err = None;
+    let expr = expr.map(&mut |w, pattern| {
+        let word = match pattern {
+            true => crate::walker::expand_pattern(w, state),
+            false => crate::walker::expand_word(w, state),
+        };
+        word.unwrap_or_else(|e| {
+            err.get_or_insert(e);
+            String::new()
+        })
+    });
+    match err {
+        Some(e) => Err(e),
+        None => Ok(expr),
     }
 }
 
@@ -300,6 +532,10 @@ impl SimpleCmd {
 #[derive(Default)]
 pub(crate) struct Prompter {
     commads: Option<Parser<Lexer<OwnedCharBuffer>>>,
+    editor: Box<Editor>,
+    /// If `HISTFILE` has been read. This waits for the first prompt so the
+    /// history variables can be set first.
+    loaded: bool,
 }
 
 impl Prompter {
@@ -309,8 +545,15 @@ impl Prompter {
                 return Some(cmd);
             }
 
+            if !std::mem::replace(&mut self.loaded, true) {
+                state.load_history();
+            }
+
+            state.run_precmd();
+            let prompt = Prompt::new(state);
+
             crossterm::terminal::enable_raw_mode().unwrap();
-            let res = read_line("$> ", state);
+            let res = read_line(&prompt, state, &mut self.editor);
             crossterm::terminal::disable_raw_mode().unwrap();
 
             let line = match res {
@@ -326,6 +569,13 @@ impl Prompter {
                     continue;
                 }
             };
+            let line = match expand_history(line, state) {
+                Some(line) => line,
+                None => continue,
+            };
+            if state.option(Opt::Verbose) {
+                eprint!("{}", line);
+            }
             state.add_history(line.trim_end());
             log::info!("got line: {}", line.trim());
 
@@ -335,261 +585,29 @@ impl Prompter {
     }
 }
 
-#[derive(Debug)]
-enum PromptError {
-    /// Temporary value used to when for whatever reason I have not made the
-    /// code yet. This is better than a panic beacuse we restore the terminal
-    /// before continuing
-    Unimplemented(&'static str),
-
-    /// Error when writing data
-    Write,
-}
-impl fmt::Display for PromptError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            PromptError::Unimplemented(msg) => write!(f, "unimplemented!({:?})", msg),
-            PromptError::Write => f.write_str("failed to write data"),
-        }
-    }
-}
-impl Context for PromptError {}
-
-#[derive(Debug, Default, Clone)]
-struct LineBuffer {
-    /// Buffer that data is written to
-    buf: Vec<char>,
-    /// Position of cursor, if none then the cursor is at the end. Repersents
-    /// the distance from the left edge. Aka the start of the buffer is 0.
-    pos: Option<usize>,
-}
-
-impl fmt::Display for LineBuffer {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for c in self.buf.iter() {
-            std::fmt::Write::write_char(f, *c)?;
-        }
-        Ok(())
-    }
-}
-impl LineBuffer {
-    /// Adds a character to the buffer. Returns true if a render is needed.
-    fn push(&mut self, c: char) -> InsertResult {
-        if let Some(ofst) = self.pos.as_mut() {
-            if *ofst > self.buf.len() {
-                unreachable!("cursor out of buffer")
-            } else {
-                self.buf.insert(*ofst, c);
-                *ofst += 1;
-                InsertResult::Render
-            }
-        } else {
-            self.buf.push(c);
-            InsertResult::Render
-        }
-    }
-
-    /// Removes the character directly to the left of the cursor. Returns true
-    /// if a render is needed.
-    fn pop(&mut self) -> InsertResult {
-        if let Some(ofst) = self.pos.as_mut() {
-            if *ofst == 0 {
-                // there is nothing to remove at the start of the word
-                InsertResult::None
-            } else {
-                self.buf.remove(*ofst - 1);
-                *ofst -= 1;
-                InsertResult::Render
-            }
-        } else {
-            self.buf.pop();
-            InsertResult::Render
-        }
-    }
-
-    fn enter(&mut self) -> InsertResult {
-        if self.pos.is_none() {
-            self.buf.push('\n');
-            InsertResult::Done
-        } else {
-            todo!()
-        }
-    }
-
-    fn left(&mut self) -> InsertResult {
-        if let Some(ofst) = self.pos.as_mut() {
-            if *ofst == 0 {
-                InsertResult::None
-            } else {
-                *ofst -= 1;
-                InsertResult::Render
-            }
-        } else if self.buf.is_empty() {
-            InsertResult::None
-        } else {
-            self.pos = Some(self.buf.len() - 1);
-            InsertResult::Render
-        }
-    }
-
-    fn right(&mut self) -> InsertResult {
-        if let Some(ofst) = self.pos.as_mut() {
-            *ofst += 1;
-            if *ofst >= self.buf.len() {
-                self.pos = None;
-            }
-            InsertResult::Render
-        } else {
-            InsertResult::None
-        }
+/// Expands `!` in a typed line. The line is shown on stderr when it changed,
+/// like bash, so it isn't mixed into the output of commands. Nothing is given
+/// back when it shouldn't be run.
+fn expand_history(line: String, state: &mut ShellState) -> Option<String> {
+    if !state.option(Opt::Histexpand) {
+        return Some(line);
     }
-
-    /// Sets the buffer to the specified buffer
-    fn set(&mut self, buf: &str) -> InsertResult {
-        self.buf = buf.chars().collect();
-        if let Some(ofst) = self.pos {
-            if ofst >= self.buf.len() {
-                self.pos = None;
+    match expand(&line, state.history()) {
+        Ok(None) => Some(line),
+        Ok(Some(expanded)) => {
+            eprintln!("{}", expanded.line.trim_end());
+            if expanded.print {
+                state.add_history(expanded.line.trim_end());
+                return None;
             }
+            Some(expanded.line)
         }
-        InsertResult::Render
-    }
-}
-
-enum InsertResult {
-    Render,
-    Done,
-    None,
-}
-
-enum ReadlineOutput {
-    Line(String),
-    /// When C-d is pressed on an empty time
-    Eof,
-    /// Corisponds to C-c
-    Exit,
-}
-
-/// Expects the terminal to be in raw mod when called.
-fn read_line(prompt: &str, state: &mut ShellState) -> Result<ReadlineOutput, PromptError> {
-    let mut stdout = std::io::stdout();
-
-    let mut buff = LineBuffer::default();
-
-    let mut hist = 0usize;
-
-    render_line(&mut stdout, prompt, &buff).unwrap();
-
-    use crossterm::event::Event as E;
-    use crossterm::event::KeyCode as K;
-    use crossterm::event::KeyModifiers as Km;
-
-    while let Ok(read) = crossterm::event::read() {
-        let result = match read {
-            E::Key(k) => match (k.code, k.modifiers) {
-                (K::Backspace, _) => buff.pop(),
-                (K::Enter, _) => {
-                    if buff.pos.is_some() {
-                        return Err(Report::new(PromptError::Unimplemented(
-                            "handle new line in middle of word",
-                        )));
-                    }
-
-                    buff.enter()
-                }
-                (K::Char(ch), Km::NONE) => buff.push(ch),
-                (K::Char(ch), Km::SHIFT) => buff.push(ch.to_ascii_uppercase()),
-                (K::Char('c'), Km::CONTROL) => {
-                    return Ok(ReadlineOutput::Exit);
-                }
-                (K::Char('d'), Km::CONTROL) => {
-                    if buff.buf.is_empty() {
-                        return Ok(ReadlineOutput::Eof);
-                    }
-                    InsertResult::None
-                }
-                (K::Char('l'), Km::CONTROL) => {
-                    // the call to render flushes these changes
-                    crossterm::queue!(
-                        stdout,
-                        crossterm::cursor::MoveTo(0, 0),
-                        crossterm::terminal::Clear(crossterm::terminal::ClearType::All)
-                    )
-                    .change_context(PromptError::Write)?;
-
-                    InsertResult::Render
-                }
-
-                (K::Left, _) => buff.left(),
-                (K::Right, _) => buff.right(),
-                (K::Up, _) => {
-                    hist += 1;
-                    if let Some(p) = state.get_history(hist) {
-                        buff.set(p);
-                        InsertResult::Render
-                    } else {
-                        hist -= 1;
-                        InsertResult::None
-                    }
-                }
-                (crossterm::event::KeyCode::Down, _) => {
-                    hist = hist.saturating_sub(1);
-                    if let Some(s) = state.get_history(hist) {
-                        buff.set(s)
-                    } else if !buff.buf.is_empty() {
-                        buff.set("")
-                    } else {
-                        InsertResult::None
-                    }
-                }
-                // crossterm::event::KeyCode::Tab => todo!(),
-                // crossterm::event::KeyCode::BackTab => todo!(),
-                (K::Esc, _) => {
-                    return Ok(ReadlineOutput::Eof);
-                }
-
-                // Most keys no one cares about
-                _ => InsertResult::None,
-            },
-            E::Paste(_) => unimplemented!(),
-            E::Resize(_, _) => InsertResult::Render,
-            E::FocusGained | E::FocusLost | E::Mouse(_) => InsertResult::None,
-        };
-
-        match result {
-            InsertResult::Render => {
-                render_line(&mut stdout, prompt, &buff).unwrap();
-            }
-            InsertResult::Done => {
-                break;
-            }
-            InsertResult::None => {}
+        Err(e) => {
+            eprintln!("rush: {}", e);
+            None
         }
     }
-
-    print!("\r\n");
-
-    // buff implements `Display`
-    Ok(ReadlineOutput::Line(ToString::to_string(&buff)))
 }
 
-fn render_line(
-    stdout: &mut std::io::Stdout,
-    prompt: &str,
-    line: &LineBuffer,
-) -> Result<(), PromptError> {
-    let pos = line.pos.unwrap_or(line.buf.len()) + prompt.len();
-    let pos = pos as u16;
-
-    crossterm::execute!(
-        stdout,
-        // clear the line
-        crossterm::cursor::MoveToColumn(0),
-        crossterm::terminal::Clear(crossterm::terminal::ClearType::UntilNewLine),
-        // write the new line
-        crossterm::style::Print(format!("{}{}", prompt, line)),
-        // put the cursor where we want it
-        crossterm::cursor::MoveToColumn(pos),
-    )
-    .change_context(PromptError::Write)
-}
+mod line;
+pub(crate) mod prompt;
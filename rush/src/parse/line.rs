@@ -0,0 +1,1116 @@
+//! The line editor used by [`super::Prompter`]. The keys follow readline's
+//! emacs mode, or vi mode with `set -o vi`.
+
+use self::highlight::{paint, styles, Theme};
+use self::menu::Menu;
+use self::vi::{Step, Vi};
+use crate::prelude::*;
+use crate::shell::history::Entry;
+use crate::shell::options::Opt;
+
+use super::prompt::{printable, prompt_width, visible, Prompt};
+use rush_core::lexer::is_complete;
+
+use crossterm::event::Event as E;
+use crossterm::event::KeyCode as K;
+use crossterm::event::KeyEvent;
+use crossterm::event::KeyModifiers as Km;
+
+#[derive(Debug)]
+pub(super) enum PromptError {
+    /// Error when writing data
+    Write,
+}
+impl fmt::Display for PromptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PromptError::Write => f.write_str("failed to write data"),
+        }
+    }
+}
+impl Context for PromptError {}
+
+/// What the line editor keeps from one line to the next.
+#[derive(Debug, Default)]
+pub(super) struct Editor {
+    ring: KillRing,
+    vi: Vi,
+}
+
+/// Text cut with keys like C-k that can be put back with C-y. It is kept
+/// between lines.
+#[derive(Debug, Default)]
+pub(super) struct KillRing {
+    /// The newest kill is at the end.
+    kills: Vec<String>,
+    /// If the next kill is added on to the last one. This is true while kill
+    /// keys are pressed one after another.
+    joining: bool,
+}
+
+impl KillRing {
+    /// How many kills are remembered.
+    const MAX: usize = 32;
+
+    /// Saves some killed text. `backward` is for text that was before the
+    /// cursor so it goes in front when joined.
+    fn kill(&mut self, text: String, backward: bool) {
+        if text.is_empty() {
+            return;
+        }
+
+        match self.kills.last_mut() {
+            Some(last) if self.joining && backward => last.insert_str(0, &text),
+            Some(last) if self.joining => last.push_str(&text),
+            _ => {
+                self.kills.push(text);
+                if self.kills.len() > Self::MAX {
+                    self.kills.remove(0);
+                }
+            }
+        }
+        self.joining = true;
+    }
+
+    /// Stops the next kill from joining the last one.
+    fn seal(&mut self) {
+        self.joining = false;
+    }
+
+    fn top(&self) -> Option<&str> {
+        self.kills.last().map(String::as_str)
+    }
+
+    /// Moves the newest kill to the back so the one before it is on top.
+    fn rotate(&mut self) {
+        if let Some(k) = self.kills.pop() {
+            self.kills.insert(0, k);
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct LineBuffer {
+    /// Buffer that data is written to
+    buf: Vec<char>,
+    /// Position of cursor, if none then the cursor is at the end. Repersents
+    /// the distance from the left edge. Aka the start of the buffer is 0.
+    pos: Option<usize>,
+    /// Where the last yank was put and how long it was so C-y can be followed
+    /// by M-y.
+    yanked: Option<(usize, usize)>,
+}
+
+impl fmt::Display for LineBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.buf.iter() {
+            std::fmt::Write::write_char(f, *c)?;
+        }
+        Ok(())
+    }
+}
+
+/// Words for moving and M-d are made of letters and numbers like readline.
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+impl LineBuffer {
+    /// Adds a character to the buffer. Returns true if a render is needed.
+    fn push(&mut self, c: char) -> InsertResult {
+        if let Some(ofst) = self.pos.as_mut() {
+            if *ofst > self.buf.len() {
+                unreachable!("cursor out of buffer")
+            } else {
+                self.buf.insert(*ofst, c);
+                *ofst += 1;
+                InsertResult::Render
+            }
+        } else {
+            self.buf.push(c);
+            InsertResult::Render
+        }
+    }
+
+    /// Removes the character directly to the left of the cursor. Returns true
+    /// if a render is needed.
+    fn pop(&mut self) -> InsertResult {
+        if let Some(ofst) = self.pos.as_mut() {
+            if *ofst == 0 {
+                // there is nothing to remove at the start of the word
+                InsertResult::None
+            } else {
+                self.buf.remove(*ofst - 1);
+                *ofst -= 1;
+                InsertResult::Render
+            }
+        } else {
+            self.buf.pop();
+            InsertResult::Render
+        }
+    }
+
+    /// Removes the character under the cursor.
+    fn delete(&mut self) -> InsertResult {
+        let at = self.cursor();
+        if at >= self.buf.len() {
+            return InsertResult::None;
+        }
+        self.buf.remove(at);
+        self.move_to(at);
+        InsertResult::Render
+    }
+
+    /// Finishes the line. The cursor doesn't have to be at the end.
+    fn enter(&mut self) -> InsertResult {
+        self.pos = None;
+        InsertResult::Done
+    }
+
+    /// Continues the command on a new line, wherever the cursor was.
+    fn newline(&mut self) -> InsertResult {
+        self.end();
+        self.push('\n')
+    }
+
+    /// Where the line that `at` is on starts. A buffer has more than one
+    /// line when a command is continued.
+    fn line_start(&self, at: usize) -> usize {
+        self.buf[..at]
+            .iter()
+            .rposition(|c| *c == '\n')
+            .map_or(0, |i| i + 1)
+    }
+
+    /// Where the line that `at` is on ends, before its newline.
+    fn line_end(&self, at: usize) -> usize {
+        self.buf[at..]
+            .iter()
+            .position(|c| *c == '\n')
+            .map_or(self.buf.len(), |i| at + i)
+    }
+
+    /// Moves to the line above keeping the column if it is long enough.
+    /// Nothing happens on the first line.
+    fn up(&mut self) -> InsertResult {
+        let at = self.cursor();
+        let start = self.line_start(at);
+        if start == 0 {
+            return InsertResult::None;
+        }
+        let above = self.line_start(start - 1);
+        self.move_to(above + (at - start).min(start - 1 - above))
+    }
+
+    /// Moves to the line below keeping the column if it is long enough.
+    /// Nothing happens on the last line.
+    fn down(&mut self) -> InsertResult {
+        let at = self.cursor();
+        let end = self.line_end(at);
+        if end == self.buf.len() {
+            return InsertResult::None;
+        }
+        let col = at - self.line_start(at);
+        self.move_to((end + 1 + col).min(self.line_end(end + 1)))
+    }
+
+    fn left(&mut self) -> InsertResult {
+        if let Some(ofst) = self.pos.as_mut() {
+            if *ofst == 0 {
+                InsertResult::None
+            } else {
+                *ofst -= 1;
+                InsertResult::Render
+            }
+        } else if self.buf.is_empty() {
+            InsertResult::None
+        } else {
+            self.pos = Some(self.buf.len() - 1);
+            InsertResult::Render
+        }
+    }
+
+    fn right(&mut self) -> InsertResult {
+        if let Some(ofst) = self.pos.as_mut() {
+            *ofst += 1;
+            if *ofst >= self.buf.len() {
+                self.pos = None;
+            }
+            InsertResult::Render
+        } else {
+            InsertResult::None
+        }
+    }
+
+    /// Where the cursor is counting from the start.
+    fn cursor(&self) -> usize {
+        self.pos.unwrap_or(self.buf.len())
+    }
+
+    /// Puts the cursor somewhere. Anything past the end is the end.
+    fn move_to(&mut self, at: usize) -> InsertResult {
+        let pos = (at < self.buf.len()).then_some(at);
+        if pos == self.pos {
+            return InsertResult::None;
+        }
+        self.pos = pos;
+        InsertResult::Render
+    }
+
+    fn home(&mut self) -> InsertResult {
+        self.move_to(0)
+    }
+
+    fn end(&mut self) -> InsertResult {
+        self.move_to(self.buf.len())
+    }
+
+    /// Where the word the cursor is in, or the one before it, starts.
+    fn word_start(&self) -> usize {
+        let mut i = self.cursor();
+        while i > 0 && !is_word(self.buf[i - 1]) {
+            i -= 1;
+        }
+        while i > 0 && is_word(self.buf[i - 1]) {
+            i -= 1;
+        }
+        i
+    }
+
+    /// Where the word the cursor is in, or the one after it, ends.
+    fn word_end(&self) -> usize {
+        let mut i = self.cursor();
+        while i < self.buf.len() && !is_word(self.buf[i]) {
+            i += 1;
+        }
+        while i < self.buf.len() && is_word(self.buf[i]) {
+            i += 1;
+        }
+        i
+    }
+
+    fn word_left(&mut self) -> InsertResult {
+        self.move_to(self.word_start())
+    }
+
+    fn word_right(&mut self) -> InsertResult {
+        self.move_to(self.word_end())
+    }
+
+    /// Swaps the characters on either side of the cursor then moves forward.
+    /// At the end of the line the last two are swapped.
+    fn transpose(&mut self) -> InsertResult {
+        let at = self.cursor();
+        if at == 0 || self.buf.len() < 2 {
+            return InsertResult::None;
+        }
+        let at = at.min(self.buf.len() - 1);
+        self.buf.swap(at - 1, at);
+        self.move_to(at + 1);
+        InsertResult::Render
+    }
+
+    /// Cuts out the text between two places into the kill ring.
+    fn kill(&mut self, from: usize, to: usize, ring: &mut KillRing) -> InsertResult {
+        if from >= to {
+            return InsertResult::None;
+        }
+        let backward = to == self.cursor();
+        let text: String = self.buf.drain(from..to).collect();
+        ring.kill(text, backward);
+        self.move_to(from);
+        InsertResult::Render
+    }
+
+    fn kill_to_start(&mut self, ring: &mut KillRing) -> InsertResult {
+        self.kill(0, self.cursor(), ring)
+    }
+
+    fn kill_to_end(&mut self, ring: &mut KillRing) -> InsertResult {
+        self.kill(self.cursor(), self.buf.len(), ring)
+    }
+
+    /// Kills back to the last space like C-w does.
+    fn kill_space_word(&mut self, ring: &mut KillRing) -> InsertResult {
+        let end = self.cursor();
+        let mut i = end;
+        while i > 0 && self.buf[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        while i > 0 && !self.buf[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        self.kill(i, end, ring)
+    }
+
+    fn kill_word(&mut self, ring: &mut KillRing) -> InsertResult {
+        self.kill(self.cursor(), self.word_end(), ring)
+    }
+
+    /// Puts the newest kill in at the cursor.
+    fn yank(&mut self, ring: &KillRing) -> InsertResult {
+        let Some(text) = ring.top() else {
+            return InsertResult::None;
+        };
+        let at = self.cursor();
+        let len = text.chars().count();
+        self.buf.splice(at..at, text.chars());
+        self.move_to(at + len);
+        self.yanked = Some((at, len));
+        InsertResult::Render
+    }
+
+    /// Swaps the text that was just yanked for the kill before it.
+    fn yank_pop(&mut self, ring: &mut KillRing) -> InsertResult {
+        let Some((at, len)) = self.yanked else {
+            return InsertResult::None;
+        };
+        self.buf.drain(at..at + len);
+        self.move_to(at);
+        ring.rotate();
+        self.yank(ring)
+    }
+
+    /// Sets the buffer to the specified buffer
+    fn set(&mut self, buf: &str) -> InsertResult {
+        self.buf = buf.chars().collect();
+        if let Some(ofst) = self.pos {
+            if ofst >= self.buf.len() {
+                self.pos = None;
+            }
+        }
+        InsertResult::Render
+    }
+}
+
+enum InsertResult {
+    Render,
+    Done,
+    None,
+}
+
+pub(super) enum ReadlineOutput {
+    Line(String),
+    /// When C-d is pressed on an empty time
+    Eof,
+    /// Corisponds to C-c
+    Exit,
+}
+
+/// Expects the terminal to be in raw mod when called.
+pub(super) fn read_line(
+    prompt: &Prompt,
+    state: &mut ShellState,
+    editor: &mut Editor,
+) -> Result<ReadlineOutput, PromptError> {
+    let mut stdout = std::io::stdout();
+
+    let mut buff = LineBuffer::default();
+
+    let mut browse = Browse::default();
+
+    let mut menu: Option<Menu> = None;
+
+    let mut screen = Screen {
+        more: prompt.more.clone(),
+        right: prompt.right.clone(),
+        row: 0,
+    };
+    let prompt = prompt.left.as_str();
+
+    let vi = state.option(Opt::Vi);
+    let mut insert = true;
+    if vi {
+        editor.vi.start(&buff);
+        crossterm::queue!(stdout, cursor_style(insert)).change_context(PromptError::Write)?;
+    }
+
+    screen.draw(&mut stdout, prompt, &buff, None, Some(state))?;
+
+    // a key that ended a search is handled after it
+    let mut pending = None;
+    loop {
+        let read = match pending.take() {
+            Some(k) => E::Key(k),
+            None => match crossterm::event::read() {
+                Ok(read) => read,
+                Err(_) => break,
+            },
+        };
+        let result = match read {
+            E::Key(k) => {
+                // any other key puts the menu away
+                if !matches!(k.code, K::Tab | K::BackTab) && menu.take().is_some() {
+                    screen.draw(&mut stdout, prompt, &buff, None, Some(state))?;
+                }
+                match (k.code, k.modifiers) {
+                    (K::Tab | K::BackTab, _) => match &mut menu {
+                        Some(m) => m.cycle(&mut buff, k.code == K::Tab),
+                        None => {
+                            let (m, result) = Menu::complete(&mut buff, state);
+                            menu = m;
+                            result
+                        }
+                    },
+                    // a command that isn't finished goes on to another line
+                    (K::Enter, _) if !is_complete(&buff.to_string()) => buff.newline(),
+                    (K::Enter, _) => buff.enter(),
+                    (K::Char('c'), Km::CONTROL) => {
+                        buff.end();
+                        screen.draw(&mut stdout, prompt, &buff, None, Some(state))?;
+                        return Ok(ReadlineOutput::Exit);
+                    }
+                    (K::Char('d'), Km::CONTROL) if buff.buf.is_empty() => {
+                        return Ok(ReadlineOutput::Eof);
+                    }
+                    (K::Char('l'), Km::CONTROL) => {
+                        // the call to render flushes these changes
+                        crossterm::queue!(
+                            stdout,
+                            crossterm::cursor::MoveTo(0, 0),
+                            crossterm::terminal::Clear(crossterm::terminal::ClearType::All)
+                        )
+                        .change_context(PromptError::Write)?;
+                        screen.row = 0;
+
+                        InsertResult::Render
+                    }
+                    (K::Char('r' | 's'), Km::CONTROL) => {
+                        let older = k.code == K::Char('r');
+                        pending = search(
+                            &mut stdout,
+                            &mut screen,
+                            &mut buff,
+                            &mut browse,
+                            state,
+                            older,
+                        )?;
+                        if vi {
+                            editor.vi.clamp(&mut buff);
+                        }
+                        InsertResult::Render
+                    }
+
+                    _ if vi => {
+                        let result = match editor.vi.key(k, &mut buff) {
+                            Step::Done(result) => result,
+                            Step::Emacs => {
+                                emacs_key(k, &mut buff, &mut browse, state, &mut editor.ring)
+                            }
+                            Step::History { older } => {
+                                history(&mut buff, &mut browse, state, older)
+                            }
+                            Step::Search => {
+                                if let Some(p) =
+                                    read_search(&mut stdout, &mut screen)?.filter(|p| !p.is_empty())
+                                {
+                                    editor.vi.search = Some(p);
+                                }
+                                if let Some(p) = &editor.vi.search {
+                                    search_history(&mut buff, &mut browse, state, p, true);
+                                }
+                                InsertResult::Render
+                            }
+                            Step::SearchAgain { older } => match &editor.vi.search {
+                                Some(p) => search_history(&mut buff, &mut browse, state, p, older),
+                                None => InsertResult::None,
+                            },
+                        };
+                        editor.vi.clamp(&mut buff);
+
+                        if editor.vi.is_insert() != insert {
+                            insert = !insert;
+                            crossterm::queue!(stdout, cursor_style(insert))
+                                .change_context(PromptError::Write)?;
+                        }
+                        result
+                    }
+                    _ => emacs_key(k, &mut buff, &mut browse, state, &mut editor.ring),
+                }
+            }
+            E::Paste(s) => {
+                for c in s.chars() {
+                    buff.push(c);
+                }
+                InsertResult::Render
+            }
+            E::Resize(_, _) => InsertResult::Render,
+            E::FocusGained | E::FocusLost | E::Mouse(_) => InsertResult::None,
+        };
+
+        match result {
+            InsertResult::Render => {
+                screen.draw(&mut stdout, prompt, &buff, menu.as_ref(), Some(state))?;
+            }
+            InsertResult::Done => {
+                // the cursor is moved to the end so nothing is written over
+                screen.draw(&mut stdout, prompt, &buff, None, Some(state))?;
+                break;
+            }
+            InsertResult::None => {}
+        }
+    }
+
+    if vi {
+        crossterm::queue!(stdout, crossterm::cursor::SetCursorStyle::DefaultUserShape)
+            .change_context(PromptError::Write)?;
+    }
+    print!("\r\n");
+
+    Ok(ReadlineOutput::Line(format!("{}\n", buff)))
+}
+
+/// Handles a key the way emacs mode does.
+fn emacs_key(
+    k: KeyEvent,
+    buff: &mut LineBuffer,
+    browse: &mut Browse,
+    state: &ShellState,
+    ring: &mut KillRing,
+) -> InsertResult {
+    let killing = matches!(
+        (k.code, k.modifiers),
+        (K::Char('w' | 'u' | 'k'), Km::CONTROL) | (K::Char('d'), Km::ALT)
+    );
+    let yanking = matches!((k.code, k.modifiers), (K::Char('y'), Km::CONTROL | Km::ALT));
+    if !killing {
+        ring.seal();
+    }
+    if !yanking {
+        buff.yanked = None;
+    }
+
+    match (k.code, k.modifiers) {
+        (K::Backspace, _) => buff.pop(),
+        (K::Delete, _) => buff.delete(),
+        (K::Char(ch), Km::NONE) => buff.push(ch),
+        (K::Char(ch), Km::SHIFT) => buff.push(ch.to_ascii_uppercase()),
+        (K::Char('d'), Km::CONTROL) => buff.delete(),
+
+        (K::Char('a'), Km::CONTROL) | (K::Home, _) => buff.home(),
+        (K::Char('e'), Km::CONTROL) | (K::End, _) => buff.end(),
+        (K::Char('b'), Km::ALT) | (K::Left, Km::CONTROL) => buff.word_left(),
+        (K::Char('f'), Km::ALT) | (K::Right, Km::CONTROL) => buff.word_right(),
+        (K::Char('b'), Km::CONTROL) | (K::Left, _) => buff.left(),
+        (K::Char('f'), Km::CONTROL) | (K::Right, _) => buff.right(),
+        (K::Char('t'), Km::CONTROL) => buff.transpose(),
+
+        (K::Char('w'), Km::CONTROL) => buff.kill_space_word(ring),
+        (K::Char('u'), Km::CONTROL) => buff.kill_to_start(ring),
+        (K::Char('k'), Km::CONTROL) => buff.kill_to_end(ring),
+        (K::Char('d'), Km::ALT) => buff.kill_word(ring),
+        (K::Char('y'), Km::CONTROL) => buff.yank(ring),
+        (K::Char('y'), Km::ALT) => buff.yank_pop(ring),
+
+        // Up and Down go between the lines of a continued command first
+        (K::Up, _) => match buff.up() {
+            InsertResult::None => history(buff, browse, state, true),
+            moved => moved,
+        },
+        (K::Down, _) => match buff.down() {
+            InsertResult::None => history(buff, browse, state, false),
+            moved => moved,
+        },
+        // Most keys no one cares about
+        _ => InsertResult::None,
+    }
+}
+
+/// Where Up and Down have got to in the history.
+#[derive(Debug, Default)]
+struct Browse {
+    /// The number of the entry in the line. `None` is the line being typed.
+    at: Option<usize>,
+    /// The line being typed. Up and Down only go to entries that start with
+    /// it.
+    typed: String,
+    /// The entry that was put in the line, to tell if it was changed since.
+    shown: String,
+}
+
+impl Browse {
+    /// Puts an entry in the line. What was typed is kept so it can be gone
+    /// back to.
+    fn show(&mut self, buff: &mut LineBuffer, entry: &Entry) {
+        if self.at.is_none() {
+            self.typed = buff.to_string();
+        }
+        self.at = Some(entry.number);
+        self.shown = entry.line.clone();
+        buff.set(&entry.line);
+        buff.end();
+    }
+}
+
+/// Moves back or forward to the next entry that starts with what was typed.
+/// Entries that are the same as the line are skipped.
+fn history(
+    buff: &mut LineBuffer,
+    browse: &mut Browse,
+    state: &ShellState,
+    older: bool,
+) -> InsertResult {
+    let line = buff.to_string();
+    if browse.at.is_some() && line != browse.shown {
+        // an entry that was changed is like a newly typed line
+        browse.at = None;
+    }
+    if browse.at.is_none() {
+        browse.typed = line.clone();
+    }
+
+    let hist = state.history();
+    let prefix = browse.typed.as_str();
+    let mut found = match (browse.at, older) {
+        (at, true) => hist.before(at, prefix),
+        (Some(at), false) => hist.after(at, prefix),
+        (None, false) => return InsertResult::None,
+    };
+    while let Some(same) = found.filter(|e| e.line == line) {
+        found = match older {
+            true => hist.before(Some(same.number), prefix),
+            false => hist.after(same.number, prefix),
+        };
+    }
+
+    match found {
+        Some(entry) => {
+            browse.show(buff, entry);
+            InsertResult::Render
+        }
+        None if older => InsertResult::None,
+        None => {
+            // past the newest entry is the line that was being typed
+            browse.at = None;
+            buff.set(&browse.typed);
+            buff.end()
+        }
+    }
+}
+
+/// Goes to the next line in the history with `pattern` in it. The cursor is
+/// put at the start like vi does.
+fn search_history(
+    buff: &mut LineBuffer,
+    browse: &mut Browse,
+    state: &ShellState,
+    pattern: &str,
+    older: bool,
+) -> InsertResult {
+    match state.history().search(browse.at, pattern, older) {
+        Some(entry) => {
+            browse.show(buff, entry);
+            buff.home()
+        }
+        None => InsertResult::None,
+    }
+}
+
+/// Searches the history as a pattern is typed, for C-r and C-s. C-r and
+/// C-s go to the next match and C-g gives up. The key that ends the search
+/// is given back to be handled like any other.
+fn search(
+    stdout: &mut std::io::Stdout,
+    screen: &mut Screen,
+    buff: &mut LineBuffer,
+    browse: &mut Browse,
+    state: &mut ShellState,
+    mut older: bool,
+) -> Result<Option<KeyEvent>, PromptError> {
+    let mut pattern = String::new();
+    let mut found: Option<Entry> = None;
+    let mut failed = false;
+    // what was found before each character so backspace can go back
+    let mut stack = Vec::new();
+
+    loop {
+        let label = match (failed, older) {
+            (false, true) => "(reverse-i-search)",
+            (false, false) => "(i-search)",
+            (true, true) => "(failed reverse-i-search)",
+            (true, false) => "(failed i-search)",
+        };
+        let mut shown = LineBuffer::default();
+        match &found {
+            Some(entry) => {
+                shown.set(&entry.line);
+                let at = match older {
+                    true => entry.line.rfind(&pattern),
+                    false => entry.line.find(&pattern),
+                };
+                let at = at.unwrap_or(0);
+                shown.move_to(entry.line[..at].chars().count());
+            }
+            None => {
+                shown.set(&buff.to_string());
+            }
+        }
+        let label = format!("{}`{}': ", label, pattern);
+        screen.draw(stdout, &label, &shown, None, Some(state))?;
+
+        let k = match crossterm::event::read() {
+            Ok(E::Key(k)) => k,
+            Ok(_) => continue,
+            Err(_) => return Ok(None),
+        };
+        let next = match (k.code, k.modifiers) {
+            (K::Char('g'), Km::CONTROL) => return Ok(None),
+            (K::Char(c @ ('r' | 's')), Km::CONTROL) => {
+                older = c == 'r';
+                if pattern.is_empty() {
+                    continue;
+                }
+                let at = found.as_ref().map(|e| e.number).or(browse.at);
+                state.history().search(at, &pattern, older).cloned()
+            }
+            (K::Backspace, _) => {
+                if let Some((f, fail)) = stack.pop() {
+                    pattern.pop();
+                    found = f;
+                    failed = fail;
+                }
+                continue;
+            }
+            (K::Char(c), Km::NONE | Km::SHIFT) => {
+                stack.push((found.clone(), failed));
+                pattern.push(c);
+                match &found {
+                    Some(entry) if entry.line.contains(&pattern) => found.clone(),
+                    _ => {
+                        let at = found.as_ref().map(|e| e.number).or(browse.at);
+                        state.history().search(at, &pattern, older).cloned()
+                    }
+                }
+            }
+            _ => {
+                if let Some(entry) = &found {
+                    browse.show(buff, entry);
+                    buff.move_to(shown.cursor());
+                }
+                return Ok(Some(k));
+            }
+        };
+
+        match next {
+            Some(entry) => {
+                found = Some(entry);
+                failed = false;
+            }
+            None => failed = true,
+        }
+    }
+}
+
+/// Reads the pattern for `/` on the prompt line. Nothing is given back if
+/// it is cancelled.
+fn read_search(
+    stdout: &mut std::io::Stdout,
+    screen: &mut Screen,
+) -> Result<Option<String>, PromptError> {
+    let mut pattern = LineBuffer::default();
+    loop {
+        screen.draw(stdout, "/", &pattern, None, None)?;
+        let k = match crossterm::event::read() {
+            Ok(E::Key(k)) => k,
+            Ok(_) => continue,
+            Err(_) => return Ok(None),
+        };
+        match (k.code, k.modifiers) {
+            (K::Enter, _) => return Ok(Some(pattern.to_string())),
+            (K::Esc, _) | (K::Char('c'), Km::CONTROL) => return Ok(None),
+            (K::Backspace, _) if pattern.buf.is_empty() => return Ok(None),
+            (K::Backspace, _) => {
+                pattern.pop();
+            }
+            (K::Char(c), Km::NONE) => {
+                pattern.push(c);
+            }
+            (K::Char(c), Km::SHIFT) => {
+                pattern.push(c.to_ascii_uppercase());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A bar while typing and a block in vi's normal mode.
+fn cursor_style(insert: bool) -> crossterm::cursor::SetCursorStyle {
+    if insert {
+        crossterm::cursor::SetCursorStyle::BlinkingBar
+    } else {
+        crossterm::cursor::SetCursorStyle::SteadyBlock
+    }
+}
+
+/// Draws the line being edited. Where the cursor was left is remembered so
+/// the next draw can go back over lines that were continued or wrapped.
+#[derive(Debug, Default)]
+struct Screen {
+    /// What lines after the first start with, from `PS2`.
+    more: String,
+    /// What is shown on the right of the first line, from `RPROMPT`.
+    right: String,
+    /// The row the cursor is on counting from the first one of the prompt.
+    row: usize,
+}
+
+impl Screen {
+    /// Draws the prompt and line. The line is colored like shell code when
+    /// there is a state to look commands up in.
+    fn draw(
+        &mut self,
+        stdout: &mut std::io::Stdout,
+        prompt: &str,
+        line: &LineBuffer,
+        menu: Option<&Menu>,
+        code: Option<&mut ShellState>,
+    ) -> Result<(), PromptError> {
+        let (width, height) = match crossterm::terminal::size() {
+            Ok((w, h)) if w > 0 && h > 0 => (w as usize, h as usize),
+            _ => (80, 24),
+        };
+        // the terminal doesn't go back to the start of a line on its own in
+        // raw mode
+        let print = |s: &str| crossterm::style::Print(printable(s).replace('\n', "\r\n"));
+
+        if self.row > 0 {
+            crossterm::queue!(stdout, crossterm::cursor::MoveUp(self.row as u16))
+                .change_context(PromptError::Write)?;
+        }
+        crossterm::queue!(
+            stdout,
+            // clear the line and any menu under it
+            crossterm::cursor::MoveToColumn(0),
+            crossterm::terminal::Clear(crossterm::terminal::ClearType::FromCursorDown),
+            print(prompt),
+        )
+        .change_context(PromptError::Write)?;
+        let mut pos = advance((0, 0), visible(prompt), width);
+
+        let lines: Vec<_> = line.buf.split(|c| *c == '\n').collect();
+        let right = prompt_width(&self.right);
+        let first = advance(pos, lines[0].iter().copied(), width);
+        if right > 0 && first.0 == pos.0 && first.1 + right + 1 < width {
+            crossterm::queue!(
+                stdout,
+                crossterm::cursor::MoveToColumn((width - right - 1) as u16),
+                print(&self.right),
+                crossterm::cursor::MoveToColumn(pos.1 as u16),
+            )
+            .change_context(PromptError::Write)?;
+        }
+
+        let colors = code.map(|state| (styles(&line.to_string(), state), Theme::new(state)));
+
+        let cursor = line.cursor();
+        let mut at = pos;
+        let mut start = 0;
+        for (i, text) in lines.into_iter().enumerate() {
+            if i > 0 {
+                crossterm::queue!(stdout, crossterm::style::Print("\r\n"), print(&self.more))
+                    .change_context(PromptError::Write)?;
+                pos = advance((pos.0 + 1, 0), visible(&self.more), width);
+            }
+            let text_out = match &colors {
+                Some((styles, theme)) => paint(text, &styles[start..start + text.len()], theme),
+                None => text.iter().collect(),
+            };
+            crossterm::queue!(stdout, crossterm::style::Print(text_out))
+                .change_context(PromptError::Write)?;
+
+            if (start..=start + text.len()).contains(&cursor) {
+                at = advance(pos, text[..cursor - start].iter().copied(), width);
+            }
+            pos = advance(pos, text.iter().copied(), width);
+            if pos.1 >= width {
+                // the terminal waits for another character before it wraps
+                crossterm::queue!(stdout, crossterm::style::Print("\r\n"))
+                    .change_context(PromptError::Write)?;
+                pos = (pos.0 + 1, 0);
+            }
+            start += text.len() + 1;
+        }
+        if at.1 >= width {
+            at = (at.0 + 1, 0);
+        }
+
+        if let Some(menu) = menu {
+            let rows = menu.rows(width, height);
+            for row in &rows {
+                crossterm::queue!(stdout, crossterm::style::Print(format!("\r\n{}", row)))
+                    .change_context(PromptError::Write)?;
+            }
+            if !rows.is_empty() {
+                crossterm::queue!(stdout, crossterm::cursor::MoveUp(rows.len() as u16))
+                    .change_context(PromptError::Write)?;
+            }
+        }
+
+        // put the cursor where we want it
+        if pos.0 > at.0 {
+            crossterm::queue!(stdout, crossterm::cursor::MoveUp((pos.0 - at.0) as u16))
+                .change_context(PromptError::Write)?;
+        }
+        self.row = at.0;
+        crossterm::execute!(stdout, crossterm::cursor::MoveToColumn(at.1 as u16))
+            .change_context(PromptError::Write)
+    }
+}
+
+/// Where the cursor is after writing some characters from `at`, as a row
+/// and column. A column of `width` is where the terminal waits to wrap.
+fn advance(at: (usize, usize), text: impl Iterator<Item = char>, width: usize) -> (usize, usize) {
+    text.fold(at, |(row, col), c| {
+        let w = crate::util::width(c);
+        match c {
+            '\n' => (row + 1, 0),
+            '\r' => (row, 0),
+            _ if col + w > width => (row + 1, w),
+            _ => (row, col + w),
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Makes a buffer with the cursor where the `|` is.
+    pub(super) fn line(s: &str) -> LineBuffer {
+        let at = s.find('|').unwrap();
+        let mut buff = LineBuffer::default();
+        buff.set(&s.replacen('|', "", 1));
+        buff.move_to(s[..at].chars().count());
+        buff
+    }
+
+    /// Shows a buffer with a `|` at the cursor.
+    pub(super) fn show(buff: &LineBuffer) -> String {
+        let mut s: String = buff.buf.iter().collect();
+        let at = s
+            .char_indices()
+            .nth(buff.cursor())
+            .map_or(s.len(), |(i, _)| i);
+        s.insert(at, '|');
+        s
+    }
+
+    #[test]
+    fn home_end() {
+        let mut b = line("echo h|i");
+        b.home();
+        assert_eq!(show(&b), "|echo hi");
+        b.end();
+        assert_eq!(show(&b), "echo hi|");
+        assert_eq!(b.pos, None);
+    }
+
+    #[test]
+    fn newline_at_end() {
+        let mut b = line("if tr|ue");
+        b.newline();
+        assert_eq!(show(&b), "if true\n|");
+    }
+
+    #[test]
+    fn word_motions() {
+        let mut b = line("cd ../some-dir|");
+        b.word_left();
+        assert_eq!(show(&b), "cd ../some-|dir");
+        b.word_left();
+        assert_eq!(show(&b), "cd ../|some-dir");
+        b.word_left();
+        assert_eq!(show(&b), "|cd ../some-dir");
+        b.word_right();
+        assert_eq!(show(&b), "cd| ../some-dir");
+        b.word_right();
+        assert_eq!(show(&b), "cd ../some|-dir");
+        b.word_right();
+        b.word_right();
+        assert_eq!(show(&b), "cd ../some-dir|");
+    }
+
+    #[test]
+    fn lines() {
+        let mut b = line("if true\n  echo a|b\nf");
+        b.up();
+        assert_eq!(show(&b), "if true|\n  echo ab\nf");
+        assert!(matches!(b.up(), InsertResult::None));
+        b.down();
+        b.down();
+        assert_eq!(show(&b), "if true\n  echo ab\nf|");
+        assert!(matches!(b.down(), InsertResult::None));
+    }
+
+    #[test]
+    fn wrapping() {
+        assert_eq!(advance((0, 3), "abcdefg".chars(), 10), (0, 10));
+        assert_eq!(advance((0, 3), "abcdefgh".chars(), 10), (1, 1));
+        assert_eq!(advance((0, 8), "日本".chars(), 10), (1, 2));
+        assert_eq!(advance((0, 8), "a\nb".chars(), 10), (1, 1));
+    }
+
+    #[test]
+    fn delete() {
+        let mut b = line("ab|c");
+        b.delete();
+        assert_eq!(show(&b), "ab|");
+        assert!(matches!(b.delete(), InsertResult::None));
+    }
+
+    #[test]
+    fn transpose() {
+        let mut b = line("a|bc");
+        b.transpose();
+        assert_eq!(show(&b), "ba|c");
+        let mut b = line("abc|");
+        b.transpose();
+        assert_eq!(show(&b), "acb|");
+        let mut b = line("|abc");
+        assert!(matches!(b.transpose(), InsertResult::None));
+    }
+
+    #[test]
+    fn kill_and_yank() {
+        let mut ring = KillRing::default();
+        let mut b = line("echo foo bar|");
+        b.kill_space_word(&mut ring);
+        assert_eq!(show(&b), "echo foo |");
+        // kills one after another join up
+        b.kill_space_word(&mut ring);
+        assert_eq!(show(&b), "echo |");
+        assert_eq!(ring.top(), Some("foo bar"));
+
+        ring.seal();
+        b.home();
+        b.kill_to_end(&mut ring);
+        assert_eq!(show(&b), "|");
+        assert_eq!(ring.top(), Some("echo "));
+
+        b.yank(&ring);
+        assert_eq!(show(&b), "echo |");
+        b.yank_pop(&mut ring);
+        assert_eq!(show(&b), "foo bar|");
+    }
+
+    #[test]
+    fn kill_lines() {
+        let mut ring = KillRing::default();
+        let mut b = line("ls -l| src");
+        b.kill_to_start(&mut ring);
+        assert_eq!(show(&b), "| src");
+        assert_eq!(ring.top(), Some("ls -l"));
+
+        ring.seal();
+        let mut b = line("git |commit -m");
+        b.kill_word(&mut ring);
+        assert_eq!(show(&b), "git | -m");
+        b.kill_word(&mut ring);
+        assert_eq!(show(&b), "git |");
+        assert_eq!(ring.top(), Some("commit -m"));
+    }
+}
+
+mod highlight;
+mod menu;
+mod vi;
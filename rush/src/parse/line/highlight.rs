@@ -0,0 +1,265 @@
+//! Colors the line as it is typed. The line is split up by the lexer so
+//! commands, strings, variables, operators and comments can each have their
+//! own color. Commands that can't be found are shown as errors and quotes
+//! and parens that aren't closed are underlined.
+//!
+//! The colors are set with `RUSH_COLORS` in the same form as `GREP_COLORS`,
+//! like `command=1;32:string=33`. Each is the parameters of an SGR escape
+//! and an empty one leaves that kind plain.
+
+use crate::drive::builtins::KEYWORDS;
+use crate::prelude::*;
+
+use rush_core::lexer::{spans, Token};
+use std::fmt::Write;
+
+/// What a part of the line is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Kind {
+    Command,
+    /// A command that can't be found.
+    Error,
+    String,
+    Variable,
+    Operator,
+    Comment,
+}
+
+/// How a character in the line is drawn.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Style {
+    pub kind: Option<Kind>,
+    /// Part of a quote or paren that isn't closed.
+    pub unmatched: bool,
+}
+
+/// The colors for each [`Kind`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Theme {
+    command: String,
+    error: String,
+    string: String,
+    variable: String,
+    operator: String,
+    comment: String,
+    unmatched: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            command: String::from("32"),
+            error: String::from("31"),
+            string: String::from("33"),
+            variable: String::from("35"),
+            operator: String::from("36"),
+            comment: String::from("90"),
+            unmatched: String::from("4"),
+        }
+    }
+}
+
+impl Theme {
+    /// The default theme changed by `RUSH_COLORS`.
+    pub fn new(state: &ShellState) -> Theme {
+        let mut theme = Theme::default();
+        let colors = state.get_env_exact("RUSH_COLORS").unwrap_or_default();
+        for (name, sgr) in colors.split(':').filter_map(|c| c.split_once('=')) {
+            let color = match name {
+                "command" => &mut theme.command,
+                "error" => &mut theme.error,
+                "string" => &mut theme.string,
+                "variable" => &mut theme.variable,
+                "operator" => &mut theme.operator,
+                "comment" => &mut theme.comment,
+                "unmatched" => &mut theme.unmatched,
+                _ => continue,
+            };
+            *color = sgr.to_owned();
+        }
+        theme
+    }
+
+    /// The SGR parameters of a style.
+    fn sgr(&self, style: Style) -> String {
+        let color = match style.kind {
+            Some(Kind::Command) => &self.command,
+            Some(Kind::Error) => &self.error,
+            Some(Kind::String) => &self.string,
+            Some(Kind::Variable) => &self.variable,
+            Some(Kind::Operator) => &self.operator,
+            Some(Kind::Comment) => &self.comment,
+            None => "",
+        };
+        match (
+            color.is_empty(),
+            style.unmatched && !self.unmatched.is_empty(),
+        ) {
+            (false, true) => format!("{};{}", color, self.unmatched),
+            (true, true) => self.unmatched.clone(),
+            _ => color.to_owned(),
+        }
+    }
+}
+
+/// Works out the style of each character in a line.
+pub(super) fn styles(line: &str, state: &mut ShellState) -> Vec<Style> {
+    let mut styles = vec![Style::default(); line.chars().count()];
+    let (spans, unclosed) = spans(line);
+
+    // if the next word is a command
+    let mut start = true;
+    let mut dollar = false;
+    let mut opens = Vec::new();
+    let mut unmatched = Vec::new();
+    for (i, span) in spans.iter().enumerate() {
+        let mut range = span.range.clone();
+        let kind = match &span.token {
+            Token::Space | Token::Tab => None,
+            Token::Comment => Some(Kind::Comment),
+            Token::SingleQuote(_) | Token::DoubleQuote(_) => Some(Kind::String),
+            Token::Doller | Token::Sub(_) | Token::Param(_) => Some(Kind::Variable),
+            Token::Ident(name) if dollar => {
+                // only the name is part of the variable
+                let len = match name.chars().next() {
+                    Some(c) if !c.is_alphanumeric() && c != '_' => 1,
+                    _ => name
+                        .chars()
+                        .take_while(|c| c.is_alphanumeric() || *c == '_')
+                        .count(),
+                };
+                range.end = range.start + len;
+                Some(Kind::Variable)
+            }
+            Token::Ident(name) if start => match resolves(name, state) {
+                true => Some(Kind::Command),
+                false => Some(Kind::Error),
+            },
+            Token::OpenParen => {
+                opens.push(i);
+                Some(Kind::Operator)
+            }
+            Token::CloseParen => {
+                if opens.pop().is_none() {
+                    unmatched.push(i);
+                }
+                Some(Kind::Operator)
+            }
+            Token::Bang if start => Some(Kind::Operator),
+            Token::Pipe | Token::Amp | Token::SemiColor | Token::LeftArrow | Token::RightArrow => {
+                Some(Kind::Operator)
+            }
+            _ => None,
+        };
+        for style in &mut styles[range] {
+            style.kind = kind;
+        }
+
+        dollar = span.token == Token::Doller;
+        start = match &span.token {
+            Token::Space | Token::Tab | Token::Comment => start,
+            Token::Newline | Token::SemiColor | Token::Pipe | Token::Amp | Token::OpenParen => true,
+            Token::Bang => start,
+            _ => false,
+        };
+    }
+
+    unmatched.extend(opens);
+    if unclosed {
+        unmatched.push(spans.len() - 1);
+    }
+    for i in unmatched {
+        for style in &mut styles[spans[i].range.clone()] {
+            style.unmatched = true;
+        }
+    }
+    styles
+}
+
+/// If a command name runs something. Commands are looked up like they are
+/// when run, so ones that are found go in the hash table.
+fn resolves(name: &str, state: &mut ShellState) -> bool {
+    if state.alias(name).is_some() || KEYWORDS.contains(&name) || state.builtins().contains(name) {
+        return true;
+    }
+    match name.contains('/') {
+        true => std::path::Path::new(name).is_file(),
+        false => state.hashed(name).is_some() || state.find_command(name).is_some(),
+    }
+}
+
+/// Writes text with the escapes that color it.
+pub(super) fn paint(text: &[char], styles: &[Style], theme: &Theme) -> String {
+    let mut out = String::new();
+    let mut last = Style::default();
+    for (c, style) in text.iter().zip(styles) {
+        if *style != last {
+            if last != Style::default() {
+                out.push_str("\x1b[0m");
+            }
+            let sgr = theme.sgr(*style);
+            if !sgr.is_empty() {
+                let _ = write!(out, "\x1b[{}m", sgr);
+            }
+            last = *style;
+        }
+        out.push(*c);
+    }
+    if last != Style::default() {
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Shows the kind of each character with a letter.
+    fn kinds(line: &str) -> String {
+        styles(line, &mut ShellState::default())
+            .into_iter()
+            .map(|s| match (s.kind, s.unmatched) {
+                (_, true) => 'u',
+                (Some(Kind::Command), _) => 'c',
+                (Some(Kind::Error), _) => 'e',
+                (Some(Kind::String), _) => 's',
+                (Some(Kind::Variable), _) => 'v',
+                (Some(Kind::Operator), _) => 'o',
+                (Some(Kind::Comment), _) => '#',
+                (None, _) => '.',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kinds_of_words() {
+        assert_eq!(kinds("echo 'a' $HOME/x | nope"), "cccc.sss.vvvvv...o.eeee");
+        assert_eq!(kinds("cd; [[ x ]] # hi"), "cco.cc.....#####");
+        assert_eq!(kinds("echo (a\"b"), "cccc.ueuu");
+        assert_eq!(kinds("echo a)"), "cccc..u");
+    }
+
+    #[test]
+    fn themes() {
+        let theme = Theme {
+            string: String::new(),
+            ..Theme::default()
+        };
+        let style = |kind| Style {
+            kind: Some(kind),
+            unmatched: true,
+        };
+        assert_eq!(theme.sgr(style(Kind::Error)), "31;4");
+        assert_eq!(theme.sgr(style(Kind::String)), "4");
+
+        let text: Vec<_> = "ls x".chars().collect();
+        let mut styles = vec![Style::default(); 4];
+        styles[0].kind = Some(Kind::Command);
+        styles[1].kind = Some(Kind::Command);
+        assert_eq!(
+            paint(&text, &styles, &Theme::default()),
+            "\x1b[32mls\x1b[0m x"
+        );
+    }
+}
@@ -0,0 +1,150 @@
+//! The candidates shown under the prompt when Tab finds more than one. Tab
+//! and BackTab go through them.
+
+use super::{InsertResult, LineBuffer};
+use crate::complete::{self, Completion};
+use crate::prelude::*;
+
+use crossterm::style::Stylize;
+use std::ops::Range;
+
+pub(super) struct Menu {
+    completion: Completion,
+    /// The candidate in the line, once Tab has been pressed again.
+    selected: Option<usize>,
+}
+
+impl Menu {
+    /// Completes the word before the cursor. When there is one candidate it
+    /// is put in the line, otherwise as much as they share is and a menu
+    /// is given back.
+    pub(super) fn complete(
+        buff: &mut LineBuffer,
+        state: &mut ShellState,
+    ) -> (Option<Menu>, InsertResult) {
+        let line = buff.to_string();
+        let Some(mut completion) = complete::complete(&line, buff.cursor(), state) else {
+            return (None, InsertResult::None);
+        };
+
+        match completion.candidates.as_slice() {
+            [] => (None, InsertResult::None),
+            [only] => {
+                let text = completion.replacement(only, true);
+                replace(buff, completion.range, &text);
+                (None, InsertResult::Render)
+            }
+            _ => {
+                let common = completion.common_prefix();
+                if common.len() > completion.word.len() {
+                    let text = completion.replacement(common, false);
+                    completion.range = replace(buff, completion.range.clone(), &text);
+                }
+                let menu = Menu {
+                    completion,
+                    selected: None,
+                };
+                (Some(menu), InsertResult::Render)
+            }
+        }
+    }
+
+    /// Puts the next candidate in the line, or the one before.
+    pub(super) fn cycle(&mut self, buff: &mut LineBuffer, forward: bool) -> InsertResult {
+        let len = self.completion.candidates.len();
+        let next = match self.selected {
+            None if forward => 0,
+            None => len - 1,
+            Some(i) if forward => (i + 1) % len,
+            Some(i) => (i + len - 1) % len,
+        };
+        self.selected = Some(next);
+
+        let text = self
+            .completion
+            .replacement(&self.completion.candidates[next], false);
+        self.completion.range = replace(buff, self.completion.range.clone(), &text);
+        InsertResult::Render
+    }
+
+    /// The lines to show under the prompt. Candidates go down each column
+    /// like `ls` and there are only as many lines as fit on the screen.
+    pub(super) fn rows(&self, width: usize, height: usize) -> Vec<String> {
+        let names: Vec<_> = self.completion.candidates.iter().map(|c| name(c)).collect();
+        let col = names.iter().map(|n| n.chars().count()).max().unwrap_or(0) + 2;
+        let cols = (width / col).max(1);
+        let max_rows = height.saturating_sub(2).max(1);
+
+        let mut rows = names.len().div_ceil(cols);
+        let mut shown = names.len();
+        if rows > max_rows {
+            // leave a line to say how many are missing
+            rows = max_rows - 1;
+            shown = rows * cols;
+        }
+
+        let mut lines = vec![String::new(); rows];
+        for (i, name) in names.iter().take(shown).enumerate() {
+            let cell = format!("{:<1$}", name, col);
+            let cell = if self.selected == Some(i) {
+                cell.reverse().to_string()
+            } else {
+                cell
+            };
+            lines[i % rows].push_str(&cell);
+        }
+
+        if shown < names.len() {
+            lines.push(format!("({} more)", names.len() - shown));
+        }
+        lines
+    }
+}
+
+/// What a candidate is shown as. Paths only show the last part.
+fn name(candidate: &str) -> &str {
+    let trimmed = candidate.trim_end_matches('/');
+    match trimmed.rfind('/') {
+        Some(i) => &candidate[i + 1..],
+        None => candidate,
+    }
+}
+
+/// Puts text in place of part of the line and gives back where it went.
+fn replace(buff: &mut LineBuffer, range: Range<usize>, text: &str) -> Range<usize> {
+    let start = range.start;
+    buff.buf.splice(range, text.chars());
+    let end = start + text.chars().count();
+    buff.move_to(end);
+    start..end
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::complete::Kind;
+
+    #[test]
+    fn columns() {
+        let candidates = ["a", "bb", "ccc", "dd", "e"].map(String::from).to_vec();
+        let mut menu = Menu {
+            completion: Completion {
+                kind: Kind::Command,
+                range: 0..0,
+                quote: None,
+                word: String::new(),
+                candidates,
+                nospace: false,
+            },
+            selected: None,
+        };
+        assert_eq!(menu.rows(15, 24), ["a    ccc  e    ", "bb   dd   "]);
+        assert_eq!(menu.rows(5, 4), ["a    ", "(4 more)"]);
+
+        let mut buff = LineBuffer::default();
+        menu.cycle(&mut buff, false);
+        assert_eq!(buff.to_string(), "e");
+        menu.cycle(&mut buff, true);
+        assert_eq!(buff.to_string(), "a");
+    }
+}
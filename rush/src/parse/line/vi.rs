@@ -0,0 +1,651 @@
+//! Vi mode for the line editor, turned on with `set -o vi`. Insert mode
+//! takes the same keys as emacs mode and normal mode reads commands like
+//! `3dw` a key at a time.
+
+use super::{InsertResult, LineBuffer};
+
+use crossterm::event::KeyCode as K;
+use crossterm::event::KeyEvent;
+use crossterm::event::KeyModifiers as Km;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// What [`super::read_line`] should do after a key.
+pub(super) enum Step {
+    Done(InsertResult),
+    /// The key is handled like it is in emacs mode.
+    Emacs,
+    /// Go to an older or newer line in the history.
+    History {
+        older: bool,
+    },
+    /// Read a pattern then search back through the history for it.
+    Search,
+    /// Search for the last pattern again.
+    SearchAgain {
+        older: bool,
+    },
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Vi {
+    normal: bool,
+    /// The keys of a command that isn't finished yet.
+    keys: String,
+    /// Text taken by `d`, `c`, `y` and `x` that `p` puts back.
+    register: String,
+    /// The line before each change, for `u`.
+    undo: Vec<LineBuffer>,
+    /// The line from before insert mode started. It goes on `undo` if
+    /// anything was typed.
+    before: Option<LineBuffer>,
+    /// The command that started insert mode, if it can be repeated.
+    inserting: Option<Command>,
+    /// What has been typed since insert mode started.
+    typed: String,
+    /// The last change and what was typed after it, for `.`.
+    last: Option<(Command, String)>,
+    /// The last `f`, `t`, `F` or `T` and its character, for `;` and `,`.
+    find: Option<(char, char)>,
+    /// The last pattern given to `/`.
+    pub(super) search: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Command {
+    count: Option<usize>,
+    /// One of `d`, `c` or `y` which works on the text the key moves over.
+    op: Option<char>,
+    key: Key,
+}
+
+impl Command {
+    fn times(&self) -> usize {
+        self.count.unwrap_or(1)
+    }
+
+    /// If `.` repeats this.
+    fn is_change(&self) -> bool {
+        match (self.op, self.key) {
+            (Some(op), _) => op != 'y',
+            (None, Key::Replace(_)) => true,
+            (None, Key::Action(c)) => "xXDCpPiaIA".contains(c),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Motion(Motion),
+    /// The operator given twice, like `dd`, which works on the whole line.
+    Line,
+    /// `r` and the character to replace with.
+    Replace(char),
+    Action(char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Motion {
+    Left,
+    Right,
+    WordNext,
+    WordBack,
+    WordEnd,
+    Start,
+    FirstNonBlank,
+    End,
+    /// `f`, `t`, `F` or `T` and the character to look for.
+    Find(char, char),
+    /// `;` or `,` which is the other way.
+    FindAgain {
+        reverse: bool,
+    },
+}
+
+enum Parse {
+    Done(Command),
+    More,
+    Bad,
+}
+
+/// Reads a normal mode command. `More` means it isn't finished yet.
+fn parse(keys: &str) -> Parse {
+    let mut chars = keys.chars().peekable();
+    let first = count(&mut chars);
+    let Some(mut c) = chars.next() else {
+        return Parse::More;
+    };
+
+    let mut op = None;
+    let mut second = None;
+    if matches!(c, 'd' | 'c' | 'y') {
+        op = Some(c);
+        second = count(&mut chars);
+        match chars.next() {
+            Some(m) if m == c => {
+                let count = times(first, second);
+                return Parse::Done(Command {
+                    count,
+                    op,
+                    key: Key::Line,
+                });
+            }
+            Some(m) => c = m,
+            None => return Parse::More,
+        }
+    }
+
+    let motion = match c {
+        'h' => Motion::Left,
+        'l' | ' ' => Motion::Right,
+        'w' => Motion::WordNext,
+        'b' => Motion::WordBack,
+        'e' => Motion::WordEnd,
+        '0' => Motion::Start,
+        '^' => Motion::FirstNonBlank,
+        '$' => Motion::End,
+        ';' => Motion::FindAgain { reverse: false },
+        ',' => Motion::FindAgain { reverse: true },
+        'f' | 't' | 'F' | 'T' => match chars.next() {
+            Some(target) => Motion::Find(c, target),
+            None => return Parse::More,
+        },
+        _ => {
+            let key = match c {
+                _ if op.is_some() => return Parse::Bad,
+                'r' => match chars.next() {
+                    Some(with) => Key::Replace(with),
+                    None => return Parse::More,
+                },
+                c if "xXDCpPiaIAu.jk/nN".contains(c) => Key::Action(c),
+                _ => return Parse::Bad,
+            };
+            return Parse::Done(Command {
+                count: first,
+                op,
+                key,
+            });
+        }
+    };
+    let count = times(first, second);
+    Parse::Done(Command {
+        count,
+        op,
+        key: Key::Motion(motion),
+    })
+}
+
+/// Reads a count. It can't start with `0` since that is a motion.
+fn count(chars: &mut Peekable<Chars>) -> Option<usize> {
+    if !matches!(chars.peek(), Some('1'..='9')) {
+        return None;
+    }
+    let mut n = 0usize;
+    while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+        n = n.saturating_mul(10).saturating_add(d as usize);
+        chars.next();
+    }
+    Some(n)
+}
+
+/// `2d3w` deletes six words.
+fn times(first: Option<usize>, second: Option<usize>) -> Option<usize> {
+    match (first, second) {
+        (Some(a), Some(b)) => Some(a.saturating_mul(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Vi splits words into runs of letters and runs of other symbols.
+fn class(c: char) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+fn next_word(buf: &[char], at: usize) -> usize {
+    let mut i = at;
+    if let Some(&c) = buf.get(i) {
+        let c = class(c);
+        while i < buf.len() && c != 0 && class(buf[i]) == c {
+            i += 1;
+        }
+    }
+    while i < buf.len() && class(buf[i]) == 0 {
+        i += 1;
+    }
+    i
+}
+
+fn prev_word(buf: &[char], at: usize) -> usize {
+    let mut i = at;
+    while i > 0 && class(buf[i - 1]) == 0 {
+        i -= 1;
+    }
+    if i > 0 {
+        let c = class(buf[i - 1]);
+        while i > 0 && class(buf[i - 1]) == c {
+            i -= 1;
+        }
+    }
+    i
+}
+
+/// The last character of the next word. A cursor already at the end of a
+/// word goes on to the next one.
+fn word_end(buf: &[char], at: usize) -> usize {
+    let mut i = at + 1;
+    while i < buf.len() && class(buf[i]) == 0 {
+        i += 1;
+    }
+    if i >= buf.len() {
+        return buf.len().saturating_sub(1).max(at);
+    }
+    let c = class(buf[i]);
+    while i + 1 < buf.len() && class(buf[i + 1]) == c {
+        i += 1;
+    }
+    i
+}
+
+/// Finds the `n`th `target` for `f`, `t`, `F` and `T`.
+fn find(buf: &[char], at: usize, kind: char, target: char, n: usize) -> Option<usize> {
+    let forward = matches!(kind, 'f' | 't');
+    let mut i = at;
+    for _ in 0..n {
+        i = if forward {
+            (i + 1..buf.len()).find(|&j| buf[j] == target)?
+        } else {
+            (0..i).rev().find(|&j| buf[j] == target)?
+        };
+    }
+    match kind {
+        't' => Some(i - 1),
+        'T' => Some(i + 1),
+        _ => Some(i),
+    }
+}
+
+fn reverse(kind: char) -> char {
+    match kind {
+        'f' => 'F',
+        'F' => 'f',
+        't' => 'T',
+        _ => 't',
+    }
+}
+
+impl Vi {
+    /// Gets ready for a new line, which starts in insert mode.
+    pub(super) fn start(&mut self, buff: &LineBuffer) {
+        self.normal = false;
+        self.keys.clear();
+        self.undo.clear();
+        self.before = Some(buff.clone());
+        self.inserting = None;
+        self.typed.clear();
+    }
+
+    pub(super) fn is_insert(&self) -> bool {
+        !self.normal
+    }
+
+    /// Keeps the cursor on a character in normal mode.
+    pub(super) fn clamp(&self, buff: &mut LineBuffer) {
+        if self.normal && !buff.buf.is_empty() && buff.cursor() >= buff.buf.len() {
+            buff.move_to(buff.buf.len() - 1);
+        }
+    }
+
+    pub(super) fn key(&mut self, key: KeyEvent, buff: &mut LineBuffer) -> Step {
+        if self.normal {
+            self.normal_key(key, buff)
+        } else {
+            self.insert_key(key, buff)
+        }
+    }
+
+    fn insert_key(&mut self, key: KeyEvent, buff: &mut LineBuffer) -> Step {
+        match (key.code, key.modifiers) {
+            (K::Esc, _) | (K::Char('['), Km::CONTROL) => Step::Done(self.escape(buff)),
+            (K::Char(c), Km::NONE) => {
+                self.typed.push(c);
+                Step::Emacs
+            }
+            (K::Char(c), Km::SHIFT) => {
+                self.typed.push(c.to_ascii_uppercase());
+                Step::Emacs
+            }
+            (K::Backspace, _) => {
+                self.typed.pop();
+                Step::Emacs
+            }
+            _ => Step::Emacs,
+        }
+    }
+
+    fn normal_key(&mut self, key: KeyEvent, buff: &mut LineBuffer) -> Step {
+        let c = match (key.code, key.modifiers) {
+            (K::Char(c), Km::NONE) => c,
+            (K::Char(c), Km::SHIFT) => c.to_ascii_uppercase(),
+            (K::Left | K::Backspace, _) => 'h',
+            (K::Right, _) => 'l',
+            (K::Up, _) => 'k',
+            (K::Down, _) => 'j',
+            (K::Home, _) => '0',
+            (K::End, _) => '$',
+            (K::Delete, _) => 'x',
+            _ => {
+                self.keys.clear();
+                return Step::Done(InsertResult::None);
+            }
+        };
+
+        self.keys.push(c);
+        let cmd = match parse(&self.keys) {
+            Parse::Done(cmd) => cmd,
+            Parse::More => return Step::Done(InsertResult::None),
+            Parse::Bad => {
+                self.keys.clear();
+                return Step::Done(InsertResult::None);
+            }
+        };
+        self.keys.clear();
+
+        if cmd.key == Key::Action('.') {
+            return self.repeat(cmd.count, buff);
+        }
+        if cmd.is_change() {
+            self.last = Some((cmd, String::new()));
+        }
+        let step = self.run(cmd, buff);
+        if self.is_insert() && cmd.is_change() {
+            self.inserting = Some(cmd);
+        }
+        step
+    }
+
+    /// Leaves insert mode. The cursor goes back onto the last character
+    /// typed.
+    fn escape(&mut self, buff: &mut LineBuffer) -> InsertResult {
+        self.normal = true;
+        if let Some(cmd) = self.inserting.take() {
+            self.last = Some((cmd, std::mem::take(&mut self.typed)));
+        }
+        if let Some(before) = self.before.take() {
+            if before.buf != buff.buf {
+                self.undo.push(before);
+            }
+        }
+        buff.left();
+        self.clamp(buff);
+        InsertResult::Render
+    }
+
+    fn insert(&mut self) {
+        self.normal = false;
+        self.typed.clear();
+    }
+
+    /// Runs the last change again, with a new count if one was given.
+    fn repeat(&mut self, count: Option<usize>, buff: &mut LineBuffer) -> Step {
+        let Some((mut cmd, typed)) = self.last.clone() else {
+            return Step::Done(InsertResult::None);
+        };
+        if count.is_some() {
+            cmd.count = count;
+        }
+        self.run(cmd, buff);
+        if self.is_insert() {
+            for c in typed.chars() {
+                buff.push(c);
+            }
+            self.escape(buff);
+        }
+        self.last = Some((cmd, typed));
+        Step::Done(InsertResult::Render)
+    }
+
+    fn run(&mut self, cmd: Command, buff: &mut LineBuffer) -> Step {
+        let before = buff.clone();
+        let n = cmd.times();
+        let at = buff.cursor();
+        let len = buff.buf.len();
+
+        match (cmd.op, cmd.key) {
+            (None, Key::Motion(m)) => {
+                if let Some(to) = self.target(buff, m, n) {
+                    buff.move_to(to);
+                }
+            }
+            (Some(op), Key::Line) => self.operate(op, 0, len, buff),
+            (Some(op), Key::Motion(m)) => {
+                // `cw` changes to the end of the word like `ce`
+                let changing_word = op == 'c'
+                    && m == Motion::WordNext
+                    && buff.buf.get(at).is_some_and(|c| !c.is_whitespace());
+                let (target, inclusive) = if changing_word {
+                    let mut end = at;
+                    while end + 1 < len && class(buff.buf[end + 1]) == class(buff.buf[at]) {
+                        end += 1;
+                    }
+                    for _ in 1..n {
+                        end = word_end(&buff.buf, end);
+                    }
+                    (Some(end), true)
+                } else {
+                    let inclusive = matches!(
+                        self.resolve(m),
+                        Some(Motion::WordEnd | Motion::Find('f' | 't', _))
+                    );
+                    (self.target(buff, m, n), inclusive)
+                };
+
+                match target {
+                    Some(to) if to >= at => {
+                        let to = if inclusive { to + 1 } else { to };
+                        self.operate(op, at, to.min(len), buff);
+                    }
+                    Some(to) => self.operate(op, to, at, buff),
+                    None => {}
+                }
+            }
+            (None, Key::Replace(with)) => {
+                if at + n <= len {
+                    buff.buf[at..at + n].fill(with);
+                    buff.move_to(at + n - 1);
+                }
+            }
+            (None, Key::Action(c)) => match c {
+                'x' if at < len => self.operate('d', at, (at + n).min(len), buff),
+                'X' if at > 0 => self.operate('d', at.saturating_sub(n), at, buff),
+                'D' => self.operate('d', at, len, buff),
+                'C' => self.operate('c', at, len, buff),
+                'p' | 'P' if !self.register.is_empty() => {
+                    let at = if c == 'p' && len > 0 { at + 1 } else { at };
+                    let text = self.register.repeat(n);
+                    let count = text.chars().count();
+                    buff.buf.splice(at..at, text.chars());
+                    buff.move_to(at + count - 1);
+                }
+                'i' => self.insert(),
+                'a' => {
+                    buff.move_to(at + 1);
+                    self.insert();
+                }
+                'I' => {
+                    buff.home();
+                    self.insert();
+                }
+                'A' => {
+                    buff.end();
+                    self.insert();
+                }
+                'u' => {
+                    if let Some(prev) = self.undo.pop() {
+                        *buff = prev;
+                        self.clamp(buff);
+                    }
+                    return Step::Done(InsertResult::Render);
+                }
+                'j' => return Step::History { older: false },
+                'k' => return Step::History { older: true },
+                '/' => return Step::Search,
+                'n' => return Step::SearchAgain { older: true },
+                'N' => return Step::SearchAgain { older: false },
+                _ => {}
+            },
+            (Some(_), _) | (None, Key::Line) => unreachable!("parse only gives operators motions"),
+        }
+
+        if self.is_insert() {
+            self.before = Some(before);
+        } else if before.buf != buff.buf {
+            self.undo.push(before);
+        }
+        self.clamp(buff);
+        Step::Done(InsertResult::Render)
+    }
+
+    /// Turns `;` and `,` into the find they repeat.
+    fn resolve(&self, m: Motion) -> Option<Motion> {
+        match m {
+            Motion::FindAgain { reverse: r } => {
+                let (kind, target) = self.find?;
+                Some(Motion::Find(if r { reverse(kind) } else { kind }, target))
+            }
+            m => Some(m),
+        }
+    }
+
+    /// Where a motion goes from the cursor, if it can go anywhere.
+    fn target(&mut self, buff: &LineBuffer, m: Motion, n: usize) -> Option<usize> {
+        let buf = &buff.buf;
+        let at = buff.cursor();
+        let to = match m {
+            Motion::Left if at == 0 => return None,
+            Motion::Left => at.saturating_sub(n),
+            Motion::Right => (at + n).min(buf.len()),
+            Motion::WordNext => (0..n).fold(at, |i, _| next_word(buf, i)),
+            Motion::WordBack => (0..n).fold(at, |i, _| prev_word(buf, i)),
+            Motion::WordEnd => (0..n).fold(at, |i, _| word_end(buf, i)),
+            Motion::Start => 0,
+            Motion::FirstNonBlank => buf.iter().position(|c| !c.is_whitespace()).unwrap_or(0),
+            Motion::End => buf.len(),
+            Motion::Find(kind, target) => {
+                self.find = Some((kind, target));
+                find(buf, at, kind, target, n)?
+            }
+            Motion::FindAgain { .. } => {
+                let Some(Motion::Find(kind, target)) = self.resolve(m) else {
+                    return None;
+                };
+                find(buf, at, kind, target, n)?
+            }
+        };
+        Some(to)
+    }
+
+    /// Runs `d`, `c` or `y` on some text. All of them save it for `p`.
+    fn operate(&mut self, op: char, from: usize, to: usize, buff: &mut LineBuffer) {
+        self.register = buff.buf[from..to].iter().collect();
+        if op != 'y' {
+            buff.buf.drain(from..to);
+        }
+        buff.move_to(from);
+        if op == 'c' {
+            self.insert();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::{line, show};
+    use super::*;
+
+    /// Types some keys in normal mode. `\x1b` is Esc.
+    fn keys(vi: &mut Vi, buff: &mut LineBuffer, keys: &str) {
+        vi.normal = true;
+        for c in keys.chars() {
+            let key = match c {
+                '\x1b' => KeyEvent::new(K::Esc, Km::NONE),
+                c => KeyEvent::new(K::Char(c), Km::NONE),
+            };
+            if let Step::Emacs = vi.key(key, buff) {
+                buff.push(c);
+            }
+        }
+    }
+
+    fn run(start: &str, typed: &str) -> String {
+        let mut vi = Vi::default();
+        let mut buff = line(start);
+        keys(&mut vi, &mut buff, typed);
+        show(&buff)
+    }
+
+    #[test]
+    fn motions() {
+        assert_eq!(run("|echo foo.bar baz", "w"), "echo |foo.bar baz");
+        assert_eq!(run("|echo foo.bar baz", "2w"), "echo foo|.bar baz");
+        assert_eq!(run("echo foo.bar |baz", "b"), "echo foo.|bar baz");
+        assert_eq!(run("|echo foo", "e"), "ech|o foo");
+        assert_eq!(run("|echo foo", "ee"), "echo fo|o");
+        assert_eq!(run("echo f|oo", "0"), "|echo foo");
+        assert_eq!(run("echo f|oo", "$"), "echo fo|o");
+        assert_eq!(run("echo f|oo", "3h"), "ech|o foo");
+        assert_eq!(run("ec|ho foo", "l"), "ech|o foo");
+    }
+
+    #[test]
+    fn find_chars() {
+        assert_eq!(run("|a/b/c/d", "f/"), "a|/b/c/d");
+        assert_eq!(run("|a/b/c/d", "2f/"), "a/b|/c/d");
+        assert_eq!(run("|a/b/c/d", "f/;"), "a/b|/c/d");
+        assert_eq!(run("|a/b/c/d", "t/"), "|a/b/c/d");
+        assert_eq!(run("a/b/c/|d", "F/"), "a/b/c|/d");
+        assert_eq!(run("a/b/c/|d", "T/,"), "a/b/c/|d");
+        assert_eq!(run("|a/b/c/d", "fz"), "|a/b/c/d");
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(run("|echo foo bar", "dw"), "|foo bar");
+        assert_eq!(run("echo foo |bar", "db"), "echo |bar");
+        assert_eq!(run("|echo foo bar", "d2w"), "|bar");
+        assert_eq!(run("|echo foo bar", "de"), "| foo bar");
+        assert_eq!(run("echo |foo bar", "d$"), "echo| ");
+        assert_eq!(run("echo |foo bar", "dfo"), "echo |o bar");
+        assert_eq!(run("echo |foo bar", "dd"), "|");
+        assert_eq!(run("echo |foo bar", "x"), "echo |oo bar");
+        assert_eq!(run("echo |foo bar", "3x"), "echo | bar");
+        assert_eq!(run("echo |foo bar", "cwbaz\x1b"), "echo ba|z bar");
+        assert_eq!(run("echo |foo bar", "Cls\x1b"), "echo l|s");
+        assert_eq!(run("echo |foo bar", "rx"), "echo |xoo bar");
+    }
+
+    #[test]
+    fn yank_and_put() {
+        assert_eq!(run("|echo foo", "ywP"), "echo| echo foo");
+        assert_eq!(run("|echo foo", "dwp"), "fecho| oo");
+        assert_eq!(run("echo |foo", "x$p"), "echo oo|f");
+        assert_eq!(run("|ab", "yl3p"), "aaa|ab");
+    }
+
+    #[test]
+    fn undo_and_repeat() {
+        assert_eq!(run("|echo foo bar", "dwu"), "|echo foo bar");
+        assert_eq!(run("|echo foo bar", "dwdwuu"), "|echo foo bar");
+        assert_eq!(run("|echo foo bar", "dw."), "|bar");
+        assert_eq!(run("|a b c d", "x2."), "| c d");
+        assert_eq!(run("|foo bar", "cwbaz\x1bw."), "baz ba|z");
+        assert_eq!(run("|foo", "ix\x1bu"), "|foo");
+        assert_eq!(run("|foo", "Ax\x1b0."), "foox|x");
+    }
+}
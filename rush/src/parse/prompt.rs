@@ -0,0 +1,283 @@
+//! Prompts like `PS1` with bash's escapes, like `\u` and `\w`, and
+//! parameter and command substitution.
+//!
+//! Text between `\[` and `\]` is marked with `\x01` and `\x02` like readline
+//! does so it isn't counted when working out how wide a prompt is. Terminal
+//! escape sequences aren't counted either.
+
+use crate::prelude::*;
+use crate::util::{host_name, strftime, user_name, width};
+
+/// The prompts for one line, ready to be drawn.
+#[derive(Debug, Default)]
+pub(super) struct Prompt {
+    /// `PS1`
+    pub left: String,
+    /// `PS2`, before each line of a command that goes on.
+    pub more: String,
+    /// `RPROMPT`, shown on the right of the first line.
+    pub right: String,
+}
+
+impl Prompt {
+    pub fn new(state: &ShellState) -> Prompt {
+        let get = |name, default: &str| {
+            let ps = state
+                .get_env_exact(name)
+                .unwrap_or_else(|| default.to_owned());
+            expand(&ps, state)
+        };
+        Prompt {
+            left: get("PS1", "$> "),
+            more: get("PS2", "> "),
+            right: get("RPROMPT", ""),
+        }
+    }
+}
+
+/// Expands the escapes and substitutions in a prompt.
+pub(crate) fn expand(ps: &str, state: &ShellState) -> String {
+    let mut out = String::new();
+    let mut chars = ps.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('D') if chars.peek() == Some(&'{') => {
+                    let _ = chars.next();
+                    let format: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    let format = if format.is_empty() { "%X" } else { &format };
+                    out.push_str(&strftime(format, now()));
+                }
+                Some(c @ '0'..='7') => {
+                    let mut n = c.to_digit(8).unwrap();
+                    for _ in 0..2 {
+                        match chars.peek().and_then(|c| c.to_digit(8)) {
+                            Some(d) => {
+                                n = n * 8 + d;
+                                let _ = chars.next();
+                            }
+                            None => break,
+                        }
+                    }
+                    out.extend(char::from_u32(n));
+                }
+                Some(c) => escape(c, state, &mut out),
+                None => out.push('\\'),
+            },
+            '$' => substitute(&mut chars, state, &mut out),
+            '`' => {
+                let code: String = chars.by_ref().take_while(|c| *c != '`').collect();
+                out.push_str(&run(&code));
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Adds what `\c` stands for. Escapes that mean nothing are kept.
+fn escape(c: char, state: &ShellState, out: &mut String) {
+    match c {
+        'u' => out.push_str(&user_name().unwrap_or_default()),
+        'h' => {
+            let host = host_name().unwrap_or_default();
+            out.push_str(host.split('.').next().unwrap_or_default());
+        }
+        'H' => out.push_str(&host_name().unwrap_or_default()),
+        'w' => out.push_str(&tilde(&state.pwd(), state.home())),
+        'W' => {
+            let pwd = tilde(&state.pwd(), state.home());
+            match pwd.rsplit_once('/') {
+                Some((_, name)) if !name.is_empty() => out.push_str(name),
+                _ => out.push_str(&pwd),
+            }
+        }
+        '$' => out.push(if unsafe { libc::geteuid() } == 0 {
+            '#'
+        } else {
+            '$'
+        }),
+        't' => out.push_str(&strftime("%H:%M:%S", now())),
+        'T' => out.push_str(&strftime("%I:%M:%S", now())),
+        '@' => out.push_str(&strftime("%I:%M %p", now())),
+        'A' => out.push_str(&strftime("%H:%M", now())),
+        'd' => out.push_str(&strftime("%a %b %d", now())),
+        // background jobs aren't kept track of yet
+        'j' => out.push('0'),
+        '?' => out.push_str(&state.prev().to_string()),
+        '!' => {
+            let next = state.history().entries().last().map_or(1, |e| e.number + 1);
+            out.push_str(&next.to_string());
+        }
+        's' => out.push_str("rush"),
+        'v' | 'V' => out.push_str(env!("CARGO_PKG_VERSION")),
+        'n' => out.push('\n'),
+        'r' => out.push('\r'),
+        'a' => out.push('\x07'),
+        'e' => out.push('\x1b'),
+        '[' => out.push('\x01'),
+        ']' => out.push('\x02'),
+        '\\' => out.push('\\'),
+        c => {
+            out.push('\\');
+            out.push(c);
+        }
+    }
+}
+
+/// Adds the value of `$name`, `${name}` or `$(command)`. A `$` that isn't
+/// any of them is kept.
+fn substitute(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    state: &ShellState,
+    out: &mut String,
+) {
+    let name = match chars.peek() {
+        Some('(') => {
+            let _ = chars.next();
+            let mut depth = 1;
+            let code: String = chars
+                .by_ref()
+                .take_while(|c| {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    depth > 0
+                })
+                .collect();
+            out.push_str(&run(&code));
+            return;
+        }
+        Some('{') => {
+            let _ = chars.next();
+            chars.by_ref().take_while(|c| *c != '}').collect()
+        }
+        Some(c) if "?#$@*!-".contains(*c) || c.is_ascii_digit() => {
+            chars.next().map(String::from).unwrap_or_default()
+        }
+        _ => {
+            let mut name = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                name.push(c);
+            }
+            name
+        }
+    };
+    if name.is_empty() {
+        out.push('$');
+        return;
+    }
+    out.push_str(&state.get_env_exact(&name).unwrap_or_default());
+}
+
+/// Runs a command in another shell and gives back what it printed without
+/// the newline at the end.
+fn run(code: &str) -> String {
+    let output = std::env::current_exe().and_then(|exe| {
+        std::process::Command::new(exe)
+            .arg("-c")
+            .arg(code)
+            .stdin(std::process::Stdio::null())
+            .output()
+    });
+    match output {
+        Ok(out) => String::from_utf8_lossy(&out.stdout)
+            .trim_end_matches('\n')
+            .to_owned(),
+        Err(e) => {
+            log::warn!("could not run prompt command {}: {}", code, e);
+            String::new()
+        }
+    }
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Writes the home directory at the start of a path as `~`.
+fn tilde(path: &str, home: &str) -> String {
+    match path.strip_prefix(home) {
+        Some(rest) if !home.is_empty() && (rest.is_empty() || rest.starts_with('/')) => {
+            format!("~{}", rest)
+        }
+        _ => path.to_owned(),
+    }
+}
+
+/// The characters of a prompt that take up space on the terminal.
+pub(super) fn visible(prompt: &str) -> impl Iterator<Item = char> + '_ {
+    let mut chars = prompt.chars();
+    std::iter::from_fn(move || loop {
+        match chars.next()? {
+            '\x01' => {
+                for c in chars.by_ref() {
+                    if c == '\x02' {
+                        break;
+                    }
+                }
+            }
+            '\x02' => {}
+            '\x1b' => match chars.next()? {
+                // a control sequence ends with a letter or the like
+                '[' => {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // an operating system command ends with a bell or `ESC \`
+                ']' => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next() == Some('\\')) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            c => return Some(c),
+        }
+    })
+}
+
+/// How many columns a prompt takes up. Only the last line counts.
+pub(super) fn prompt_width(prompt: &str) -> usize {
+    let last = visible(prompt).collect::<String>();
+    let last = last.rsplit('\n').next().unwrap_or_default();
+    last.chars().map(width).sum()
+}
+
+/// A prompt without the `\[` and `\]` markers so it can be written.
+pub(crate) fn printable(prompt: &str) -> String {
+    prompt.replace(['\x01', '\x02'], "")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escapes() {
+        let state = ShellState::default();
+        assert_eq!(expand("a\\\\b \\x", &state), "a\\b \\x");
+        assert_eq!(expand("\\101\\n", &state), "A\n");
+        assert_eq!(expand("\\[\\e[32m\\]>", &state), "\x01\x1b[32m\x02>");
+        assert_eq!(expand("$? ${?}$", &state), "0 0$");
+        assert_eq!(tilde("/home/me/src", "/home/me"), "~/src");
+        assert_eq!(tilde("/home/meow", "/home/me"), "/home/meow");
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(prompt_width("\x01\x1b[1;32m\x02rush\x01\x1b[0m\x02 $ "), 7);
+        assert_eq!(prompt_width("\x1b[31m日本\x1b[0m> "), 6);
+        assert_eq!(prompt_width("first line\n> "), 2);
+        assert_eq!(printable("\x01\x1b[0m\x02$ "), "\x1b[0m$ ");
+    }
+}
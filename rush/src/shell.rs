@@ -1,6 +1,18 @@
-use crate::drive::run_command;
+use crate::complete::{Completer, Completers};
+use crate::drive::builtins::{Builtins, Io, ShellBuiltin};
+use crate::drive::{run_command, wait_all};
 use crate::parse::{Parser, Prompter};
 use crate::prelude::*;
+use crate::signal;
+use crate::util::{error_message, OwnedCharBuffer, Verbose};
+
+use self::history::{Filter, History};
+use self::options::{Opt, Options};
+use rush_core::lexer::Lexer;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::rc::Rc;
 
 // use nix::unistd::Uid;
 // use os_pipe::{dup_stderr, dup_stdin, dup_stdout, PipeReader, PipeWriter};
@@ -17,32 +29,307 @@ use crate::parse::{Cmd, CmdError, Streams};
 #[derive(Debug)]
 pub struct ShellState {
     pub exit: bool,
+    /// If this shell is reading commands from a user.
+    pub interactive: bool,
     home: String,
     /// The most recent exit status of a command
     prev: i32,
     // __cache: StaticMap<String, String>,
-    hist: Vec<String>,
+    hist: History,
+    /// The name of the shell or script. Aka `$0`
+    name: String,
+    /// The positional parameters. Aka `$1`, `$2`, ...
+    args: Vec<String>,
+    /// The directory stack of `pushd` and `popd` without the current
+    /// directory. The top of the stack is the end.
+    pub(crate) dirs: Vec<String>,
+    /// Variables that are not exported.
+    vars: HashMap<String, Var>,
+    builtins: Builtins,
+    /// Words that are replaced at the start of a command.
+    aliases: HashMap<String, String>,
+    /// Where commands were found in `PATH`.
+    hash: CommandCache,
+    /// What completes the arguments of each command.
+    completers: Completers,
+    /// What runs before each prompt.
+    precmd: Precmd,
+    /// Code set by `trap` to run when something happens.
+    traps: BTreeMap<Condition, String>,
+    options: Options,
+    /// If the status of the last command was tested by `&&`, `||` or `!`.
+    /// `errexit` and the `ERR` trap leave these alone.
+    pub(crate) tested: bool,
+    /// If a trap is running. Other traps don't run inside it.
+    trapping: bool,
+}
+
+/// A function run by the shell when something happens.
+type Hook = Rc<dyn Fn(&mut ShellState)>;
+
+/// Functions run before each prompt.
+#[derive(Default)]
+struct Precmd(Vec<Hook>);
+
+impl fmt::Debug for Precmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} hooks", self.0.len())
+    }
+}
+
+/// Something a trap can be set on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Condition {
+    /// The shell is exiting.
+    Exit,
+    Signal(libc::c_int),
+    /// A command exited with a non zero status.
+    Err,
+    /// A command is about to run.
+    Debug,
+    /// A file run by `.` finished.
+    Return,
+}
+
+impl Condition {
+    /// Gets a condition from a name like `EXIT`, `INT`, `SIGINT` or `2`.
+    pub fn from_name(name: &str) -> Option<Condition> {
+        match name {
+            "EXIT" | "0" => Some(Condition::Exit),
+            "ERR" => Some(Condition::Err),
+            "DEBUG" => Some(Condition::Debug),
+            "RETURN" => Some(Condition::Return),
+            _ => signal::from_name(name).map(Condition::Signal),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Exit => f.write_str("EXIT"),
+            Condition::Signal(sig) => match signal::name(*sig) {
+                Some(name) => write!(f, "SIG{}", name),
+                None => write!(f, "{}", sig),
+            },
+            Condition::Err => f.write_str("ERR"),
+            Condition::Debug => f.write_str("DEBUG"),
+            Condition::Return => f.write_str("RETURN"),
+        }
+    }
+}
+
+/// Remembers where commands are in `PATH` so it isn't searched every time.
+/// Everything is forgotten when `PATH` changes.
+#[derive(Debug, Default)]
+pub struct CommandCache {
+    /// The `PATH` the commands were found with.
+    path: Option<String>,
+    commands: HashMap<String, Hashed>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hashed {
+    pub path: PathBuf,
+    /// How many times the command was looked up.
+    pub hits: usize,
+}
+
+/// The value of a shell variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Var {
+    Scalar(String),
+    Array(Vec<String>),
+}
+
+impl Var {
+    /// Gets this as a string. For arrays this is the first element.
+    pub fn value(&self) -> Option<&str> {
+        match self {
+            Var::Scalar(s) => Some(s),
+            Var::Array(v) => v.first().map(String::as_str),
+        }
+    }
 }
 
 impl Default for ShellState {
     fn default() -> Self {
         Self {
             exit: false,
+            interactive: false,
             prev: 0,
             home: std::env::var("HOME").unwrap(),
             // __cache: StaticMap::new()
-            hist: Vec::new(),
+            hist: History::default(),
+            name: std::env::args()
+                .next()
+                .unwrap_or_else(|| String::from("rush")),
+            args: Vec::new(),
+            dirs: Vec::new(),
+            vars: HashMap::new(),
+            builtins: Builtins::default(),
+            aliases: HashMap::new(),
+            hash: CommandCache::default(),
+            completers: Completers::default(),
+            precmd: Precmd::default(),
+            traps: BTreeMap::new(),
+            options: Options::default(),
+            tested: false,
+            trapping: false,
         }
     }
 }
 
 impl ShellState {
+    /// The exit status of the last command. Aka `$?`
+    pub fn prev(&self) -> i32 {
+        self.prev
+    }
+
+    pub(crate) fn set_prev(&mut self, code: i32) {
+        self.prev = code;
+    }
+
+    /// The builtins this shell can run.
+    pub fn builtins(&self) -> &Builtins {
+        &self.builtins
+    }
+
+    pub fn builtins_mut(&mut self) -> &mut Builtins {
+        &mut self.builtins
+    }
+
+    pub fn option(&self, opt: Opt) -> bool {
+        self.options.get(opt)
+    }
+
+    pub fn set_option(&mut self, opt: Opt, on: bool) {
+        self.options.set(opt, on);
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// The positional parameters. Aka `$@`
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
     pub fn home(&self) -> &str {
         &self.home
     }
 
+    /// The logical working directory. This is `PWD` when it is set and
+    /// correct otherwise the real one.
+    pub fn pwd(&self) -> String {
+        use std::os::unix::fs::MetadataExt;
+
+        let same = |a: &str, b: &str| match (std::fs::metadata(a), std::fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        };
+
+        match self.get_env_exact("PWD") {
+            Some(pwd) if pwd.starts_with('/') && same(&pwd, ".") => pwd,
+            _ => std::env::current_dir()
+                .map(|d| d.to_string_lossy().into_owned())
+                .unwrap_or_else(|_| String::from("/")),
+        }
+    }
+
     pub fn get_env_exact(&self, key: &str) -> Option<String> {
-        std::env::var(key).ok()
+        if let Ok(num) = key.parse::<usize>() {
+            return if num == 0 {
+                Some(self.name.clone())
+            } else {
+                self.args.get(num - 1).cloned()
+            };
+        }
+
+        match key {
+            // these are technically more complicated but it works for now
+            "@" | "*" => Some(self.args.join(" ")),
+            "#" => Some(self.args.len().to_string()),
+            "?" => Some(self.prev.to_string()),
+            "$" => Some(std::process::id().to_string()),
+            "-" => {
+                let mut flags = self.options.flags();
+                if self.interactive {
+                    flags.push('i');
+                }
+                Some(flags)
+            }
+            _ => match self.vars.get(key) {
+                Some(var) => var.value().map(ToOwned::to_owned),
+                None => std::env::var(key).ok(),
+            },
+        }
+    }
+
+    /// Gets a variable set in this shell. This does not look at the
+    /// environment.
+    pub fn get_var(&self, key: &str) -> Option<&Var> {
+        self.vars.get(key)
+    }
+
+    /// Every variable set in this shell in order. This does not look at the
+    /// environment.
+    pub fn vars(&self) -> Vec<(&str, &Var)> {
+        let mut vars: Vec<_> = self.vars.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        vars.sort_unstable_by_key(|(k, _)| *k);
+        vars
+    }
+
+    /// Gets the elements of a variable. One that isn't an array is like an
+    /// array with just its value.
+    pub fn get_elements(&self, key: &str) -> Option<Vec<String>> {
+        match self.vars.get(key) {
+            Some(Var::Array(values)) => Some(values.clone()),
+            _ => self.get_env_exact(key).map(|v| vec![v]),
+        }
+    }
+
+    /// Sets a variable. Variables that are exported stay exported.
+    pub fn set_var(&mut self, key: &str, value: impl Into<String>) {
+        let value = value.into();
+        if std::env::var_os(key).is_some() {
+            std::env::set_var(key, value);
+        } else {
+            self.vars.insert(key.to_owned(), Var::Scalar(value));
+        }
+    }
+
+    /// Sets a variable to an array. Arrays can't be exported so this removes
+    /// it from the environment.
+    pub fn set_array(&mut self, key: &str, values: Vec<String>) {
+        std::env::remove_var(key);
+        self.vars.insert(key.to_owned(), Var::Array(values));
+    }
+
+    /// The code that runs for a condition. An empty string means it is
+    /// ignored.
+    pub fn trap(&self, cond: Condition) -> Option<&str> {
+        self.traps.get(&cond).map(String::as_str)
+    }
+
+    /// Every trap that is set in the order `trap -p` shows them.
+    pub fn traps(&self) -> impl Iterator<Item = (Condition, &str)> {
+        self.traps.iter().map(|(c, code)| (*c, code.as_str()))
+    }
+
+    /// Sets the code for a condition. This does not change how signals are
+    /// handled, see [`crate::signal`].
+    pub fn set_trap(&mut self, cond: Condition, code: String) {
+        self.traps.insert(cond, code);
+    }
+
+    pub fn remove_trap(&mut self, cond: Condition) -> Option<String> {
+        self.traps.remove(&cond)
     }
 
     /// Gets an variable from the current scope. This matches on the smallest
@@ -58,19 +345,296 @@ impl ShellState {
         (String::new(), key)
     }
 
-    pub fn get_history(&self, index: usize) -> Option<&str> {
-        let leng = self.hist.len();
-        if index > leng {
-            None
-        } else {
-            self.hist.get(leng - index).map(|s| s.as_str())
+    pub fn alias(&self, name: &str) -> Option<&str> {
+        self.aliases.get(name).map(String::as_str)
+    }
+
+    pub fn set_alias(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.aliases.insert(name.into(), value.into());
+    }
+
+    pub fn remove_alias(&mut self, name: &str) -> Option<String> {
+        self.aliases.remove(name)
+    }
+
+    pub fn clear_aliases(&mut self) {
+        self.aliases.clear();
+    }
+
+    /// Every alias sorted by name.
+    pub fn aliases(&self) -> Vec<(&str, &str)> {
+        let mut all: Vec<_> = self
+            .aliases
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        all.sort_unstable();
+        all
+    }
+
+    /// Gets the completer of a command. A command given as a path uses the
+    /// completer of its name when it has none of its own.
+    pub fn completer(&self, name: &str) -> Option<Rc<dyn Completer>> {
+        self.completers.get(name).or_else(|| {
+            let (_, base) = name.rsplit_once('/')?;
+            self.completers.get(base)
+        })
+    }
+
+    pub fn completers(&self) -> &Completers {
+        &self.completers
+    }
+
+    pub fn completers_mut(&mut self) -> &mut Completers {
+        &mut self.completers
+    }
+
+    /// Runs the hooks set with [`Shell::register_precmd`] then
+    /// `PROMPT_COMMAND`. They don't change `$?`.
+    pub(crate) fn run_precmd(&mut self) {
+        let prev = self.prev;
+        for hook in self.precmd.0.clone() {
+            hook(self);
+        }
+        if let Some(code) = self.get_env_exact("PROMPT_COMMAND") {
+            self.run_code(code, None, None);
+        }
+        self.prev = prev;
+    }
+
+    /// Runs some code in this shell and gives back the status of its last
+    /// command. This is used by `.`, `eval`, traps and hooks. Each command is
+    /// run like the shell's own, with the `DEBUG` and `ERR` traps and
+    /// `errexit`, and gets its streams from `io` or the shell's own when there
+    /// is none. When args are given they replace the positional parameters
+    /// while it runs.
+    pub(crate) fn run_code(
+        &mut self,
+        code: String,
+        args: Option<Vec<String>>,
+        io: Option<&Io>,
+    ) -> i32 {
+        let args = args.map(|a| std::mem::replace(&mut self.args, a));
+        let mut cmds = Parser::new(Lexer::new(OwnedCharBuffer::new(code)));
+        while let Some(res) = cmds.next(self) {
+            let cmd = match res {
+                Ok(cmd) => cmd,
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    break;
+                }
+            };
+            let streams = match io {
+                Some(io) => io.streams().change_context(ShellError::Spawn),
+                None => Ok(Streams::default()),
+            };
+            if let Err(e) = streams.and_then(|streams| self.run_checked(cmd, streams)) {
+                eprintln!("{:?}", e);
+                break;
+            }
+            if self.exit {
+                break;
+            }
+        }
+        if let Some(args) = args {
+            self.args = args;
+        }
+        self.prev
+    }
+
+    /// Runs a command the way the main loop does. The `DEBUG` trap runs
+    /// first, and when it fails the `ERR` trap runs and `errexit` is checked.
+    fn run_checked(&mut self, cmd: Cmd, streams: Streams) -> Result<(), ShellError> {
+        self.run_trap(Condition::Debug);
+        self.run_one(cmd, streams)?;
+        if self.prev != 0 && !self.tested {
+            self.run_trap(Condition::Err);
+            if self.option(Opt::Errexit) {
+                log::info!("exiting because a command failed");
+                self.exit = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a single command and waits for it.
+    fn run_one(&mut self, cmd: Cmd, streams: Streams) -> Result<(), ShellError> {
+        let handles = run_command(cmd, streams, self).change_context(ShellError::Spawn)?;
+
+        if !handles.is_empty() {
+            wait_all(handles, self).change_context(ShellError::Spawn)?;
         }
+        Ok(())
     }
 
-    /// Adds a command to this shells history
+    /// Runs the trap for a condition if one is set.
+    pub(crate) fn run_trap(&mut self, cond: Condition) {
+        if let Some(code) = self.trap(cond) {
+            let code = code.to_owned();
+            self.run_trap_code(code);
+        }
+    }
+
+    /// Runs the code of a trap right away. Traps don't set `$?` unless they
+    /// call `exit`, and other traps don't run inside them.
+    fn run_trap_code(&mut self, code: String) {
+        if self.trapping {
+            return;
+        }
+        let prev = self.prev;
+        self.trapping = true;
+        self.run_code(code, None, None);
+        self.trapping = false;
+        if !self.exit {
+            self.prev = prev;
+        }
+    }
+
+    /// Finds a program in `PATH`. Places are remembered so each command is
+    /// only searched for once until `PATH` changes.
+    pub fn find_command(&mut self, name: &str) -> Option<PathBuf> {
+        self.check_hash();
+        if let Some(h) = self.hash.commands.get_mut(name) {
+            h.hits += 1;
+            return Some(h.path.clone());
+        }
+
+        let path = search_path(name, &self.get_env_exact("PATH").unwrap_or_default())?;
+        self.hash.commands.insert(
+            name.to_owned(),
+            Hashed {
+                path: path.clone(),
+                hits: 1,
+            },
+        );
+        Some(path)
+    }
+
+    /// Gets where a command was found without searching for it.
+    pub fn hashed(&mut self, name: &str) -> Option<&Hashed> {
+        self.check_hash();
+        self.hash.commands.get(name)
+    }
+
+    /// Every remembered command sorted by name.
+    pub fn all_hashed(&mut self) -> Vec<(&str, &Hashed)> {
+        self.check_hash();
+        let mut all: Vec<_> = self
+            .hash
+            .commands
+            .iter()
+            .map(|(k, v)| (k.as_str(), v))
+            .collect();
+        all.sort_unstable_by_key(|(k, _)| *k);
+        all
+    }
+
+    /// Remembers a command as being at a path without checking it.
+    pub fn set_hashed(&mut self, name: &str, path: PathBuf) {
+        self.check_hash();
+        let hashed = Hashed { path, hits: 0 };
+        self.hash.commands.insert(name.to_owned(), hashed);
+    }
+
+    pub fn forget_command(&mut self, name: &str) -> bool {
+        self.hash.commands.remove(name).is_some()
+    }
+
+    pub fn forget_commands(&mut self) {
+        self.hash.commands.clear();
+    }
+
+    /// Clears the remembered commands if `PATH` is not what they were found
+    /// with.
+    fn check_hash(&mut self) {
+        let path = self.get_env_exact("PATH");
+        if self.hash.path != path {
+            self.hash.commands.clear();
+            self.hash.path = path;
+        }
+    }
+
+    /// Gets the line `index` commands back, where 1 is the last one.
+    pub fn get_history(&self, index: usize) -> Option<&str> {
+        let i = self.hist.len().checked_sub(index).filter(|_| index > 0)?;
+        Some(&self.hist.entries()[i].line)
+    }
+
+    pub fn history(&self) -> &History {
+        &self.hist
+    }
+
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.hist
+    }
+
+    /// Adds a command to this shells history. It is left out when
+    /// `HISTCONTROL` or `HISTIGNORE` say to, otherwise it is also added to
+    /// `HISTFILE`.
     pub fn add_history(&mut self, item: impl Into<String>) {
-        self.hist.push(item.into())
+        let filter = Filter::new(
+            &self.get_env_exact("HISTCONTROL").unwrap_or_default(),
+            &self.get_env_exact("HISTIGNORE").unwrap_or_default(),
+        );
+        let Some(entry) = self.hist.add(&item.into(), &filter, self.history_size()) else {
+            return;
+        };
+        if let Some(path) = self.history_file() {
+            if let Err(e) = history::append(&path, &entry) {
+                log::warn!("could not write to {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Reads the lines in `HISTFILE` into the history.
+    pub(crate) fn load_history(&mut self) {
+        let Some(path) = self.history_file() else {
+            return;
+        };
+        let size = self.history_size();
+        let filesize = self.get_env_exact("HISTFILESIZE");
+        if let Err(e) = self
+            .hist
+            .load(&path, history::size(filesize.as_deref(), size))
+        {
+            eprintln!("rush: {}: {}", path.display(), error_message(&e));
+        }
+        self.hist.limit(size);
     }
+
+    /// `HISTSIZE`, the most lines kept in memory.
+    fn history_size(&self) -> Option<usize> {
+        let size = self.get_env_exact("HISTSIZE");
+        history::size(size.as_deref(), Some(history::DEFAULT_SIZE))
+    }
+
+    /// Where the history is kept. An empty `HISTFILE` means it isn't.
+    pub(crate) fn history_file(&self) -> Option<PathBuf> {
+        match self.get_env_exact("HISTFILE") {
+            Some(file) if file.is_empty() => None,
+            Some(file) => Some(PathBuf::from(file)),
+            None => Some(PathBuf::from(&self.home).join(".rush_history")),
+        }
+    }
+}
+
+/// Searches each directory in a `PATH` for an executable file.
+pub fn search_path(name: &str, path: &str) -> Option<PathBuf> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+
+    std::env::split_paths(path)
+        .map(|dir| dir.join(name))
+        .find(|file| {
+            let Ok(c) = CString::new(file.as_os_str().as_bytes()) else {
+                return false;
+            };
+            file.is_file() && unsafe { libc::access(c.as_ptr(), libc::X_OK) } == 0
+        })
 }
 
 #[derive(Debug)]
@@ -124,9 +688,12 @@ where
 
 impl Shell<std::iter::Empty<Token>> {
     pub fn interactive() -> Shell<std::iter::Empty<Token>> {
+        let mut state = ShellState::default();
+        // `!` is only expanded in lines that are typed
+        state.set_option(Opt::Histexpand, true);
         Shell {
             cmmds: CommandSource::Interactive(Prompter::default()),
-            state: ShellState::default(),
+            state,
         }
     }
 
@@ -135,6 +702,23 @@ impl Shell<std::iter::Empty<Token>> {
     }
 }
 
+impl<C> Shell<Lexer<Verbose<C>>>
+where
+    C: Iterator<Item = char>,
+{
+    /// Constructs a shell that runs a script then exits. Unlike
+    /// [`Shell::sourced`] this can print the script as it is read when
+    /// `verbose` is set.
+    pub fn script(input: C) -> Self {
+        let state = ShellState::default();
+        let input = Verbose::new(input, state.options.verbose_flag());
+        Shell {
+            cmmds: CommandSource::NonInteractively(Parser::new(Lexer::new(input))),
+            state,
+        }
+    }
+}
+
 // Sudo code for how this could be inplemented
 // fn poll(//
 //     // reader: AtomicSlice<u8>
@@ -182,6 +766,86 @@ where
         }
     }
 
+    /// Turns an option on or off before the shell starts.
+    pub fn set_option(&mut self, opt: Opt, on: bool) {
+        self.state.set_option(opt, on);
+    }
+
+    /// Adds a builtin to this shell. It replaces any builtin with the same
+    /// name, including the ones the shell comes with.
+    pub fn register_builtin(
+        &mut self,
+        name: impl Into<String>,
+        builtin: impl ShellBuiltin + 'static,
+    ) {
+        self.state.builtins.register(name, builtin);
+    }
+
+    /// Adds a completer for the arguments of a command. It replaces one set
+    /// by `complete`.
+    pub fn register_completer(
+        &mut self,
+        name: impl Into<String>,
+        completer: impl Completer + 'static,
+    ) {
+        self.state.completers.set(name, Rc::new(completer));
+    }
+
+    /// Adds a function that runs before each prompt, before
+    /// `PROMPT_COMMAND`.
+    pub fn register_precmd(&mut self, hook: impl Fn(&mut ShellState) + 'static) {
+        self.state.precmd.0.push(Rc::new(hook));
+    }
+
+    /// Runs commands until the input runs out or `exit` is run.
+    fn main_loop(&mut self, live: bool) -> Result<(), ShellError> {
+        while let Some(res) = self.cmmds.next(&mut self.state) {
+            let cmd = match (res, live) {
+                (Ok(cmd), _) => cmd,
+                (Err(e), true) => {
+                    eprintln!("{:?}", e);
+                    continue;
+                }
+                (Err(e), false) => return Err(e.change_context(ShellError::Parse)),
+            };
+
+            // only a script can be checked this way
+            if !live && self.state.option(Opt::Noexec) {
+                continue;
+            }
+
+            match (self.state.run_checked(cmd, Streams::default()), live) {
+                (Ok(()), _) => {}
+                (Err(e), true) => {
+                    eprintln!("{:?}", e);
+                    continue;
+                }
+                (Err(e), false) => return Err(e),
+            }
+            self.handle_signals();
+
+            if self.state.exit {
+                log::info!("exiting beacuse flag was set");
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the traps of the signals that came in since the last command.
+    fn handle_signals(&mut self) {
+        for sig in signal::pending() {
+            log::info!("got signal {}", sig);
+            if self.state.trap(Condition::Signal(sig)).is_some() {
+                self.state.run_trap(Condition::Signal(sig));
+            } else if sig == libc::SIGINT && !self.state.interactive {
+                // a script stops on ^C like any other program would
+                self.state.prev = 128 + sig;
+                self.state.exit = true;
+            }
+        }
+    }
+
     /// Runs the main event loop for this shell. Gets commands from the its
     /// stream and evaluates them.
     ///
@@ -195,58 +859,46 @@ where
     /// When this is ran as a login shell it will refuse to panic or error.
     /// The shell will attempt to restart itself whenever some thing bad
     /// happens.
-    pub fn run(self, live: bool) -> Result<(), ShellError> {
-        self.run_with_output(live, Streams::default()).map(|_| {})
+    ///
+    /// Returns the status the shell exited with. This is the status of the
+    /// last command run unless `exit` was given one.
+    pub fn run(self, live: bool) -> Result<i32, ShellError> {
+        self.run_with_output(live, Streams::default())
+            .map(|o| o.status.code().unwrap_or(1))
     }
 
+    /// Same as [`Shell::run`] but gives back an [`std::process::Output`]. The
+    /// output of commands is not captured yet so only the status is set.
     pub fn run_with_output(
         mut self,
         live: bool,
         _streams: Streams,
     ) -> Result<std::process::Output, ShellError> {
-        while let Some(res) = self.cmmds.next(&mut self.state) {
-            let cmd = {
-                match (res, live) {
-                    (Ok(cmd), _) => cmd,
-                    (Err(e), true) => {
-                        eprintln!("{:?}", e);
-                        continue;
-                    }
-                    (Err(e), false) => return Err(e.change_context(ShellError::Parse)),
-                }
-            };
-
-            let res = run_command(cmd, Streams::default(), &mut self.state);
+        use std::os::unix::process::ExitStatusExt;
 
-            let handles = match (res, live) {
-                (Ok(a), _) => a,
-                (Err(e), true) => {
-                    eprintln!("{:?}", e);
-                    continue;
-                }
-                (Err(e), false) => return Err(e.change_context(ShellError::Spawn)),
-            };
+        self.state.interactive = live;
+        // a ^C stops the command that is running, not the shell
+        if let Err(e) = signal::catch(libc::SIGINT) {
+            log::warn!("could not catch SIGINT: {}", e);
+        }
 
-            for h in handles {
-                // loop {}
-                // let _ = h.poll();
-                self.state.prev = h.wait().change_context(ShellError::Spawn)?;
-                // .attach("task had internal error")?;
-            }
+        let res = self.main_loop(live);
 
-            if self.state.exit {
-                log::info!("exiting beacuse flag was set");
-                break;
-            }
+        // the exit trap runs however the shell stops
+        if let Some(code) = self.state.remove_trap(Condition::Exit) {
+            self.state.exit = false;
+            self.state.run_trap_code(code);
         }
+        res?;
         log::info!("no more commands.");
 
-        // Ok(std::process::Output {
-        //     status: todo!(),
-        //     stdout: todo!(),
-        //     stderr: todo!(),
-        // })
-        todo!()
+        // a wait status has the exit code in the second byte
+        let code = self.state.prev & 0xff;
+        Ok(std::process::Output {
+            status: std::process::ExitStatus::from_raw(code << 8),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        })
     }
 
     // pub fn next_prompt(&mut self, prompt: &str) -> Option<String> {
@@ -290,3 +942,6 @@ where
     //     }
     // }
 }
+
+pub mod history;
+pub mod options;
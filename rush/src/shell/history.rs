@@ -0,0 +1,472 @@
+//! The lines typed into an interactive shell. They are added to `HISTFILE`
+//! as they are run so later shells, and ones running at the same time, can
+//! see them.
+//!
+//! The file uses bash's format with timestamps. A line like `#1700000000`
+//! gives the time of the entry after it, and every line up to the next
+//! timestamp is part of that entry.
+
+use crate::util::glob_match;
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::ops::Bound;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many lines are kept when `HISTSIZE` isn't set.
+pub const DEFAULT_SIZE: usize = 500;
+
+/// A line in the history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub line: String,
+    /// When it was run in seconds since the epoch. Lines from files without
+    /// timestamps don't have one.
+    pub time: Option<u64>,
+    /// Where this is in the history, counting from one. Numbers stay the
+    /// same when older entries are dropped.
+    pub number: usize,
+}
+
+/// Which lines are kept. This is `HISTCONTROL` and `HISTIGNORE`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    /// Lines that start with a space aren't kept.
+    pub ignorespace: bool,
+    /// A line that is the same as the one before isn't kept.
+    pub ignoredups: bool,
+    /// Older copies of a line are removed when it is added.
+    pub erasedups: bool,
+    /// Patterns of lines that aren't kept. `&` is the line before.
+    pub ignore: Vec<String>,
+}
+
+impl Filter {
+    /// Reads the colon separated lists of `HISTCONTROL` and `HISTIGNORE`.
+    pub fn new(control: &str, ignore: &str) -> Filter {
+        let mut filter = Filter::default();
+        for word in control.split(':') {
+            match word {
+                "ignorespace" => filter.ignorespace = true,
+                "ignoredups" => filter.ignoredups = true,
+                "ignoreboth" => {
+                    filter.ignorespace = true;
+                    filter.ignoredups = true;
+                }
+                "erasedups" => filter.erasedups = true,
+                _ => {}
+            }
+        }
+        filter.ignore = ignore
+            .split(':')
+            .filter(|p| !p.is_empty())
+            .map(str::to_owned)
+            .collect();
+        filter
+    }
+
+    /// If a line should be kept. `prev` is the newest line in the history.
+    fn keeps(&self, line: &str, prev: Option<&str>) -> bool {
+        if line.trim().is_empty() || (self.ignorespace && line.starts_with(' ')) {
+            return false;
+        }
+        if self.ignoredups && prev == Some(line) {
+            return false;
+        }
+        !self.ignore.iter().any(|p| match p.as_str() {
+            "&" => prev == Some(line),
+            p => glob_match(p, line),
+        })
+    }
+}
+
+/// The history of a shell. It only holds what this shell read and added,
+/// the file has the lines of every shell.
+#[derive(Debug)]
+pub struct History {
+    /// Sorted by number.
+    entries: Vec<Entry>,
+    /// The number the next entry gets.
+    next: usize,
+    /// The numbers of the entries with each line. Lines starting with some
+    /// text are next to each other here so they are found without looking
+    /// through every entry.
+    index: BTreeMap<String, Vec<usize>>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            entries: Vec::new(),
+            next: 1,
+            index: BTreeMap::new(),
+        }
+    }
+}
+
+impl History {
+    /// Every entry from oldest to newest.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Gets an entry by its number.
+    pub fn get(&self, number: usize) -> Option<&Entry> {
+        let i = self
+            .entries
+            .binary_search_by_key(&number, |e| e.number)
+            .ok()?;
+        Some(&self.entries[i])
+    }
+
+    /// The newest entry older than `number` that starts with `prefix`.
+    /// Without a number this starts from the newest entry.
+    pub fn before(&self, number: Option<usize>, prefix: &str) -> Option<&Entry> {
+        let number = number.unwrap_or(usize::MAX);
+        if prefix.is_empty() {
+            let i = self.entries.partition_point(|e| e.number < number);
+            return i.checked_sub(1).map(|i| &self.entries[i]);
+        }
+
+        let found = self.with_prefix(prefix).filter_map(|numbers| {
+            let i = numbers.partition_point(|n| *n < number);
+            i.checked_sub(1).map(|i| numbers[i])
+        });
+        self.get(found.max()?)
+    }
+
+    /// The oldest entry newer than `number` that starts with `prefix`.
+    pub fn after(&self, number: usize, prefix: &str) -> Option<&Entry> {
+        if prefix.is_empty() {
+            let i = self.entries.partition_point(|e| e.number <= number);
+            return self.entries.get(i);
+        }
+
+        let found = self.with_prefix(prefix).filter_map(|numbers| {
+            let i = numbers.partition_point(|n| *n <= number);
+            numbers.get(i).copied()
+        });
+        self.get(found.min()?)
+    }
+
+    /// The numbers of each line that starts with `prefix`.
+    fn with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a Vec<usize>> {
+        self.index
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(line, _)| line.starts_with(prefix))
+            .map(|(_, numbers)| numbers)
+    }
+
+    /// The next entry with `pattern` in it, going older or newer from
+    /// `number`. Without a number this starts at the newest entry.
+    pub fn search(&self, number: Option<usize>, pattern: &str, older: bool) -> Option<&Entry> {
+        let number = number.unwrap_or(usize::MAX);
+        if older {
+            let i = self.entries.partition_point(|e| e.number < number);
+            self.entries[..i]
+                .iter()
+                .rev()
+                .find(|e| e.line.contains(pattern))
+        } else {
+            let i = self.entries.partition_point(|e| e.number <= number);
+            self.entries[i..].iter().find(|e| e.line.contains(pattern))
+        }
+    }
+
+    /// Adds a line unless the filter leaves it out. The entry is given back
+    /// when it was added so it can be written to the file.
+    pub fn add(&mut self, line: &str, filter: &Filter, size: Option<usize>) -> Option<Entry> {
+        let prev = self.entries.last().map(|e| e.line.as_str());
+        if !filter.keeps(line, prev) {
+            return None;
+        }
+        if filter.erasedups {
+            if let Some(numbers) = self.index.remove(line) {
+                self.entries
+                    .retain(|e| numbers.binary_search(&e.number).is_err());
+            }
+        }
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let entry = Entry {
+            line: line.to_owned(),
+            time: Some(time),
+            number: self.next,
+        };
+        self.push(entry.clone());
+        self.limit(size);
+        Some(entry)
+    }
+
+    /// Removes an entry by its number.
+    pub fn remove(&mut self, number: usize) -> Option<Entry> {
+        let i = self
+            .entries
+            .binary_search_by_key(&number, |e| e.number)
+            .ok()?;
+        let entry = self.entries.remove(i);
+        if let Some(numbers) = self.index.get_mut(&entry.line) {
+            numbers.retain(|n| *n != number);
+            if numbers.is_empty() {
+                self.index.remove(&entry.line);
+            }
+        }
+        Some(entry)
+    }
+
+    /// Removes every entry. New entries keep counting on from the old ones.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.index.clear();
+    }
+
+    /// Adds the entries of a file after the ones this has.
+    pub fn read(&mut self, path: &Path) -> io::Result<()> {
+        let mut text = String::new();
+        open(path, OpenOptions::new().read(true))?.read_to_string(&mut text)?;
+        for mut entry in parse(&text) {
+            entry.number = self.next;
+            self.push(entry);
+        }
+        Ok(())
+    }
+
+    /// Replaces a file with the entries this has.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut text = Vec::new();
+        for entry in &self.entries {
+            write_entry(&mut text, entry)?;
+        }
+        let mut file = open(path, OpenOptions::new().write(true).create(true))?;
+        // it is only cut once it's locked
+        file.set_len(0)?;
+        file.write_all(&text)
+    }
+
+    fn push(&mut self, entry: Entry) {
+        self.next = entry.number + 1;
+        let numbers = self.index.entry(entry.line.clone()).or_default();
+        numbers.push(entry.number);
+        self.entries.push(entry);
+    }
+
+    /// Drops the oldest entries so there are at most `size`.
+    pub fn limit(&mut self, size: Option<usize>) {
+        let Some(size) = size else {
+            return;
+        };
+        let extra = self.entries.len().saturating_sub(size);
+        for entry in self.entries.drain(..extra) {
+            // these are the oldest so they are first
+            if let Some(numbers) = self.index.get_mut(&entry.line) {
+                numbers.remove(0);
+                if numbers.is_empty() {
+                    self.index.remove(&entry.line);
+                }
+            }
+        }
+    }
+
+    /// Reads the entries of a file in front of the ones this has. The file
+    /// is cut down to `filesize` entries while it is open. Every entry is
+    /// numbered again from one.
+    pub fn load(&mut self, path: &Path, filesize: Option<usize>) -> io::Result<()> {
+        let mut file = open(path, OpenOptions::new().read(true).write(true).create(true))?;
+
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        let mut entries = parse(&text);
+
+        if let Some(size) = filesize.filter(|s| *s < entries.len()) {
+            entries.drain(..entries.len() - size);
+            let mut text = Vec::new();
+            for entry in &entries {
+                write_entry(&mut text, entry)?;
+            }
+            file.set_len(0)?;
+            file.rewind()?;
+            file.write_all(&text)?;
+        }
+
+        entries.append(&mut self.entries);
+        *self = History::default();
+        for mut entry in entries {
+            entry.number = self.next;
+            self.push(entry);
+        }
+        Ok(())
+    }
+}
+
+/// Adds an entry to the end of a file.
+pub fn append(path: &Path, entry: &Entry) -> io::Result<()> {
+    let mut file = open(path, OpenOptions::new().append(true).create(true))?;
+    // one write so other shells can't end up in the middle of it
+    let mut text = Vec::new();
+    write_entry(&mut text, entry)?;
+    file.write_all(&text)
+}
+
+/// Opens a history file and locks it. It is only readable by the user and
+/// the lock is let go when the file is closed.
+fn open(path: &Path, opts: &mut OpenOptions) -> io::Result<File> {
+    let file = opts.mode(0o600).open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+fn write_entry(out: &mut impl Write, entry: &Entry) -> io::Result<()> {
+    if let Some(time) = entry.time {
+        writeln!(out, "#{}", time)?;
+    }
+    writeln!(out, "{}", entry.line)
+}
+
+/// Reads the entries of a history file. Files without timestamps have an
+/// entry on each line.
+fn parse(text: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+    for line in text.lines() {
+        let time = line.strip_prefix('#').and_then(|t| t.parse().ok());
+        match (time, entries.last_mut()) {
+            (Some(time), _) => entries.push(Entry {
+                line: String::new(),
+                time: Some(time),
+                number: 0,
+            }),
+            (None, Some(last)) if last.time.is_some() => {
+                if !last.line.is_empty() {
+                    last.line.push('\n');
+                }
+                last.line.push_str(line);
+            }
+            (None, _) => entries.push(Entry {
+                line: line.to_owned(),
+                time: None,
+                number: 0,
+            }),
+        }
+    }
+    entries.retain(|e| !e.line.is_empty());
+    entries
+}
+
+/// Reads `HISTSIZE` or `HISTFILESIZE`. Values that are negative or not a
+/// number mean there is no limit.
+pub fn size(value: Option<&str>, default: Option<usize>) -> Option<usize> {
+    match value {
+        None => default,
+        Some(v) => v.trim().parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn filters() {
+        let filter = Filter::new("ignoreboth", "ls:cd *:&");
+        let mut hist = History::default();
+        for line in ["echo a", "echo a", " secret", "ls", "cd /tmp", "", "lsblk"] {
+            hist.add(line, &filter, None);
+        }
+        let lines: Vec<_> = hist.entries().iter().map(|e| e.line.as_str()).collect();
+        assert_eq!(lines, ["echo a", "lsblk"]);
+
+        let filter = Filter::new("erasedups", "");
+        for line in ["a", "b", "a", "c"] {
+            hist.add(line, &filter, Some(3));
+        }
+        let lines: Vec<_> = hist.entries().iter().map(|e| e.line.as_str()).collect();
+        assert_eq!(lines, ["b", "a", "c"]);
+
+        assert_eq!(hist.get(4).map(|e| e.line.as_str()), Some("b"));
+        assert!(hist.get(3).is_none());
+
+        assert_eq!(size(Some("-1"), Some(DEFAULT_SIZE)), None);
+        assert_eq!(size(Some("20"), None), Some(20));
+    }
+
+    #[test]
+    fn file() {
+        let text = "old\n#10\nfor x\ndone\n#20\nls\n";
+        let parsed = parse(text);
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[1].line, "for x\ndone");
+        assert_eq!(parsed[2].time, Some(20));
+
+        let path = std::env::temp_dir().join(format!("rush-history-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        for entry in &parsed {
+            append(&path, entry).unwrap();
+        }
+        let mut hist = History::default();
+        hist.load(&path, Some(2)).unwrap();
+        let lines: Vec<_> = hist.entries().iter().map(|e| e.line.as_str()).collect();
+        assert_eq!(lines, ["for x\ndone", "ls"]);
+        assert_eq!(hist.entries()[1].number, 2);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "#10\nfor x\ndone\n#20\nls\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn lookups() {
+        let mut hist = History::default();
+        for line in ["git status", "ls", "git log", "grep x", "git status", "ls"] {
+            hist.add(line, &Filter::default(), None);
+        }
+        let line = |e: Option<&Entry>| e.map(|e| (e.number, e.line.clone()));
+
+        assert_eq!(line(hist.before(None, "")), Some((6, "ls".into())));
+        assert_eq!(
+            line(hist.before(None, "git")),
+            Some((5, "git status".into()))
+        );
+        assert_eq!(
+            line(hist.before(Some(5), "git")),
+            Some((3, "git log".into()))
+        );
+        assert_eq!(line(hist.before(Some(1), "git")), None);
+        assert_eq!(line(hist.after(1, "g")), Some((3, "git log".into())));
+        assert_eq!(line(hist.after(5, "git")), None);
+
+        assert_eq!(line(hist.search(None, "s", true)), Some((6, "ls".into())));
+        assert_eq!(
+            line(hist.search(Some(5), "x", true)),
+            Some((4, "grep x".into()))
+        );
+        assert_eq!(
+            line(hist.search(Some(2), "stat", false)),
+            Some((5, "git status".into()))
+        );
+
+        hist.limit(Some(3));
+        assert_eq!(
+            line(hist.before(None, "git")),
+            Some((5, "git status".into()))
+        );
+        assert_eq!(line(hist.before(Some(5), "git")), None);
+    }
+}
+
+pub mod expand;
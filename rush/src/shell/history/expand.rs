@@ -0,0 +1,372 @@
+//! History expansion, like `!!` and `^old^new`, on lines typed into an
+//! interactive shell. It is turned off with `set +H`.
+//!
+//! An expansion is an event that picks an entry (`!!`, `!n`, `!-n`,
+//! `!string` or `!?string?`), then maybe a word designator (`:n`, `^`, `$`,
+//! `*` or `x-y`) and modifiers (`:h`, `:t`, `:r`, `:e`, `:p`, `:s/a/b/` and
+//! `:gs/a/b/`). `!$`, `!^` and `!*` are short for the words of `!!`.
+
+use super::{Entry, History};
+
+/// A line after expansion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expanded {
+    pub line: String,
+    /// If `:p` was used. The line is shown and added to the history but
+    /// not run.
+    pub print: bool,
+}
+
+/// Expands every `!` in a line. Nothing is given back when there weren't
+/// any. Errors are the message to show.
+pub fn expand(line: &str, hist: &History) -> Result<Option<Expanded>, String> {
+    let mut chars = Expander {
+        chars: line.chars().collect(),
+        at: 0,
+        hist,
+        print: false,
+    };
+    let mut out = String::new();
+    let mut changed = false;
+
+    if line.starts_with('^') {
+        out = chars.quick_substitution()?;
+        changed = true;
+    }
+
+    let mut single = false;
+    let mut double = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if !single => {
+                out.push(c);
+                out.extend(chars.next());
+            }
+            '\'' if !double => {
+                single = !single;
+                out.push(c);
+            }
+            '"' if !single => {
+                double = !double;
+                out.push(c);
+            }
+            '!' if !single && !chars.plain_bang(double) => {
+                out.push_str(&chars.expansion()?);
+                changed = true;
+            }
+            c => out.push(c),
+        }
+    }
+
+    Ok(changed.then_some(Expanded {
+        line: out,
+        print: chars.print,
+    }))
+}
+
+struct Expander<'a> {
+    chars: Vec<char>,
+    at: usize,
+    hist: &'a History,
+    /// `:p` was seen.
+    print: bool,
+}
+
+impl Expander<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.at).copied()
+    }
+
+    fn peek_at(&self, ahead: usize) -> Option<char> {
+        self.chars.get(self.at + ahead).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.at += 1;
+        Some(c)
+    }
+
+    /// Takes characters while they match.
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.peek().filter(|c| f(*c)) {
+            s.push(c);
+            self.at += 1;
+        }
+        s
+    }
+
+    /// Takes characters up to a delimiter and skips the delimiter. A line
+    /// can end it too.
+    fn take_until(&mut self, delim: char) -> String {
+        let s = self.take_while(|c| c != delim && c != '\n');
+        if self.peek() == Some(delim) {
+            self.at += 1;
+        }
+        s
+    }
+
+    /// The text from `start` to here, for error messages.
+    fn since(&self, start: usize) -> String {
+        self.chars[start..self.at].iter().collect()
+    }
+
+    /// A `!` that is just a `!` because of what comes after it.
+    fn plain_bang(&self, double: bool) -> bool {
+        match self.peek() {
+            None | Some(' ' | '\t' | '\n' | '=' | '(') => true,
+            Some('"') => double,
+            Some(_) => false,
+        }
+    }
+
+    /// `^old^new^` at the start of a line is `!!:s^old^new^`.
+    fn quick_substitution(&mut self) -> Result<String, String> {
+        self.at = 1;
+        let old = self.take_until('^');
+        let new = self.take_until('^');
+        let prev = event_line(self.hist.entries().last(), "!!")?;
+        substitute(&prev, &old, &new, false)
+            .ok_or_else(|| format!("^{}^{}: substitution failed", old, new))
+    }
+
+    /// Reads an expansion after a `!` and gives back its text.
+    fn expansion(&mut self) -> Result<String, String> {
+        let start = self.at - 1;
+        let entries = self.hist.entries();
+
+        let event = match self.peek() {
+            Some('!') => {
+                self.at += 1;
+                entries.last()
+            }
+            // `!$`, `!^` and `!*` are words of the last command
+            Some('$' | '^' | '*') => entries.last(),
+            Some('-') => {
+                self.at += 1;
+                let n: usize = self.take_while(|c| c.is_ascii_digit()).parse().unwrap_or(0);
+                entries.len().checked_sub(n).and_then(|i| entries.get(i))
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let n = self.take_while(|c| c.is_ascii_digit()).parse().unwrap_or(0);
+                self.hist.get(n)
+            }
+            Some('?') => {
+                self.at += 1;
+                let pattern = self.take_until('?');
+                self.hist.search(None, &pattern, true)
+            }
+            _ => {
+                let prefix = self.take_while(|c| !" \t\n:;&|()<>\"'".contains(c));
+                self.hist.before(None, &prefix)
+            }
+        };
+        let text = event_line(event, &self.since(start))?;
+
+        let mut text = self.words(&text, start)?;
+        self.modifiers(&mut text, start)?;
+        Ok(text)
+    }
+
+    /// Reads a word designator and picks those words out of the line.
+    fn words(&mut self, line: &str, start: usize) -> Result<String, String> {
+        let colon = self.peek() == Some(':')
+            && self
+                .peek_at(1)
+                .is_some_and(|c| c.is_ascii_digit() || "^$*-".contains(c));
+        let bare = self.peek().is_some_and(|c| "^$*-".contains(c));
+        if !colon && !bare {
+            return Ok(line.to_owned());
+        }
+        if colon {
+            self.at += 1;
+        }
+
+        let words = split(line);
+        let last = words.len().saturating_sub(1);
+        let index = |s: &mut Self| -> Option<usize> {
+            match s.peek()? {
+                '^' => {
+                    s.at += 1;
+                    Some(1)
+                }
+                '$' => {
+                    s.at += 1;
+                    Some(last)
+                }
+                c if c.is_ascii_digit() => s.take_while(|c| c.is_ascii_digit()).parse().ok(),
+                _ => None,
+            }
+        };
+
+        let (from, to) = if self.peek() == Some('*') {
+            self.at += 1;
+            (1, Some(last))
+        } else {
+            let from = index(self).unwrap_or(0);
+            match self.peek() {
+                Some('*') => {
+                    self.at += 1;
+                    (from, Some(last))
+                }
+                Some('-') => {
+                    self.at += 1;
+                    // `x-` leaves off the last word
+                    (from, index(self).or(last.checked_sub(1)))
+                }
+                _ => (from, Some(from)),
+            }
+        };
+
+        match to {
+            // `*` with only a command is nothing
+            Some(to) if from > to && from == 1 && to == 0 => Ok(String::new()),
+            Some(to) if from <= to && to < words.len() => Ok(words[from..=to].join(" ")),
+            _ => Err(format!("{}: bad word specifier", self.since(start))),
+        }
+    }
+
+    /// Reads modifiers like `:h` and `:s/old/new/` and changes the text.
+    fn modifiers(&mut self, text: &mut String, start: usize) -> Result<(), String> {
+        while self.peek() == Some(':') {
+            let global = self.peek_at(1) == Some('g');
+            let letter = self.peek_at(if global { 2 } else { 1 });
+            let changed = match letter {
+                Some('h') => text.rfind('/').map(|i| text[..i].to_owned()),
+                Some('t') => text.rfind('/').map(|i| text[i + 1..].to_owned()),
+                Some('r') => suffix(text).map(|i| text[..i].to_owned()),
+                Some('e') => suffix(text).map(|i| text[i..].to_owned()),
+                Some('p') => {
+                    self.print = true;
+                    None
+                }
+                Some('s') => None,
+                _ => return Ok(()),
+            };
+            self.at += if global { 3 } else { 2 };
+
+            if letter == Some('s') {
+                let Some(delim) = self.next() else {
+                    return Err(format!("{}: bad word specifier", self.since(start)));
+                };
+                let old = self.take_until(delim);
+                let new = self.take_until(delim);
+                *text = substitute(text, &old, &new, global)
+                    .ok_or_else(|| format!("{}: substitution failed", self.since(start)))?;
+            } else if let Some(changed) = changed {
+                *text = changed;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The line of an entry. `name` is how it was asked for.
+fn event_line(event: Option<&Entry>, name: &str) -> Result<String, String> {
+    match event {
+        Some(entry) => Ok(entry.line.clone()),
+        None => Err(format!("{}: event not found", name)),
+    }
+}
+
+/// Replaces `old` with `new`, where `&` in `new` is `old`. Nothing is given
+/// back if `old` isn't there.
+fn substitute(text: &str, old: &str, new: &str, global: bool) -> Option<String> {
+    if old.is_empty() || !text.contains(old) {
+        return None;
+    }
+    let new = new.replace('&', old);
+    Some(match global {
+        true => text.replace(old, &new),
+        false => text.replacen(old, &new, 1),
+    })
+}
+
+/// Where the `.suffix` of the last part of a path starts.
+fn suffix(text: &str) -> Option<usize> {
+    let dot = text.rfind('.')?;
+    (!text[dot..].contains('/')).then_some(dot)
+}
+
+/// Splits a line into words at spaces outside of quotes. The quotes are
+/// kept.
+fn split(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+    for c in line.trim_end_matches('\n').chars() {
+        match (c, quote) {
+            _ if escaped => escaped = false,
+            ('\\', Some('"') | None) => escaped = true,
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (' ' | '\t' | '\n', None) => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shell::history::Filter;
+
+    fn history() -> History {
+        let mut hist = History::default();
+        for line in ["cd /usr/lib", "echo 'a b' c", "tar xf file.tar.gz dir"] {
+            hist.add(line, &Filter::default(), None);
+        }
+        hist
+    }
+
+    fn ex(line: &str) -> Result<Option<String>, String> {
+        expand(line, &history()).map(|e| e.map(|e| e.line))
+    }
+
+    #[test]
+    fn events() {
+        assert_eq!(
+            ex("sudo !!"),
+            Ok(Some("sudo tar xf file.tar.gz dir".into()))
+        );
+        assert_eq!(ex("!1"), Ok(Some("cd /usr/lib".into())));
+        assert_eq!(ex("!-2"), Ok(Some("echo 'a b' c".into())));
+        assert_eq!(ex("!cd; ls"), Ok(Some("cd /usr/lib; ls".into())));
+        assert_eq!(ex("!?xf?"), Ok(Some("tar xf file.tar.gz dir".into())));
+        assert_eq!(ex("!nope"), Err("!nope: event not found".into()));
+        assert_eq!(ex("echo hi!"), Ok(None));
+        assert_eq!(ex("echo '!!' \\!!"), Ok(None));
+    }
+
+    #[test]
+    fn words_and_modifiers() {
+        assert_eq!(ex("ls !$"), Ok(Some("ls dir".into())));
+        assert_eq!(ex("echo !*"), Ok(Some("echo xf file.tar.gz dir".into())));
+        assert_eq!(ex("!echo:1"), Ok(Some("'a b'".into())));
+        assert_eq!(ex("!!:0-1"), Ok(Some("tar xf".into())));
+        assert_eq!(ex("!!:2-"), Ok(Some("file.tar.gz".into())));
+        assert_eq!(ex("!!:2:r"), Ok(Some("file.tar".into())));
+        assert_eq!(ex("!1:1:h"), Ok(Some("/usr".into())));
+        assert_eq!(ex("!1:1:t"), Ok(Some("lib".into())));
+        assert_eq!(ex("!!:9"), Err("!!:9: bad word specifier".into()));
+        assert_eq!(ex("!!:s/xf/tf/"), Ok(Some("tar tf file.tar.gz dir".into())));
+        assert_eq!(ex("!1:gs/l/L"), Ok(Some("cd /usr/Lib".into())));
+        assert_eq!(ex("^dir^&s"), Ok(Some("tar xf file.tar.gz dirs".into())));
+        assert_eq!(ex("^zz^y"), Err("^zz^y: substitution failed".into()));
+
+        let printed = expand("!!:p", &history()).unwrap().unwrap();
+        assert!(printed.print);
+    }
+}
@@ -0,0 +1,195 @@
+//! Options that change how the shell runs. They are set with `set` and
+//! `shopt` or on the command line.
+
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+/// A single shell option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Opt {
+    /// `-e` exit when a command fails.
+    Errexit,
+    /// `-C` don't let `>` overwrite files.
+    Noclobber,
+    /// `-n` read commands without running them.
+    Noexec,
+    /// `-f` don't expand paths.
+    Noglob,
+    /// `-H` expand `!` from the history in typed lines.
+    Histexpand,
+    /// `-u` treat using an unset variable as an error.
+    Nounset,
+    /// A pipeline fails when any of its commands fail.
+    Pipefail,
+    /// `-v` print input as it is read.
+    Verbose,
+    /// `-x` print commands before they run.
+    Xtrace,
+    /// Edit lines with emacs keys.
+    Emacs,
+    /// Edit lines with vi keys.
+    Vi,
+    /// Aliases are expanded.
+    ExpandAliases,
+    /// `.` searches `PATH` for files without a slash.
+    Sourcepath,
+}
+
+impl Opt {
+    /// The options of `set` in the order `set -o` shows them.
+    pub const SET: &'static [Opt] = &[
+        Opt::Emacs,
+        Opt::Errexit,
+        Opt::Histexpand,
+        Opt::Noclobber,
+        Opt::Noexec,
+        Opt::Noglob,
+        Opt::Nounset,
+        Opt::Pipefail,
+        Opt::Verbose,
+        Opt::Vi,
+        Opt::Xtrace,
+    ];
+
+    /// The options only `shopt` changes.
+    pub const SHOPT: &'static [Opt] = &[Opt::ExpandAliases, Opt::Sourcepath];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Opt::Errexit => "errexit",
+            Opt::Noclobber => "noclobber",
+            Opt::Noexec => "noexec",
+            Opt::Noglob => "noglob",
+            Opt::Histexpand => "histexpand",
+            Opt::Nounset => "nounset",
+            Opt::Pipefail => "pipefail",
+            Opt::Verbose => "verbose",
+            Opt::Xtrace => "xtrace",
+            Opt::Emacs => "emacs",
+            Opt::Vi => "vi",
+            Opt::ExpandAliases => "expand_aliases",
+            Opt::Sourcepath => "sourcepath",
+        }
+    }
+
+    /// The letter used for this with `set -e` and in `$-`.
+    pub fn letter(self) -> Option<char> {
+        match self {
+            Opt::Errexit => Some('e'),
+            Opt::Noclobber => Some('C'),
+            Opt::Noexec => Some('n'),
+            Opt::Noglob => Some('f'),
+            Opt::Histexpand => Some('H'),
+            Opt::Nounset => Some('u'),
+            Opt::Verbose => Some('v'),
+            Opt::Xtrace => Some('x'),
+            Opt::Pipefail | Opt::Emacs | Opt::Vi | Opt::ExpandAliases | Opt::Sourcepath => None,
+        }
+    }
+
+    /// Gets one of the options of `set` by its long name.
+    pub fn from_name(name: &str) -> Option<Opt> {
+        Opt::SET.iter().copied().find(|o| o.name() == name)
+    }
+
+    pub fn from_letter(c: char) -> Option<Opt> {
+        Opt::SET.iter().copied().find(|o| o.letter() == Some(c))
+    }
+
+    /// Gets one of the options only `shopt` changes by its name.
+    pub fn from_shopt(name: &str) -> Option<Opt> {
+        Opt::SHOPT.iter().copied().find(|o| o.name() == name)
+    }
+}
+
+/// Which options are on.
+#[derive(Debug, Clone)]
+pub struct Options {
+    on: BTreeSet<Opt>,
+    /// `verbose` is shared with the input of a script so it can print lines
+    /// as they are read.
+    verbose: Rc<Cell<bool>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            on: BTreeSet::from([Opt::Emacs, Opt::ExpandAliases, Opt::Sourcepath]),
+            verbose: Rc::default(),
+        }
+    }
+}
+
+impl Options {
+    pub fn get(&self, opt: Opt) -> bool {
+        match opt {
+            Opt::Verbose => self.verbose.get(),
+            opt => self.on.contains(&opt),
+        }
+    }
+
+    /// Turns an option on or off. Only one of `emacs` and `vi` can be on.
+    pub fn set(&mut self, opt: Opt, on: bool) {
+        match (opt, on) {
+            (Opt::Emacs, true) => {
+                self.on.remove(&Opt::Vi);
+                self.on.insert(opt);
+            }
+            (Opt::Vi, true) => {
+                self.on.remove(&Opt::Emacs);
+                self.on.insert(opt);
+            }
+            (Opt::Verbose, on) => self.verbose.set(on),
+            (opt, true) => {
+                self.on.insert(opt);
+            }
+            (opt, false) => {
+                self.on.remove(&opt);
+            }
+        }
+    }
+
+    /// The letters of every option that is on. This is `$-` without the
+    /// `i` for interactive shells.
+    pub fn flags(&self) -> String {
+        Opt::SET
+            .iter()
+            .filter(|o| self.get(**o))
+            .filter_map(|o| o.letter())
+            .collect()
+    }
+
+    /// A handle to `verbose` that sees it change.
+    pub(crate) fn verbose_flag(&self) -> Rc<Cell<bool>> {
+        Rc::clone(&self.verbose)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn option_flags() {
+        let mut opts = Options::default();
+        assert_eq!(opts.flags(), "");
+
+        opts.set(Opt::Xtrace, true);
+        opts.set(Opt::Errexit, true);
+        opts.set(Opt::Pipefail, true);
+        opts.set(Opt::Verbose, true);
+        assert_eq!(opts.flags(), "evx");
+        assert!(opts.verbose_flag().get());
+
+        opts.set(Opt::Errexit, false);
+        assert_eq!(opts.flags(), "vx");
+        assert_eq!(Opt::from_letter('C'), Some(Opt::Noclobber));
+        assert_eq!(Opt::from_name("sourcepath"), None);
+
+        opts.set(Opt::Vi, true);
+        assert!(!opts.get(Opt::Emacs));
+        opts.set(Opt::Emacs, true);
+        assert!(!opts.get(Opt::Vi));
+    }
+}
@@ -0,0 +1,167 @@
+//! Signal handling for the shell.
+//!
+//! Signals are caught by a handler that writes their number to a pipe. The
+//! shell reads the pipe between commands with [`pending`] so traps never run
+//! in the middle of something else.
+
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// The write end of the self pipe, or -1 before [`init`] is called.
+static WRITE: AtomicI32 = AtomicI32::new(-1);
+static READ: AtomicI32 = AtomicI32::new(-1);
+
+/// Every signal by name without the `SIG` prefix.
+pub const SIGNALS: &[(&str, libc::c_int)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+/// Gets a signal from a name like `INT`, `SIGINT` or `2`.
+pub fn from_name(name: &str) -> Option<libc::c_int> {
+    if let Ok(n) = name.parse::<libc::c_int>() {
+        return SIGNALS.iter().find(|(_, s)| *s == n).map(|(_, s)| *s);
+    }
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS.iter().find(|(n, _)| *n == name).map(|(_, s)| *s)
+}
+
+pub fn name(sig: libc::c_int) -> Option<&'static str> {
+    SIGNALS.iter().find(|(_, s)| *s == sig).map(|(n, _)| *n)
+}
+
+/// Makes the self pipe. This has to be called before anything is caught.
+pub fn init() -> std::io::Result<()> {
+    if READ.load(Ordering::Relaxed) >= 0 {
+        return Ok(());
+    }
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    READ.store(fds[0], Ordering::Relaxed);
+    WRITE.store(fds[1], Ordering::Relaxed);
+    Ok(())
+}
+
+extern "C" fn handler(sig: libc::c_int) {
+    let fd: RawFd = WRITE.load(Ordering::Relaxed);
+    if fd < 0 {
+        return;
+    }
+    // only async signal safe calls can be made here
+    let byte = sig as u8;
+    unsafe {
+        let errno = *libc::__errno_location();
+        libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+        *libc::__errno_location() = errno;
+    }
+}
+
+fn handler_addr() -> libc::sighandler_t {
+    handler as extern "C" fn(libc::c_int) as libc::sighandler_t
+}
+
+fn set(sig: libc::c_int, action: libc::sighandler_t) -> std::io::Result<()> {
+    unsafe {
+        let mut act = std::mem::zeroed::<libc::sigaction>();
+        act.sa_sigaction = action;
+        act.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut act.sa_mask);
+        if libc::sigaction(sig, &act, std::ptr::null_mut()) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Catches a signal so it is given by [`pending`]. Programs the shell runs
+/// still get the default action.
+pub fn catch(sig: libc::c_int) -> std::io::Result<()> {
+    init()?;
+    set(sig, handler_addr())
+}
+
+/// Ignores a signal. Programs the shell runs ignore it too.
+pub fn ignore(sig: libc::c_int) -> std::io::Result<()> {
+    set(sig, libc::SIG_IGN)
+}
+
+/// Gives a signal its default action.
+pub fn reset(sig: libc::c_int) -> std::io::Result<()> {
+    set(sig, libc::SIG_DFL)
+}
+
+/// Puts back the default action for every caught signal. This is for child
+/// processes that run shell code, which don't share the shell's traps.
+pub fn reset_caught() {
+    for (_, sig) in SIGNALS {
+        let mut old = unsafe { std::mem::zeroed::<libc::sigaction>() };
+        unsafe { libc::sigaction(*sig, std::ptr::null(), &mut old) };
+        if old.sa_sigaction == handler_addr() {
+            let _ = reset(*sig);
+        }
+    }
+}
+
+/// Gets every signal that was caught since this was last called.
+pub fn pending() -> Vec<libc::c_int> {
+    let fd = READ.load(Ordering::Relaxed);
+    if fd < 0 {
+        return vec![];
+    }
+
+    let mut sigs = vec![];
+    let mut buf = [0u8; 64];
+    loop {
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n <= 0 {
+            break;
+        }
+        sigs.extend(buf[..n as usize].iter().map(|b| *b as libc::c_int));
+    }
+    sigs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn signal_names() {
+        assert_eq!(from_name("INT"), Some(libc::SIGINT));
+        assert_eq!(from_name("sigterm"), Some(libc::SIGTERM));
+        assert_eq!(from_name("9"), Some(libc::SIGKILL));
+        assert_eq!(from_name("NOPE"), None);
+        assert_eq!(name(libc::SIGHUP), Some("HUP"));
+    }
+}
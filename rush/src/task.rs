@@ -6,6 +6,8 @@ use crate::prelude::*;
 #[must_use]
 pub enum Task {
     System(std::process::Child),
+    /// A builtin that was run in a child process, like in a pipeline
+    Forked(libc::pid_t),
     Builtin(i32),
 }
 
@@ -28,25 +30,65 @@ impl Task {
             Task::System(mut c) => {
                 let pid = c.id();
                 // c.try_wait()
-                let code = c
-                    .wait()
-                    .change_context(TaskError::Wait)?
-                    .code()
-                    .unwrap_or(-127);
+                let status = c.wait().change_context(TaskError::Wait)?;
+                let code = status_code(status);
                 log::info!("process ({}): exit {}", pid, code);
                 Ok(code)
             }
+            Task::Forked(pid) => {
+                use std::os::unix::process::ExitStatusExt;
+
+                let mut status = 0;
+                while unsafe { libc::waitpid(pid, &mut status, 0) } < 0 {
+                    let err = std::io::Error::last_os_error();
+                    if err.kind() != std::io::ErrorKind::Interrupted {
+                        return Err(err).change_context(TaskError::Wait);
+                    }
+                }
+                let code = status_code(std::process::ExitStatus::from_raw(status));
+                log::info!("builtin ({}): exit {}", pid, code);
+                Ok(code)
+            }
             Task::Builtin(code) => Ok(code),
         }
     }
 
+    /// Stops the task and reaps it. This is for tasks that were started but
+    /// can't be used, like the first part of a pipeline that failed to start.
+    pub fn kill(self) {
+        match self {
+            Task::System(mut c) => {
+                let _ = c.kill();
+                let _ = c.wait();
+            }
+            Task::Forked(pid) => {
+                unsafe { libc::kill(pid, libc::SIGKILL) };
+                let _ = Task::Forked(pid).wait();
+            }
+            Task::Builtin(_) => {}
+        }
+    }
+
     pub fn poll(&mut self) -> std::task::Poll<Result<i32, TaskError>> {
         match self {
             Task::System(c) => {
                 let _ = c.try_wait();
                 todo!()
             }
+            Task::Forked(_) => todo!(),
             Task::Builtin(_) => todo!(),
         }
     }
 }
+
+/// Gets the status the shell shows for a process. One killed by a signal
+/// exits with 128 + the signal.
+fn status_code(status: std::process::ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(sig)) => 128 + sig,
+        (None, None) => 1,
+    }
+}
@@ -1,8 +1,11 @@
 #![allow(unused)]
 
 use std::{
+    cell::Cell,
+    ffi::{CStr, CString},
     fmt::Debug,
     mem,
+    rc::Rc,
     sync::{atomic::AtomicPtr, Arc},
 };
 
@@ -26,7 +29,9 @@ pub(crate) struct OwnedCharBuffer {
 impl From<String> for OwnedCharBuffer {
     fn from(value: String) -> Self {
         // safety: chars will not escape the scope of this object
-        let chars = unsafe { mem::transmute(value.chars()) };
+        let chars = unsafe {
+            mem::transmute::<std::str::Chars<'_>, std::str::Chars<'static>>(value.chars())
+        };
         Self { _s: value, chars }
     }
 }
@@ -45,6 +50,210 @@ impl Iterator for OwnedCharBuffer {
     }
 }
 
+/// Prints input to stderr a line at a time while a flag is on. This is how
+/// `verbose` shows a script as it is read.
+pub struct Verbose<I> {
+    chars: I,
+    line: String,
+    on: Rc<Cell<bool>>,
+}
+
+impl<I> Verbose<I> {
+    pub fn new(chars: I, on: Rc<Cell<bool>>) -> Self {
+        Verbose {
+            chars,
+            line: String::new(),
+            on,
+        }
+    }
+}
+
+impl<I: Iterator<Item = char>> Iterator for Verbose<I> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        match c {
+            Some('\n') => {
+                if self.on.get() {
+                    eprintln!("{}", self.line);
+                }
+                self.line.clear();
+            }
+            Some(c) => self.line.push(c),
+            None if !self.line.is_empty() => {
+                if self.on.get() {
+                    eprintln!("{}", self.line);
+                }
+                self.line.clear();
+            }
+            None => {}
+        }
+        c
+    }
+}
+
+/// Formats an io error the way other shells show them, without the
+/// `(os error N)` rust adds on the end.
+pub fn error_message(e: &std::io::Error) -> String {
+    let msg = e.to_string();
+    match (e.raw_os_error(), msg.rfind(" (os error ")) {
+        (Some(_), Some(i)) => msg[..i].to_owned(),
+        _ => msg,
+    }
+}
+
+/// Formats a time, in seconds since the epoch, in the local time zone the
+/// way `strftime(3)` does.
+pub fn strftime(format: &str, time: u64) -> String {
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+    let time = time as libc::time_t;
+    let mut buf = [0u8; 256];
+    let len = unsafe {
+        let mut tm = std::mem::zeroed::<libc::tm>();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return String::new();
+        }
+        libc::strftime(buf.as_mut_ptr().cast(), buf.len(), format.as_ptr(), &tm)
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// How many columns a character takes up on a terminal. Marks that join
+/// the character before them take none and most CJK and emoji take two.
+pub fn width(c: char) -> usize {
+    const ZERO: &[(u32, u32)] = &[
+        (0x0300, 0x036F),
+        (0x0483, 0x0489),
+        (0x0591, 0x05BD),
+        (0x0610, 0x061A),
+        (0x064B, 0x065F),
+        (0x0670, 0x0670),
+        (0x06D6, 0x06DC),
+        (0x0E31, 0x0E31),
+        (0x0E34, 0x0E3A),
+        (0x0E47, 0x0E4E),
+        (0x1AB0, 0x1AFF),
+        (0x1DC0, 0x1DFF),
+        (0x200B, 0x200F),
+        (0x2028, 0x202E),
+        (0x2060, 0x2064),
+        (0x20D0, 0x20FF),
+        (0xFE00, 0xFE0F),
+        (0xFE20, 0xFE2F),
+        (0xFEFF, 0xFEFF),
+        (0xE0100, 0xE01EF),
+    ];
+    const WIDE: &[(u32, u32)] = &[
+        (0x1100, 0x115F),
+        (0x231A, 0x231B),
+        (0x2329, 0x232A),
+        (0x23E9, 0x23EC),
+        (0x25FD, 0x25FE),
+        (0x2614, 0x2615),
+        (0x2648, 0x2653),
+        (0x26AA, 0x26AB),
+        (0x26BD, 0x26BE),
+        (0x26C4, 0x26C5),
+        (0x2705, 0x2705),
+        (0x270A, 0x270B),
+        (0x274C, 0x274C),
+        (0x2753, 0x2757),
+        (0x2795, 0x2797),
+        (0x2B1B, 0x2B1C),
+        (0x2B50, 0x2B55),
+        (0x2E80, 0x303E),
+        (0x3041, 0x33FF),
+        (0x3400, 0x4DBF),
+        (0x4E00, 0x9FFF),
+        (0xA000, 0xA4CF),
+        (0xA960, 0xA97F),
+        (0xAC00, 0xD7A3),
+        (0xF900, 0xFAFF),
+        (0xFE10, 0xFE19),
+        (0xFE30, 0xFE6F),
+        (0xFF00, 0xFF60),
+        (0xFFE0, 0xFFE6),
+        (0x1F300, 0x1F64F),
+        (0x1F680, 0x1F6FF),
+        (0x1F900, 0x1F9FF),
+        (0x20000, 0x3FFFD),
+    ];
+    let within = |ranges: &[(u32, u32)]| {
+        ranges
+            .binary_search_by(|&(lo, hi)| {
+                if hi < c as u32 {
+                    std::cmp::Ordering::Less
+                } else if lo > c as u32 {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok()
+    };
+
+    if c.is_control() || within(ZERO) {
+        0
+    } else if within(WIDE) {
+        2
+    } else {
+        1
+    }
+}
+
+/// The name of the user this shell runs as.
+pub fn user_name() -> Option<String> {
+    let name = unsafe {
+        let pw = libc::getpwuid(libc::geteuid());
+        if pw.is_null() {
+            return None;
+        }
+        std::ffi::CStr::from_ptr((*pw).pw_name)
+    };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// The name of this computer.
+pub fn host_name() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+/// Matches a string against a shell pattern.
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    let (Ok(pattern), Ok(s)) = (CString::new(pattern), CString::new(s)) else {
+        return false;
+    };
+    unsafe { libc::fnmatch(pattern.as_ptr(), s.as_ptr(), 0) == 0 }
+}
+
+/// Finds the paths that match a shell pattern in sorted order. Names that
+/// start with `.` are only matched by a `.` in the pattern.
+pub fn glob(pattern: &str) -> Vec<String> {
+    let Ok(pattern) = CString::new(pattern) else {
+        return Vec::new();
+    };
+    unsafe {
+        let mut found = std::mem::zeroed::<libc::glob_t>();
+        let paths = match libc::glob(pattern.as_ptr(), 0, None, &mut found) {
+            0 => std::slice::from_raw_parts(found.gl_pathv, found.gl_pathc)
+                .iter()
+                .map(|p| CStr::from_ptr(*p).to_string_lossy().into_owned())
+                .collect(),
+            _ => Vec::new(),
+        };
+        libc::globfree(&mut found);
+        paths
+    }
+}
+
 /// An alternative to a hash map which is backed by a static array. Good for
 /// small inputs.
 #[derive(Debug, Default, PartialEq, Eq)]
@@ -225,7 +434,16 @@ impl<T> AtomicSlice<T> {
 
 #[cfg(test)]
 mod test {
-    use super::AtomicBuffer;
+    use super::{width, AtomicBuffer};
+
+    #[test]
+    fn widths() {
+        let w = |s: &str| s.chars().map(width).sum::<usize>();
+        assert_eq!(w("ls -l"), 5);
+        assert_eq!(w("日本語"), 6);
+        assert_eq!(w("e\u{301}"), 1);
+        assert_eq!(w("\u{1F600}"), 2);
+    }
 
     #[test]
     fn atomic_ptr_follow() {
@@ -1,17 +1,97 @@
-use crate::{Shell, ShellState};
+use crate::parse::CmdError;
+use crate::prelude::*;
+use crate::shell::options::Opt;
 use rush_core::{lexer::Lexer, walker::Expand};
 
-pub fn expand(thing: Expand, state: &ShellState) -> String {
-    match thing {
+/// Expands all the parts of a word and joins them together.
+pub fn expand_word(word: Vec<Expand>, state: &ShellState) -> Result<String, CmdError> {
+    word.into_iter()
+        .map(|e| expand(e, state))
+        .try_fold(String::new(), |mut s, e| {
+            s.push_str(&e?);
+            Ok(s)
+        })
+}
+
+/// Expands a word used as a pattern. The parts that were quoted have their
+/// special characters escaped so they only match themselves. The same
+/// escapes work for both globs and regexes.
+pub fn expand_pattern(word: Vec<Expand>, state: &ShellState) -> Result<String, CmdError> {
+    let mut pattern = String::new();
+    for part in word {
+        match part {
+            Expand::Quoted(parts) => escape(&expand_word(parts, state)?, &mut pattern),
+            part => pattern.push_str(&expand(part, state)?),
+        }
+    }
+    Ok(pattern)
+}
+
+/// Expands a word into the paths it matches when it has a `*`, `?` or `[`
+/// that wasn't quoted. A word that matches nothing, or any word when
+/// `noglob` is set, is left as it is.
+pub fn expand_glob(word: Vec<Expand>, state: &ShellState) -> Result<Vec<String>, CmdError> {
+    let mut text = String::new();
+    let mut pattern = String::new();
+    let mut glob = false;
+    for part in word {
+        match part {
+            Expand::Quoted(parts) => {
+                let s = expand_word(parts, state)?;
+                escape(&s, &mut pattern);
+                text.push_str(&s);
+            }
+            part => {
+                let s = expand(part, state)?;
+                glob |= s.contains(['*', '?', '[']);
+                pattern.push_str(&s);
+                text.push_str(&s);
+            }
+        }
+    }
+
+    if !glob || state.option(Opt::Noglob) {
+        return Ok(vec![text]);
+    }
+    let paths = crate::util::glob(&pattern);
+    match paths.is_empty() {
+        true => Ok(vec![text]),
+        false => Ok(paths),
+    }
+}
+
+/// Adds text to a pattern so it only matches itself.
+fn escape(text: &str, pattern: &mut String) {
+    for c in text.chars() {
+        if "\\*?[.()+{|^$".contains(c) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+}
+
+/// Expands one part of a word. This fails for unset variables when
+/// `nounset` is on and for a `${...}` that isn't understood.
+pub fn expand(thing: Expand, state: &ShellState) -> Result<String, CmdError> {
+    Ok(match thing {
         Expand::Literal(s) => s,
         Expand::Var(k) => {
             log::info!("explanding key: {}", k);
             // let (var, rest) = state.get_env(&k);
             // format!("{}{}", var, rest)
 
-            state.get_env_exact(&k).unwrap_or_default()
+            match state.get_env_exact(&k) {
+                Some(v) => v,
+                // `$@` and `$*` are fine with no arguments
+                None if state.option(Opt::Nounset) && k != "@" && k != "*" => {
+                    return Err(Report::new(CmdError::Unbound(k)));
+                }
+                None => String::new(),
+            }
         }
         Expand::Home => state.home().to_owned(),
+        Expand::Quoted(parts) => expand_word(parts, state)?,
+        Expand::Param(text) => param(&text, state)?,
         // Expand::Brace(_, _, _) => todo!(),
         Expand::Sub(s) => {
             let s = Shell::sourced(Lexer::new(crate::util::OwnedCharBuffer::new(s)));
@@ -19,8 +99,46 @@ pub fn expand(thing: Expand, state: &ShellState) -> String {
             todo!("get shell output")
         }
         Expand::Brace(_, _, _) => todo!(),
+    })
+}
+/// Expands the text inside `${...}`. This is a name or an element of an
+/// array like `name[1]`, where a negative index counts from the end and
+/// `name[@]` or `name[*]` is every element.
+fn param(text: &str, state: &ShellState) -> Result<String, CmdError> {
+    let bad = || Report::new(CmdError::BadSubstitution(text.to_owned()));
+
+    let (name, index) = match text.strip_suffix(']').and_then(|t| t.split_once('[')) {
+        Some((name, index)) => (name, Some(index)),
+        None => (text, None),
+    };
+    let special = name.len() == 1 && "@*#?$-!".contains(name);
+    if !special && (name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_')) {
+        return Err(bad());
+    }
+    let Some(index) = index else {
+        return expand(Expand::Var(name.to_owned()), state);
+    };
+
+    let elements = state.get_elements(name).unwrap_or_default();
+    let value = match index {
+        "@" | "*" if elements.is_empty() => None,
+        "@" | "*" => Some(elements.join(" ")),
+        i => {
+            let i: isize = i.parse().map_err(|_| bad())?;
+            let i = match i < 0 {
+                true => elements.len().checked_sub(i.unsigned_abs()),
+                false => Some(i as usize),
+            };
+            i.and_then(|i| elements.get(i)).cloned()
+        }
+    };
+    match value {
+        Some(v) => Ok(v),
+        None if state.option(Opt::Nounset) => Err(Report::new(CmdError::Unbound(text.to_owned()))),
+        None => Ok(String::new()),
     }
 }
+
 //     fn expand_word(&mut self, expansions: Vec<Expand>) -> String {
 //         let mut phrase = String::new();
 //         for word in expansions {
@@ -0,0 +1,98 @@
+//! Uses the shell as a library the way code that embeds it would.
+
+use rush::complete::{Completer, Request};
+use rush::drive::builtins::{Io, ShellBuiltin, Stream};
+use rush::shell::{Shell, ShellState};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Keeps the args of every call.
+struct Record(Rc<RefCell<Vec<Vec<String>>>>);
+impl ShellBuiltin for Record {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let _ = (state, io);
+        self.0.borrow_mut().push(args.to_vec());
+        args.len() as i32
+    }
+
+    fn help(&self) -> &str {
+        "record [args ...]"
+    }
+}
+
+#[test]
+fn registered_builtin() {
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let mut shell = Shell::script("record a b; record && record c".chars());
+    shell.register_builtin("record", Record(calls.clone()));
+    let status = shell.run(false).unwrap();
+
+    assert_eq!(status, 1);
+    assert_eq!(*calls.borrow(), [vec!["a", "b"], vec![], vec!["c"]]);
+}
+
+/// Completes the arguments of a made up `git`.
+struct Git;
+impl Completer for Git {
+    fn complete(&self, req: &Request, state: &mut ShellState) -> Vec<String> {
+        let _ = state;
+        ["checkout", "cherry-pick", "commit"]
+            .into_iter()
+            .filter(|w| w.starts_with(req.word()))
+            .map(String::from)
+            .collect()
+    }
+}
+
+#[test]
+fn registered_completer() {
+    let mut state = ShellState::default();
+    state.completers_mut().set("git", Rc::new(Git));
+
+    let found = rush::complete::complete("git ch", 6, &mut state).unwrap();
+    assert_eq!(found.candidates, ["checkout", "cherry-pick"]);
+    assert_eq!(found.common_prefix(), "che");
+}
+
+/// Sets `COMPREPLY` the way a completion function for `git` would.
+struct GitReply;
+impl ShellBuiltin for GitReply {
+    fn run(&self, args: &[String], state: &mut ShellState, io: &mut Io) -> i32 {
+        let _ = io;
+        // the command, the word and the word before it
+        let [_, word, _] = args else {
+            return 2;
+        };
+        let mut reply: Vec<_> = ["checkout", "cherry-pick", "commit"]
+            .into_iter()
+            .filter(|w| w.starts_with(word.as_str()))
+            .map(String::from)
+            .collect();
+        reply.extend(state.get_env_exact("COMP_CWORD"));
+        state.set_array("COMPREPLY", reply);
+        0
+    }
+
+    fn help(&self) -> &str {
+        "_git command word prev"
+    }
+}
+
+#[test]
+fn completion_function() {
+    let mut state = ShellState::default();
+    state.builtins_mut().register("_git", GitReply);
+
+    let mut io = Io {
+        stdin: Stream(None),
+        stdout: Stream(None),
+        stderr: Stream(None),
+    };
+    let complete = state.builtins().get("complete").unwrap();
+    let args: Vec<_> = ["-F", "_git", "git"].map(String::from).into();
+    assert_eq!(complete.run(&args, &mut state, &mut io), 0);
+
+    let found = rush::complete::complete("git ch", 6, &mut state).unwrap();
+    assert_eq!(found.candidates, ["1", "checkout", "cherry-pick"]);
+}
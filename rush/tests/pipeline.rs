@@ -0,0 +1,137 @@
+//! Runs pipelines through the shell binary and checks what comes out the end.
+
+use std::process::{Command, Output};
+
+fn rush(code: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rush"))
+        .args(["-c", code])
+        .output()
+        .expect("failed to run rush")
+}
+
+fn stdout(code: &str) -> String {
+    let out = rush(code);
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[test]
+fn long_pipeline() {
+    let cats = " | cat".repeat(12);
+    assert_eq!(stdout(&format!("echo hello{}", cats)), "hello\n");
+}
+
+#[test]
+fn long_pipeline_of_builtins() {
+    // builtins in a pipeline are forked by the shell
+    let code = format!("echo a{} | cat", " | echo b | cat".repeat(6));
+    assert_eq!(stdout(&code), "b\n");
+}
+
+#[test]
+fn large_data() {
+    let size = 16 * 1024 * 1024;
+    let code = format!("head -c {} /dev/zero{} | wc -c", size, " | cat".repeat(10));
+    assert_eq!(stdout(&code).trim(), size.to_string());
+
+    let code = format!("seq 200000{} | wc -l", " | cat".repeat(11));
+    assert_eq!(stdout(&code).trim(), "200000");
+}
+
+#[test]
+fn large_data_through_builtins() {
+    let code = format!("printf '%0200000d\\n' 0{} | wc -c", " | cat".repeat(10));
+    assert_eq!(stdout(&code).trim(), "200001");
+}
+
+#[test]
+fn early_exit() {
+    // `head` leaves before the rest finish writing
+    let code = format!("yes{} | head -n 3", " | cat".repeat(10));
+    assert_eq!(stdout(&code), "y\ny\ny\n");
+}
+
+#[test]
+fn stage_statuses() {
+    let out = stdout("true | false | sh -c 'exit 3' | true; echo $?");
+    assert_eq!(out, "0\n");
+
+    let out = stdout("set -o pipefail; true | sh -c 'exit 3' | false | true; echo $?");
+    assert_eq!(out, "1\n");
+}
+
+#[test]
+fn stage_status_array() {
+    assert_eq!(stdout("false | true; echo ${PIPESTATUS[@]}"), "1 0\n");
+    let out = stdout("true | sh -c 'exit 3' | false; echo ${PIPESTATUS[1]} ${PIPESTATUS[-1]}");
+    assert_eq!(out, "3 1\n");
+    assert_eq!(
+        stdout("false; echo ${PIPESTATUS[@]} ${PIPESTATUS[1]}x"),
+        "1 x\n"
+    );
+
+    let out = rush("echo ${PIPESTATUS[x]}; echo after");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "");
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(
+        stderr.contains("${PIPESTATUS[x]}: bad substitution"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn missing_stage() {
+    let out = rush("echo hi | nosuchcommand | cat; echo $?");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "0\n");
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("nosuchcommand: command not found"));
+}
+
+#[test]
+fn eval_in_pipeline() {
+    assert_eq!(stdout("eval 'echo hi' | cat"), "hi\n");
+    assert_eq!(stdout("eval false && echo x; echo $?"), "1\n");
+}
+
+#[test]
+fn and_or_expand_late() {
+    // words are expanded when their command runs, not when the line is read
+    assert_eq!(stdout("false || echo \"status $?\""), "status 1\n");
+    assert_eq!(stdout("true && false; echo $?"), "1\n");
+    assert_eq!(stdout("sh -c 'exit 3' || echo $? && echo $?"), "3\n0\n");
+    assert_eq!(stdout("cd / && echo $PWD"), "/\n");
+}
+
+#[test]
+fn and_or_streams() {
+    // both sides write where the whole list was redirected
+    assert_eq!(stdout("eval 'echo a && echo b' >/dev/null; echo c"), "c\n");
+    assert_eq!(stdout("eval 'false || echo a' >/dev/null; echo c"), "c\n");
+
+    let out = rush("eval '[[ x =~ ( ]]' 2>/dev/null; echo $?");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "2\n");
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(!stderr.contains("invalid regular expression"), "{}", stderr);
+}
+
+#[test]
+fn globs() {
+    let dir = std::env::temp_dir().join(format!("rush-glob-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in ["a.txt", "b.txt", "c.rs", ".hidden.txt"] {
+        std::fs::write(dir.join(name), "").unwrap();
+    }
+    let run = |code: &str| stdout(&format!("cd {} && {}", dir.display(), code));
+
+    assert_eq!(run("echo *.txt"), "a.txt b.txt\n");
+    assert_eq!(run("echo ?.rs [b].txt"), "c.rs b.txt\n");
+    // quoted globs and ones that match nothing stay as they are
+    assert_eq!(
+        run("echo '*'.txt '?'.rs \"*\" *.none"),
+        "*.txt ?.rs * *.none\n"
+    );
+    assert_eq!(run("set -f; echo *.txt; echo $-"), "*.txt\nf\n");
+    assert_eq!(run("set -o noglob; set +f; echo *.rs"), "c.rs\n");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}